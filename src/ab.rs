@@ -0,0 +1,239 @@
+/// A/B saves, alternating between two copies of the filter and a small
+/// manifest naming the current one, for crash-safe persistence where
+/// renaming over a file isn't atomic or rewriting a huge one in place is too
+/// risky.
+///
+/// The copies live beside the manifest at `path`, with ".a" and ".b"
+/// appended to its name.  Each save brings the copy not named by the
+/// manifest up to date, writing only pages changed since that copy was
+/// saved where it holds the same filter, syncs it, then points the manifest
+/// at it.  The manifest is a single small write carrying its own checksum;
+/// should that be torn by a crash, loading falls back to whichever copy is
+/// intact and newest, so the previous save is never put at risk.
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::format::{self, FileHeader};
+use crate::paged::{self, invalid_data};
+use crate::{BloomFilter, Durability};
+
+const MANIFEST_MAGIC: &[u8; 8] = b"BLOOMAB1";
+
+/// Magic, slot name, generation and CRC-32C of the rest.
+const MANIFEST_SIZE: usize = 8 + 1 + 8 + 4;
+
+const SLOTS: [u8; 2] = [b'a', b'b'];
+
+fn slot_path(path: &Path, slot: u8) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push((slot as char).to_string());
+    PathBuf::from(name)
+}
+
+/// The slot and generation named by the manifest at `path`, if it's intact.
+fn read_manifest(path: &Path) -> io::Result<Option<(u8, u64)>> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if buf.len() != MANIFEST_SIZE
+        || &buf[..8] != MANIFEST_MAGIC
+        || !SLOTS.contains(&buf[8])
+        || format::crc32c(&buf[..17]).to_be_bytes() != buf[17..]
+    {
+        return Ok(None);
+    }
+
+    Ok(Some((
+        buf[8],
+        u64::from_be_bytes(buf[9..17].try_into().unwrap()),
+    )))
+}
+
+fn write_manifest(
+    path: &Path,
+    slot: u8,
+    generation: u64,
+    durability: Durability,
+) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(MANIFEST_SIZE);
+    buf.extend_from_slice(MANIFEST_MAGIC);
+    buf.push(slot);
+    buf.extend_from_slice(&generation.to_be_bytes());
+    buf.extend_from_slice(&format::crc32c(&buf).to_be_bytes());
+
+    // overwritten in place, as small enough to land in a single sector
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.write_all(&buf)?;
+    file.set_len(MANIFEST_SIZE as u64)?;
+    durability.sync(&file)
+}
+
+impl BloomFilter {
+    /// Save the filter A/B style, with `path` as the manifest.  Load it with
+    /// `load_ab`.
+    pub fn save_ab<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let previous = self.generation;
+        self.generation += 1;
+
+        let path = path.as_ref();
+        let slot = match read_manifest(path)? {
+            Some((current, _)) if current == SLOTS[0] => SLOTS[1],
+            _ => SLOTS[0],
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(slot_path(path, slot))?;
+
+        let len = file.metadata()?.len();
+        let existing = if len >= format::HEADER_SIZE as u64 {
+            FileHeader::read(&mut file).ok()
+        } else {
+            None
+        };
+
+        match existing {
+            // the copy holds this filter as of an earlier save, so only
+            // pages changed since need writing
+            Some(mut header)
+                if header.without_checksums() == self.file_header().without_checksums()
+                    && header.generation() <= previous
+                    && len >= header.file_len() =>
+            {
+                let pages: Vec<usize> = self
+                    .changed
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, changed)| **changed > header.generation())
+                    .map(|(index, _)| index)
+                    .collect();
+
+                self.stamp(&mut header);
+                paged::write_in_place(&mut file, header, self.filter.as_bytes(), &pages)?;
+            }
+            _ => {
+                file.set_len(0)?;
+                file.seek(io::SeekFrom::Start(0))?;
+                self.write_full(&mut file)?;
+            }
+        }
+
+        // the copy must be on disk before the manifest names it
+        self.durability.sync(&file)?;
+        write_manifest(path, slot, self.generation, self.durability)?;
+        self.clear_dirty();
+
+        Ok(())
+    }
+
+    /// Load a filter saved with `save_ab`, from the copy named by the
+    /// manifest at `path`, or if that's damaged the newest intact copy.
+    pub fn load_ab<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Some((slot, generation)) = read_manifest(path)? {
+            match Self::load(slot_path(path, slot)) {
+                Ok(filter) if filter.generation() == generation => return Ok(filter),
+                _ => {}
+            }
+        }
+
+        let mut newest: Option<Self> = None;
+        let mut error = None;
+        for slot in SLOTS.iter().copied() {
+            match Self::load(slot_path(path, slot)) {
+                Ok(filter) => {
+                    let newer = match &newest {
+                        Some(newest) => filter.generation() > newest.generation(),
+                        None => true,
+                    };
+                    if newer {
+                        newest = Some(filter);
+                    }
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+
+        newest.ok_or_else(|| error.unwrap_or_else(|| invalid_data("no intact copy")))
+    }
+
+    /// Remove the manifest and both copies of a filter saved with `save_ab`.
+    pub fn remove_ab<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        for file in [
+            path.to_path_buf(),
+            slot_path(path, SLOTS[0]),
+            slot_path(path, SLOTS[1]),
+        ] {
+            match fs::remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomfilter_save_ab() {
+        let path = Path::new("test_ab.bf");
+        BloomFilter::remove_ab(path).unwrap();
+
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        bf.save_ab(path).unwrap();
+        assert_eq!(Some((b'a', 1)), read_manifest(path).unwrap());
+
+        bf.insert(5000u32);
+        bf.save_ab(path).unwrap();
+        assert_eq!(Some((b'b', 2)), read_manifest(path).unwrap());
+
+        // the first copy catches up with changes from both saves since
+        bf.insert(6000u32);
+        bf.save_ab(path).unwrap();
+        assert_eq!(Some((b'a', 3)), read_manifest(path).unwrap());
+        BloomFilter::verify(slot_path(path, b'a')).unwrap();
+
+        let loaded = BloomFilter::load_ab(path).unwrap();
+        assert_eq!(3, loaded.generation());
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+        assert!(loaded.contains(5000u32) && loaded.contains(6000u32));
+
+        // a torn manifest falls back to the newest intact copy
+        fs::write(path, b"BLOOMAB1torn").unwrap();
+        assert_eq!(3, BloomFilter::load_ab(path).unwrap().generation());
+
+        // as does a damaged current copy
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(slot_path(path, b'a'))
+            .unwrap();
+        file.write_all(b"garbage").unwrap();
+        drop(file);
+        let loaded = BloomFilter::load_ab(path).unwrap();
+        assert_eq!(2, loaded.generation());
+        assert!(loaded.contains(5000u32));
+
+        BloomFilter::remove_ab(path).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+/// Time-decaying bloom filter, where items expire a fixed number of ticks
+/// after they were last inserted.
+///
+/// Each cell holds the number of ticks it has left to live.  Inserting an
+/// item resets its k cells to the filter's lifetime, and every `tick()` counts
+/// all live cells down by one, so an item is forgotten after `lifetime` ticks
+/// unless it is seen again.  Calling `tick()` on a timer bounds the filter's
+/// memory to roughly one lifetime's worth of items, however long it runs.
+///
+/// Saved files are a short header followed by one byte per cell:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "AGEBLv00"
+/// 8       8     ticks elapsed (big-endian u64)
+/// 16      4     number of cells (big-endian u32)
+/// 20      4     k, number of hashes (big-endian u32)
+/// 24      1     lifetime in ticks
+/// 25      7     zero padding
+/// 32      ...   remaining lifetime of each cell
+/// ```
+use std::convert::{TryFrom, TryInto};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::{BloomFilterParams, BloomHash};
+
+const AGING_MAGIC: &[u8; 8] = b"AGEBLv00";
+const AGING_HEADER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgingBloomFilter {
+    k: u32,
+    lifetime: u8,
+    ticks: u64,
+    cells: Vec<u8>,
+}
+
+impl AgingBloomFilter {
+    pub fn new(cells: u32, k: u32, lifetime: u8) -> Self {
+        assert!(cells > 0);
+        assert!(k > 0);
+        assert!(lifetime > 0);
+
+        Self {
+            k,
+            lifetime,
+            ticks: 0,
+            cells: vec![0; cells as usize],
+        }
+    }
+
+    /// A filter holding up to `capacity` live items at a false-positive rate
+    /// of `p`, each living for `lifetime` ticks.
+    pub fn with_capacity_p(capacity: u64, p: f64, lifetime: u8) -> Self {
+        let params = BloomFilterParams::with_capacity_p(capacity, p);
+        let cells = u32::try_from(params.m).expect("too many cells for an aging filter");
+        Self::new(cells, params.k, lifetime)
+    }
+
+    fn cell(&self, hash: BloomHash, i: u32) -> usize {
+        (hash.nth(i) % self.cells.len() as u64) as usize
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        (0..self.k).all(|i| self.cells[self.cell(*hash, i)] > 0)
+    }
+
+    /// Insert an item, or refresh its lifetime, returning `true` if it was not
+    /// already present.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let hash = *hash;
+        let seen = self.contains_hash(&hash);
+
+        for i in 0..self.k {
+            let cell = self.cell(hash, i);
+            self.cells[cell] = self.lifetime;
+        }
+
+        !seen
+    }
+
+    /// Advance the clock by one tick, expiring items inserted `lifetime`
+    /// ticks ago.
+    pub fn tick(&mut self) {
+        for cell in self.cells.iter_mut() {
+            *cell = cell.saturating_sub(1);
+        }
+
+        self.ticks += 1;
+    }
+
+    /// Number of ticks since the filter was created.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn lifetime(&self) -> u8 {
+        self.lifetime
+    }
+
+    /// Fraction of cells which are live.
+    pub fn fill_ratio(&self) -> f64 {
+        self.cells.iter().filter(|c| **c > 0).count() as f64 / self.cells.len() as f64
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; AGING_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != AGING_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let ticks = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        let cells = u32::from_be_bytes(header[16..20].try_into().unwrap());
+        let k = u32::from_be_bytes(header[20..24].try_into().unwrap());
+        let lifetime = header[24];
+
+        if cells == 0 || k == 0 || lifetime == 0 {
+            return Err(invalid_data("invalid parameters"));
+        }
+
+        let mut ret = Self::new(cells, k, lifetime);
+        ret.ticks = ticks;
+        reader.read_exact(&mut ret.cells[..])?;
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; AGING_HEADER_SIZE];
+        header[0..8].copy_from_slice(AGING_MAGIC);
+        header[8..16].copy_from_slice(&self.ticks.to_be_bytes());
+        header[16..20].copy_from_slice(&(self.cells.len() as u32).to_be_bytes());
+        header[20..24].copy_from_slice(&self.k.to_be_bytes());
+        header[24] = self.lifetime;
+
+        writer.write_all(&header[..])?;
+        writer.write_all(&self.cells[..])
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        self.write_to(&mut file)?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aging_expiry_save_load() {
+        let path = "test_aging.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut abf = AgingBloomFilter::with_capacity_p(1000, 0.01, 3);
+        for i in 0..500 {
+            assert!(abf.insert(i));
+        }
+        assert!(!abf.insert_hash(&BloomHash::from(0)));
+        assert!(abf.contains_hash(&BloomHash::from(499)));
+
+        abf.tick();
+        abf.tick();
+        for i in 500..1000 {
+            abf.insert(i);
+        }
+
+        abf.save(path).unwrap();
+        let mut abf = AgingBloomFilter::load(path).unwrap();
+        assert_eq!(2, abf.ticks());
+        assert!((0..1000).all(|i| abf.contains(i)));
+
+        abf.tick();
+        assert!((0..500).filter(|i| abf.contains(*i)).count() < 10);
+        assert!((500..1000).all(|i| abf.contains(i)));
+
+        abf.tick();
+        abf.tick();
+        assert_eq!(0.0, abf.fill_ratio());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
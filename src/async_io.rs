@@ -0,0 +1,164 @@
+/// Loading and saving through tokio, so services on an async runtime don't
+/// block their executor reading or flushing huge filters.
+///
+/// These follow `load` and `save`, except that a sparse file is read back
+/// as usual but any existing file in another format is replaced in full,
+/// dense.
+///
+/// Available with the `tokio` feature.
+use std::io;
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::format::{self, FileHeader};
+use crate::paged::{self, invalid_data};
+use crate::{compressed, loadable_params, BloomFilter, Durability};
+
+async fn read_header(file: &mut File) -> io::Result<FileHeader> {
+    let mut buf = vec![0; format::HEADER_SIZE];
+    file.read_exact(&mut buf).await?;
+    FileHeader::from_bytes(&buf)
+}
+
+async fn write_header(file: &mut File, header: &FileHeader) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(format::HEADER_SIZE);
+    header.write(&mut buf)?;
+    file.seek(io::SeekFrom::Start(0)).await?;
+    file.write_all(&buf).await
+}
+
+impl BloomFilter {
+    /// Like `load`, without blocking the executor.
+    pub async fn load_async<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = File::open(path.as_ref()).await?;
+        let header = read_header(&mut file).await?;
+        let params = loadable_params(&header, (0, 0))?;
+
+        let mut filter = BitVec::from_elem((params.m / 8 * 8) as usize, false);
+        if header.is_compressed() {
+            // compressed bodies are small enough to read whole
+            let mut body =
+                vec![0; (file.metadata().await?.len() - format::HEADER_SIZE as u64) as usize];
+            file.read_exact(&mut body).await?;
+            filter.with_bytes_mut(|buf| compressed::read_body(&body[..], buf))?;
+        } else {
+            // a page at a time, rather than a second copy of the whole body
+            let page_size = header.page_size() as usize;
+            let mut page = vec![0; page_size];
+            for index in 0..header.pages() as usize {
+                let present = match &header.page_map {
+                    Some(map) => map[index],
+                    None => true,
+                };
+                if present {
+                    file.read_exact(&mut page).await?;
+                    let start = index * page_size;
+                    filter
+                        .with_bytes_mut(|buf| buf[start..start + page_size].copy_from_slice(&page));
+                }
+            }
+        }
+        header.verify_body(filter.as_bytes())?;
+
+        Ok(Self::from_loaded(params, filter, &header))
+    }
+
+    /// Like `save`, without blocking the executor.
+    pub async fn save_async<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.generation += 1;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path.as_ref())
+            .await?;
+
+        let len = file.metadata().await?.len();
+        let existing = if len >= format::HEADER_SIZE as u64 {
+            Some(read_header(&mut file).await?)
+        } else {
+            None
+        };
+
+        let bytes = self.filter.as_bytes();
+        match existing {
+            Some(header) if header.is_encrypted() => {
+                return Err(invalid_data(
+                    "encrypted filters must be saved with save_encrypted",
+                ))
+            }
+            Some(mut header)
+                if header.without_checksums() == self.file_header().without_checksums()
+                    && len >= header.file_len() =>
+            {
+                self.stamp(&mut header);
+                let dirty = paged::dirty_pages(&self.dirty);
+                for (offset, page) in paged::in_place(&mut header, bytes, &dirty) {
+                    file.seek(io::SeekFrom::Start(offset)).await?;
+                    file.write_all(page).await?;
+                }
+
+                write_header(&mut file, &header).await?;
+            }
+            _ => {
+                file.set_len(0).await?;
+                write_header(&mut file, &self.checksummed_header()).await?;
+                file.write_all(bytes).await?;
+            }
+        }
+
+        file.flush().await?;
+        match self.durability {
+            Durability::Full => file.sync_all().await?,
+            Durability::Data => file.sync_data().await?,
+            Durability::Unsynced => {}
+        }
+        self.clear_dirty();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomfilter_async() {
+        let path = "test_async.bf";
+        let _ = std::fs::remove_file(path);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+            for i in 0..1000u32 {
+                bf.insert(i);
+            }
+            bf.save_async(path).await.unwrap();
+
+            let mut loaded = BloomFilter::load_async(path).await.unwrap();
+            assert!((0..1000u32).all(|i| loaded.contains(i)));
+
+            loaded.insert(5000u32);
+            loaded.save_async(path).await.unwrap();
+            BloomFilter::verify(path).unwrap();
+            assert_eq!(2, BloomFilter::load(path).unwrap().generation());
+
+            // sparse files read back too
+            loaded.compact(path).unwrap();
+            let sparse = BloomFilter::load_async(path).await.unwrap();
+            assert!(sparse.contains(5000u32));
+        });
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+/// Attenuated bloom filter, after Rhea and Kubiatowicz, for routing queries
+/// towards resources in a network.
+///
+/// A stack of `BloomFilter`s by distance: level 0 holds what a node has
+/// itself, and level i what is reachable through a neighbour in i hops.  A
+/// node builds its stack by merging each neighbour's stack shifted down one
+/// level, and forwards a query to the neighbour whose stack finds the item at
+/// the shallowest level.
+///
+/// The whole stack is saved to a single file: a short header recording the
+/// depth, followed by each level in the usual single-filter format.
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::{BloomFilter, BloomFilterParams, BloomHash};
+
+const ATTENUATED_MAGIC: &[u8; 8] = b"ABLOOM00";
+const ATTENUATED_HEADER_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub struct AttenuatedBloomFilter {
+    levels: Vec<BloomFilter>,
+}
+
+/// Bitwise-or `src` into `dst`, which must have the same geometry.
+fn merge_level(dst: &mut BloomFilter, src: &BloomFilter) {
+    let src_bytes = src.filter.as_bytes();
+    dst.filter.with_bytes_mut(|bytes| {
+        for (a, b) in bytes.iter_mut().zip(src_bytes.iter()) {
+            *a |= *b;
+        }
+    });
+
+    dst.count = dst.count_estimate();
+    dst.mark_all_dirty();
+}
+
+impl AttenuatedBloomFilter {
+    /// A stack of `depth` levels, each sized by `params`.
+    pub fn new(params: BloomFilterParams, depth: usize) -> Self {
+        assert!(depth > 0);
+
+        let first = BloomFilter::from_params(params);
+        let mut levels = (1..depth).map(|_| first.empty_like()).collect::<Vec<_>>();
+        levels.insert(0, first);
+
+        Self { levels }
+    }
+
+    pub fn with_capacity_p(capacity: u64, p: f64, depth: usize) -> Self {
+        Self::new(BloomFilterParams::with_capacity_p(capacity, p), depth)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Whether `other` has the same depth and level geometry, and so can be
+    /// merged with this stack.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.depth() == other.depth()
+            && self.levels[0].params.m == other.levels[0].params.m
+            && self.levels[0].params.k == other.levels[0].params.k
+    }
+
+    /// Insert a locally held item at level 0.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_at(0, item)
+    }
+
+    /// Insert a pre-computed hash at level 0.  As with `BloomFilter`'s
+    /// `insert_hash`, it's used as it is, without the levels' key.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        self.levels[0].insert_hash(hash)
+    }
+
+    /// Check every level for a pre-computed hash.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.levels.iter().any(|level| level.contains_hash(hash))
+    }
+
+    /// Insert an item at the given distance.
+    pub fn insert_at<T: Into<BloomHash>>(&mut self, level: usize, item: T) -> bool {
+        let hash = self.levels[level].rehash(item.into());
+        self.levels[level].insert_hash(&hash)
+    }
+
+    pub fn contains_at<T: Into<BloomHash>>(&self, level: usize, item: T) -> bool {
+        self.levels[level].contains(item)
+    }
+
+    /// The shallowest level holding an item, if any.
+    pub fn first_level<T: Into<BloomHash>>(&self, item: T) -> Option<usize> {
+        let hash = self.levels[0].rehash(item.into());
+        self.levels
+            .iter()
+            .position(|level| level.contains_hash(&hash))
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.first_level(item).is_some()
+    }
+
+    /// This stack as seen from a neighbour one hop away: every level moves one
+    /// deeper, level 0 is empty, and the deepest level is dropped.
+    pub fn shifted(&self) -> Self {
+        let mut ret = Self {
+            levels: self.levels.iter().map(BloomFilter::empty_like).collect(),
+        };
+
+        for (dst, src) in ret.levels.iter_mut().skip(1).zip(self.levels.iter()) {
+            merge_level(dst, src);
+        }
+
+        ret
+    }
+
+    /// Bitwise-or each level of `other` into the same level of this stack.
+    /// Typically `other` is a neighbour's stack after `shifted()`.
+    ///
+    /// Panics if the stacks are not compatible.
+    pub fn merge(&mut self, other: &Self) {
+        assert!(self.is_compatible(other), "incompatible filter geometry");
+
+        for (dst, src) in self.levels.iter_mut().zip(other.levels.iter()) {
+            merge_level(dst, src);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(BloomFilter::is_empty)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; ATTENUATED_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != ATTENUATED_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let depth = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        if depth == 0 {
+            return Err(invalid_data("no levels"));
+        }
+
+        let levels = (0..depth)
+            .map(|_| BloomFilter::from_reader(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let params = &levels[0].params;
+        if levels
+            .iter()
+            .any(|level| level.params.m != params.m || level.params.k != params.k)
+        {
+            return Err(invalid_data("mismatched levels"));
+        }
+
+        Ok(Self { levels })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(File::open(path.as_ref())?))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; ATTENUATED_HEADER_SIZE];
+        header[0..8].copy_from_slice(ATTENUATED_MAGIC);
+        header[8..12].copy_from_slice(&(self.levels.len() as u32).to_be_bytes());
+        writer.write_all(&header[..])?;
+
+        for level in &self.levels {
+            level.write_to(&mut writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write every level to `path`, replacing any existing file.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut writer = io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        for level in self.levels.iter_mut() {
+            level.clear_dirty();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attenuated_routing_save_load() {
+        let path = "test_attenuated.bf";
+        let _ = std::fs::remove_file(path);
+
+        // a - b - c, each holding one resource
+        let mut a = AttenuatedBloomFilter::with_capacity_p(1000, 0.01, 3);
+        let mut b = AttenuatedBloomFilter::with_capacity_p(1000, 0.01, 3);
+        let mut c = AttenuatedBloomFilter::with_capacity_p(1000, 0.01, 3);
+        a.insert("a");
+        b.insert("b");
+        c.insert("c");
+
+        b.merge(&c.shifted());
+        a.merge(&b.shifted());
+
+        assert_eq!(Some(0), a.first_level("a"));
+        assert_eq!(Some(1), a.first_level("b"));
+        assert_eq!(Some(2), a.first_level("c"));
+        assert_eq!(None, a.first_level("d"));
+        assert!(a.contains_hash(&BloomHash::from("c")));
+        assert!(!a.contains_hash(&BloomHash::from("d")));
+        assert!(a.insert_hash(&BloomHash::from("d")));
+        assert_eq!(Some(0), a.first_level("d"));
+
+        // beyond the stack's depth
+        assert_eq!(None, a.shifted().first_level("c"));
+
+        a.save(path).unwrap();
+        let loaded = AttenuatedBloomFilter::load(path).unwrap();
+        assert_eq!(3, loaded.depth());
+        assert_eq!(Some(2), loaded.first_level("c"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+/// Bit-sliced signature index, after BIGSI, answering which of many sets
+/// contain an item.
+///
+/// Every set has its own bloom filter of the same geometry, stored
+/// column-wise: row i holds bit i of every set's filter, one bit per set.  A
+/// query ANDs together the k rows an item probes, leaving a bit set for each
+/// set which may contain it, so all sets are tested in a single pass over k
+/// rows rather than one lookup per set.
+///
+/// Saved files are a short header followed by the rows:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "BIGSIv00"
+/// 8       4     m, bits per filter (big-endian u32)
+/// 12      4     k, hashes per item (big-endian u32)
+/// 16      4     number of sets (big-endian u32)
+/// 20      4     zero padding
+/// 24      ...   m rows of ceil(sets / 64) big-endian u64 words, set 0 in
+///               the least significant bit of the first word
+/// ```
+use std::convert::{TryFrom, TryInto};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::{BloomFilterParams, BloomHash};
+
+const BIGSI_MAGIC: &[u8; 8] = b"BIGSIv00";
+const BIGSI_HEADER_SIZE: usize = 24;
+
+#[derive(Debug, Clone)]
+pub struct BitSlicedIndex {
+    m: u32,
+    k: u32,
+    sets: usize,
+    /// Words allocated per row, which may exceed those in use so adding a
+    /// set doesn't always mean moving every row.
+    stride: usize,
+    rows: Vec<u64>,
+}
+
+impl BitSlicedIndex {
+    /// An empty index whose sets' filters are sized by `params`.
+    pub fn new(params: BloomFilterParams) -> Self {
+        assert!(params.m > 0 && params.k > 0);
+
+        Self {
+            m: u32::try_from(params.m).expect("filters too large to slice"),
+            k: params.k,
+            sets: 0,
+            stride: 0,
+            rows: vec![],
+        }
+    }
+
+    /// An empty index for sets of up to `capacity` items each.
+    pub fn with_capacity_p(capacity: u64, p: f64) -> Self {
+        Self::new(BloomFilterParams::with_capacity_p(capacity, p))
+    }
+
+    /// Number of sets indexed.
+    pub fn num_sets(&self) -> usize {
+        self.sets
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets == 0
+    }
+
+    fn words(&self) -> usize {
+        self.sets.div_ceil(64)
+    }
+
+    fn bits(&self, hash: &BloomHash) -> impl Iterator<Item = usize> {
+        let hash = *hash;
+        let m = u64::from(self.m);
+        (0..self.k).map(move |i| (hash.nth(i) % m) as usize)
+    }
+
+    /// Add a new set holding `items`, returning its index.
+    pub fn add_set<I, T>(&mut self, items: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<BloomHash>,
+    {
+        let set = self.sets;
+        self.sets += 1;
+
+        if self.words() > self.stride {
+            let stride = (self.stride * 2).max(1);
+            let mut rows = vec![0; self.m as usize * stride];
+            for (new, old) in rows
+                .chunks_mut(stride)
+                .zip(self.rows.chunks(self.stride.max(1)))
+            {
+                new[..self.stride].copy_from_slice(old);
+            }
+            self.rows = rows;
+            self.stride = stride;
+        }
+
+        for item in items {
+            self.insert(set, item);
+        }
+
+        set
+    }
+
+    /// Add an item to an existing set.
+    ///
+    /// Panics if `set` is out of range.
+    pub fn insert<T: Into<BloomHash>>(&mut self, set: usize, item: T) {
+        assert!(set < self.sets, "no such set");
+
+        let hash = item.into();
+        let bits: Vec<usize> = self.bits(&hash).collect();
+        for bit in bits {
+            self.rows[bit * self.stride + set / 64] |= 1 << (set % 64);
+        }
+    }
+
+    /// Whether a single set may contain an item.
+    pub fn contains<T: Into<BloomHash>>(&self, set: usize, item: T) -> bool {
+        assert!(set < self.sets, "no such set");
+
+        let hash = item.into();
+        self.bits(&hash)
+            .all(|bit| self.rows[bit * self.stride + set / 64] & (1 << (set % 64)) != 0)
+    }
+
+    /// Every set which may contain an item, in ascending order.
+    pub fn query<T: Into<BloomHash>>(&self, item: T) -> Vec<usize> {
+        self.query_hash(&item.into())
+    }
+
+    pub fn query_hash(&self, hash: &BloomHash) -> Vec<usize> {
+        let words = self.words();
+        let mut matches = vec![u64::MAX; words];
+
+        for bit in self.bits(hash) {
+            let row = &self.rows[bit * self.stride..bit * self.stride + words];
+            for (a, b) in matches.iter_mut().zip(row.iter()) {
+                *a &= *b;
+            }
+        }
+
+        matches
+            .iter()
+            .enumerate()
+            .flat_map(|(word, bits)| {
+                (0..64)
+                    .filter(move |bit| bits & (1 << bit) != 0)
+                    .map(move |bit| word * 64 + bit)
+            })
+            .filter(|set| *set < self.sets)
+            .collect()
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; BIGSI_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != BIGSI_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let m = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        let k = u32::from_be_bytes(header[12..16].try_into().unwrap());
+        let sets = u32::from_be_bytes(header[16..20].try_into().unwrap()) as usize;
+        if m == 0 || k == 0 {
+            return Err(invalid_data("invalid index geometry"));
+        }
+
+        let stride = sets.div_ceil(64);
+        let mut buf = vec![0; m as usize * stride * 8];
+        reader.read_exact(&mut buf[..])?;
+
+        Ok(Self {
+            m,
+            k,
+            sets,
+            stride,
+            rows: buf
+                .chunks(8)
+                .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(File::open(path.as_ref())?))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; BIGSI_HEADER_SIZE];
+        header[0..8].copy_from_slice(BIGSI_MAGIC);
+        header[8..12].copy_from_slice(&self.m.to_be_bytes());
+        header[12..16].copy_from_slice(&self.k.to_be_bytes());
+        header[16..20].copy_from_slice(&(self.sets as u32).to_be_bytes());
+        writer.write_all(&header[..])?;
+
+        let words = self.words();
+        for row in self.rows.chunks(self.stride.max(1)) {
+            for word in &row[..words] {
+                writer.write_all(&word.to_be_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut writer = io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitsliced_query_save_load() {
+        let path = "test_bitsliced.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut index = BitSlicedIndex::with_capacity_p(100, 0.001);
+        for set in 0..200u32 {
+            assert_eq!(set as usize, index.add_set(set * 100..set * 100 + 100));
+        }
+        index.insert(150, 42u32);
+
+        assert_eq!(vec![0, 150], index.query(42u32));
+        assert!(index.contains(7, 750u32));
+        assert!(!index.contains(8, 750u32));
+        assert!((0..20_000u32).all(|i| index.query(i).contains(&(i as usize / 100))));
+
+        index.save(path).unwrap();
+        let loaded = BitSlicedIndex::load(path).unwrap();
+        assert_eq!(200, loaded.num_sets());
+        assert!((0..20_000u32).all(|i| loaded.query(i) == index.query(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+/// Static Bloomier filter, an approximate map from keys to small fixed-width
+/// values, after Chazelle et al.
+///
+/// Built with the same 3-wise peeling as `XorFilter`, but each slot holds a
+/// fingerprint and a value side by side, and the xor of a key's three slots
+/// yields both.  Keys in the map always return their value; other keys return
+/// `None`, except with probability `p`, when they return an arbitrary value.
+///
+/// Saved files are a short header followed by the slots, each in the fewest
+/// whole big-endian bytes which hold its fingerprint and value:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "BLMRFv00"
+/// 8       8     seed (big-endian u64)
+/// 16      4     block length (big-endian u32)
+/// 20      4     number of keys (big-endian u32)
+/// 24      1     value bits
+/// 25      1     fingerprint bits
+/// 26      6     zero padding
+/// 32      ...   3 * block length slots
+/// ```
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::xor::{block_length, mix, peel, slots};
+use crate::BloomHash;
+
+const BLOOMIER_MAGIC: &[u8; 8] = b"BLMRFv00";
+const BLOOMIER_HEADER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomierFilter {
+    seed: u64,
+    block_length: u32,
+    len: u32,
+    value_bits: u32,
+    fingerprint_bits: u32,
+    slots: Vec<u64>,
+}
+
+impl BloomierFilter {
+    /// Build a map from `(key, value)` pairs, with each value fitting in
+    /// `value_bits` bits and a false-positive rate of at most `p` for keys not
+    /// in the map.  If a key appears more than once, its first value is kept.
+    ///
+    /// Panics if a value doesn't fit, or if `value_bits` plus the fingerprint
+    /// bits needed for `p` exceed 64.  Returns `None` if no working seed could
+    /// be found, which is vanishingly unlikely.
+    pub fn build<I, K>(pairs: I, value_bits: u32, p: f64) -> Option<Self>
+    where
+        I: IntoIterator<Item = (K, u64)>,
+        K: Into<BloomHash>,
+    {
+        assert!(p > 0.0 && p < 1.0);
+
+        let fingerprint_bits = (1.0 / p).log2().ceil().max(1.0) as u32;
+        assert!(value_bits > 0 && value_bits + fingerprint_bits <= 64);
+
+        let mut pairs: Vec<(u64, u64)> = pairs
+            .into_iter()
+            .map(|(key, value)| {
+                assert!(value >> value_bits == 0, "value exceeds value_bits");
+                (key.into().h1, value)
+            })
+            .collect();
+        pairs.sort_by_key(|(key, _)| *key);
+        pairs.dedup_by_key(|(key, _)| *key);
+
+        let keys: Vec<u64> = pairs.iter().map(|(key, _)| *key).collect();
+        let len = keys.len();
+        let block_length = block_length(len);
+        let (seed, order) = peel(&keys, block_length)?;
+
+        let mut ret = Self {
+            seed,
+            block_length,
+            len: len as u32,
+            value_bits,
+            fingerprint_bits,
+            slots: vec![0; block_length as usize * 3],
+        };
+
+        for (key, i) in order.iter().rev() {
+            let hash = mix(keys[*key], seed);
+            let [a, b, c] = slots(hash, block_length);
+            let entry = (ret.fingerprint(hash) << value_bits) | pairs[*key].1;
+
+            ret.slots[*i] = 0;
+            ret.slots[*i] = entry ^ ret.slots[a] ^ ret.slots[b] ^ ret.slots[c];
+        }
+
+        Some(ret)
+    }
+
+    fn fingerprint(&self, hash: u64) -> u64 {
+        mix(hash, 0) >> (64 - self.fingerprint_bits)
+    }
+
+    pub fn get<K: Into<BloomHash>>(&self, key: K) -> Option<u64> {
+        self.get_hash(&key.into())
+    }
+
+    pub fn get_hash(&self, hash: &BloomHash) -> Option<u64> {
+        let hash = mix(hash.h1, self.seed);
+        let [a, b, c] = slots(hash, self.block_length);
+        let entry = self.slots[a] ^ self.slots[b] ^ self.slots[c];
+
+        if entry >> self.value_bits == self.fingerprint(hash) {
+            Some(entry & ((1 << self.value_bits) - 1))
+        } else {
+            None
+        }
+    }
+
+    /// Number of distinct keys the map was built from.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn value_bits(&self) -> u32 {
+        self.value_bits
+    }
+
+    /// Probability that a key not in the map returns a value.
+    pub fn false_positive_rate(&self) -> f64 {
+        0.5_f64.powi(self.fingerprint_bits as i32)
+    }
+
+    fn slot_bytes(&self) -> usize {
+        (self.value_bits + self.fingerprint_bits).div_ceil(8) as usize
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; BLOOMIER_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != BLOOMIER_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let mut ret = Self {
+            seed: u64::from_be_bytes(header[8..16].try_into().unwrap()),
+            block_length: u32::from_be_bytes(header[16..20].try_into().unwrap()),
+            len: u32::from_be_bytes(header[20..24].try_into().unwrap()),
+            value_bits: u32::from(header[24]),
+            fingerprint_bits: u32::from(header[25]),
+            slots: vec![],
+        };
+
+        if ret.block_length == 0
+            || ret.value_bits == 0
+            || ret.fingerprint_bits == 0
+            || ret.value_bits + ret.fingerprint_bits > 64
+        {
+            return Err(invalid_data("invalid parameters"));
+        }
+
+        let slot_bytes = ret.slot_bytes();
+        let mut buf = vec![0; ret.block_length as usize * 3 * slot_bytes];
+        reader.read_exact(&mut buf[..])?;
+
+        ret.slots = buf
+            .chunks(slot_bytes)
+            .map(|slot| slot.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+            .collect();
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; BLOOMIER_HEADER_SIZE];
+        header[0..8].copy_from_slice(BLOOMIER_MAGIC);
+        header[8..16].copy_from_slice(&self.seed.to_be_bytes());
+        header[16..20].copy_from_slice(&self.block_length.to_be_bytes());
+        header[20..24].copy_from_slice(&self.len.to_be_bytes());
+        header[24] = self.value_bits as u8;
+        header[25] = self.fingerprint_bits as u8;
+
+        let slot_bytes = self.slot_bytes();
+        let mut buf = Vec::with_capacity(self.slots.len() * slot_bytes);
+        for slot in &self.slots {
+            buf.extend_from_slice(&slot.to_be_bytes()[8 - slot_bytes..]);
+        }
+
+        writer.write_all(&header[..])?;
+        writer.write_all(&buf[..])
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        self.write_to(&mut file)?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomier_build_get_save_load() {
+        let path = "test_bloomier.bf";
+        let _ = std::fs::remove_file(path);
+
+        let pairs = (0..50_000u32).map(|i| (i, u64::from(i % 1000)));
+        let bf = BloomierFilter::build(pairs.chain(vec![(0, 999)]), 10, 0.001).unwrap();
+        assert_eq!(50_000, bf.len());
+        assert!((0..50_000u32).all(|i| bf.get(i) == Some(u64::from(i % 1000))));
+
+        let fp = (50_000..150_000u32)
+            .filter(|i| bf.get(*i).is_some())
+            .count();
+        assert!(fp < 200);
+
+        bf.save(path).unwrap();
+        let loaded = BloomierFilter::load(path).unwrap();
+        assert_eq!(bf, loaded);
+        assert_eq!(Some(999), loaded.get(999u32));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
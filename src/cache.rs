@@ -0,0 +1,209 @@
+/// A memory-bounded set of filters, loaded on demand from their paths.
+///
+/// When the filters held exceed the budget, victims chosen by an
+/// `EvictionPolicy` are saved if dirty and dropped, to be transparently
+/// reloaded on their next use.  The filter being accessed is never evicted,
+/// so a single filter larger than the budget will still be served.
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{BloomFilter, BloomHash};
+
+/// Chooses which cached filter to evict next.
+pub trait EvictionPolicy {
+    /// Record a use of `key`.
+    fn touch(&mut self, key: &Path);
+
+    /// Forget `key`, which has been evicted.
+    fn remove(&mut self, key: &Path);
+
+    /// The next key to evict, other than `exclude`.
+    fn victim(&self, exclude: &Path) -> Option<PathBuf>;
+}
+
+/// Evict the least-recently used filter.
+#[derive(Debug, Default)]
+pub struct LruPolicy {
+    tick: u64,
+    used: HashMap<PathBuf, u64>,
+}
+
+impl EvictionPolicy for LruPolicy {
+    fn touch(&mut self, key: &Path) {
+        self.tick += 1;
+        self.used.insert(key.to_path_buf(), self.tick);
+    }
+
+    fn remove(&mut self, key: &Path) {
+        self.used.remove(key);
+    }
+
+    fn victim(&self, exclude: &Path) -> Option<PathBuf> {
+        self.used
+            .iter()
+            .filter(|(key, _)| key.as_path() != exclude)
+            .min_by_key(|(_, tick)| **tick)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct BloomFilterCache<E = LruPolicy> {
+    budget: usize,
+    policy: E,
+    filters: HashMap<PathBuf, BloomFilter>,
+}
+
+impl BloomFilterCache<LruPolicy> {
+    pub fn new(budget: usize) -> Self {
+        Self::with_policy(budget, LruPolicy::default())
+    }
+}
+
+impl<E: EvictionPolicy> BloomFilterCache<E> {
+    pub fn with_policy(budget: usize, policy: E) -> Self {
+        Self {
+            budget,
+            policy,
+            filters: HashMap::new(),
+        }
+    }
+
+    /// Fetch the filter for `path`, loading it if necessary.
+    pub fn get<P: AsRef<Path>>(&mut self, path: P) -> io::Result<&mut BloomFilter> {
+        let path = path.as_ref();
+
+        if !self.filters.contains_key(path) {
+            let filter = BloomFilter::load(path)?;
+            self.filters.insert(path.to_path_buf(), filter);
+        }
+
+        self.policy.touch(path);
+        self.evict(path)?;
+
+        Ok(self.filters.get_mut(path).expect("just inserted"))
+    }
+
+    /// Add a new filter to be saved to `path` when evicted or flushed,
+    /// returning any filter already cached under that path.
+    pub fn insert_filter<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        filter: BloomFilter,
+    ) -> io::Result<Option<BloomFilter>> {
+        let path = path.as_ref();
+        let old = self.filters.insert(path.to_path_buf(), filter);
+
+        self.policy.touch(path);
+        self.evict(path)?;
+
+        Ok(old)
+    }
+
+    pub fn contains<P: AsRef<Path>, T: Into<BloomHash>>(
+        &mut self,
+        path: P,
+        item: T,
+    ) -> io::Result<bool> {
+        Ok(self.get(path)?.contains(item))
+    }
+
+    pub fn insert<P: AsRef<Path>, T: Into<BloomHash>>(
+        &mut self,
+        path: P,
+        item: T,
+    ) -> io::Result<bool> {
+        Ok(self.get(path)?.insert(item))
+    }
+
+    /// Whether the filter for `path` is currently held in memory.
+    pub fn is_cached<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.filters.contains_key(path.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.filters.values().map(|f| f.memory_bytes()).sum()
+    }
+
+    /// Save every dirty filter.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (path, filter) in self.filters.iter_mut() {
+            if filter.is_dirty() {
+                filter.save(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evict(&mut self, keep: &Path) -> io::Result<()> {
+        while self.memory_bytes() > self.budget {
+            let victim = match self.policy.victim(keep) {
+                Some(victim) => victim,
+                None => break,
+            };
+
+            if let Some(mut filter) = self.filters.remove(&victim) {
+                if filter.is_dirty() {
+                    if let Err(e) = filter.save(&victim) {
+                        self.filters.insert(victim, filter);
+                        return Err(e);
+                    }
+                }
+            }
+
+            self.policy.remove(&victim);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_evicts_and_reloads() {
+        let paths: Vec<String> = (0..3).map(|i| format!("test_cache_{}.bf", i)).collect();
+
+        for (i, path) in paths.iter().enumerate() {
+            let _ = std::fs::remove_file(path);
+            let mut bf = BloomFilter::with_capacity_p(1024, 0.01);
+            bf.insert(i);
+            bf.save(path).unwrap();
+        }
+
+        let one = BloomFilter::load(&paths[0]).unwrap().memory_bytes();
+        let mut cache = BloomFilterCache::new(one * 2);
+
+        for (i, path) in paths.iter().enumerate() {
+            assert!(cache.contains(path, i).unwrap());
+        }
+
+        assert_eq!(2, cache.len());
+        assert!(!cache.is_cached(&paths[0]));
+        assert!(cache.memory_bytes() <= one * 2);
+
+        // dirty filters must survive eviction
+        assert!(cache.insert(&paths[1], "meep").unwrap());
+        assert!(cache.contains(&paths[0], 0usize).unwrap());
+        assert!(!cache.is_cached(&paths[2]));
+        assert!(cache.contains(&paths[2], 2usize).unwrap());
+        assert!(!cache.is_cached(&paths[1]));
+        assert!(cache.contains(&paths[1], "meep").unwrap());
+
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,237 @@
+/// Bloom filter cascade, as used by CRLite, encoding a set exactly within a
+/// known universe.
+///
+/// Given every item which should be included, and every other item which may
+/// ever be queried, the first level holds the included items, the second
+/// holds the excluded items the first level wrongly matches, the third the
+/// included items the second wrongly matches, and so on until a level makes
+/// no mistakes.  Queries walk down the levels until one doesn't match; the
+/// parity of that level gives the answer, with no false positives or
+/// negatives for any item in the universe.  Items outside it get an answer
+/// with roughly the first level's false-positive rate.
+///
+/// Each level hashes with its own salt, so its mistakes are independent of
+/// the last.
+///
+/// The cascade is saved to a single file: a short header recording the number
+/// of levels, followed by each level in the usual single-filter format.
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::xor::mix;
+use crate::{BloomFilter, BloomHash};
+
+const CASCADE_MAGIC: &[u8; 8] = b"CBLOOM00";
+const CASCADE_HEADER_SIZE: usize = 16;
+
+/// Levels after the first only have to halve the set each time.
+const CASCADE_LATER_P: f64 = 0.5;
+
+/// Enough for any cascade over disjoint sets; reaching it means they overlap.
+const CASCADE_MAX_LEVELS: usize = 64;
+
+#[derive(Debug, Default, Clone)]
+pub struct CascadeBuilder {
+    include: Vec<BloomHash>,
+    exclude: Vec<BloomHash>,
+    p: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct BloomCascade {
+    levels: Vec<BloomFilter>,
+}
+
+fn level_hash(hash: &BloomHash, level: usize) -> BloomHash {
+    BloomHash {
+        h1: mix(hash.h1, level as u64),
+        h2: mix(hash.h2, level as u64),
+    }
+}
+
+impl CascadeBuilder {
+    /// Add items which the cascade should contain.
+    pub fn include<I, T>(&mut self, items: I) -> &mut Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<BloomHash>,
+    {
+        self.include.extend(items.into_iter().map(Into::into));
+        self
+    }
+
+    /// Add items which the cascade should not contain.
+    pub fn exclude<I, T>(&mut self, items: I) -> &mut Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<BloomHash>,
+    {
+        self.exclude.extend(items.into_iter().map(Into::into));
+        self
+    }
+
+    /// False-positive rate of the first level.  By default this is chosen to
+    /// roughly minimise the total size, as in the CRLite paper.
+    pub fn false_positives(&mut self, p: f64) -> &mut Self {
+        assert!(p > 0.0 && p < 1.0);
+
+        self.p = Some(p);
+        self
+    }
+
+    /// Build the cascade, returning `None` if an item was both included and
+    /// excluded.
+    pub fn build(&self) -> Option<BloomCascade> {
+        let p = self.p.unwrap_or_else(|| {
+            let ratio = self.include.len().max(1) as f64 / self.exclude.len().max(1) as f64;
+            (ratio / std::f64::consts::SQRT_2).clamp(1e-6, CASCADE_LATER_P)
+        });
+
+        let mut levels = vec![];
+        let mut include = self.include.clone();
+        let mut exclude = self.exclude.clone();
+
+        while !include.is_empty() {
+            if levels.len() == CASCADE_MAX_LEVELS {
+                return None;
+            }
+
+            let level = levels.len();
+            let level_p = if level == 0 { p } else { CASCADE_LATER_P };
+            let mut filter = BloomFilter::with_capacity_p(include.len() as u64, level_p);
+
+            for hash in &include {
+                filter.insert_hash(&level_hash(hash, level));
+            }
+
+            // the mistakes become the next level's contents
+            let mistakes: Vec<BloomHash> = exclude
+                .into_iter()
+                .filter(|hash| filter.contains_hash(&level_hash(hash, level)))
+                .collect();
+
+            levels.push(filter);
+            exclude = include;
+            include = mistakes;
+        }
+
+        Some(BloomCascade { levels })
+    }
+}
+
+impl BloomCascade {
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        match (0..self.levels.len())
+            .find(|level| !self.levels[*level].contains_hash(&level_hash(hash, *level)))
+        {
+            // rejected by an even level, which holds included items
+            Some(level) => level % 2 == 1,
+            None => self.levels.len() % 2 == 1,
+        }
+    }
+
+    /// Number of levels in the cascade.
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Total size of the filter bodies in bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| level.filter.as_bytes().len())
+            .sum()
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; CASCADE_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != CASCADE_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let depth = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        if depth as usize > CASCADE_MAX_LEVELS {
+            return Err(invalid_data("too many levels"));
+        }
+
+        let levels = (0..depth)
+            .map(|_| BloomFilter::from_reader(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self { levels })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(File::open(path.as_ref())?))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; CASCADE_HEADER_SIZE];
+        header[0..8].copy_from_slice(CASCADE_MAGIC);
+        header[8..12].copy_from_slice(&(self.levels.len() as u32).to_be_bytes());
+        writer.write_all(&header[..])?;
+
+        for level in &self.levels {
+            level.write_to(&mut writer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut writer = io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascade_exact_save_load() {
+        let path = "test_cascade.bf";
+        let _ = std::fs::remove_file(path);
+
+        let cascade = CascadeBuilder::default()
+            .include(0..10_000)
+            .exclude(10_000..1_000_000)
+            .build()
+            .unwrap();
+
+        assert!(cascade.depth() > 1);
+        assert!((0..10_000).all(|i| cascade.contains(i)));
+        assert!((10_000..1_000_000).all(|i| !cascade.contains(i)));
+
+        cascade.save(path).unwrap();
+        let loaded = BloomCascade::load(path).unwrap();
+        assert_eq!(cascade.depth(), loaded.depth());
+        assert!((0..10_000).all(|i| loaded.contains(i)));
+        assert!((10_000..1_000_000).all(|i| !loaded.contains(i)));
+
+        assert!(CascadeBuilder::default()
+            .include(0..10)
+            .exclude(5..20)
+            .build()
+            .is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+/// zstd-compressed saves of plain `BloomFilter`s.
+///
+/// A filter at low fill is mostly zero bytes and compresses extremely well,
+/// which matters more than save speed when it's shipped around as a
+/// blocklist.  The body is compressed in independent runs of
+/// `format::COMPRESSED_RUN_PAGES` pages, so neither end needs a second copy of
+/// the whole body in memory.
+///
+/// Compressed files can't be updated in place: `save` on one recompresses the
+/// whole filter.  Without the `zstd` feature they can't be loaded at all.
+use std::io::{self, Read};
+
+use crate::paged::invalid_data;
+#[cfg(feature = "zstd")]
+use {
+    crate::{format, BloomFilter},
+    std::{fs::OpenOptions, io::Write, path::Path},
+};
+
+#[cfg(feature = "zstd")]
+const RUN_LEN: usize = format::PAGE_SIZE as usize * format::COMPRESSED_RUN_PAGES;
+
+#[cfg(feature = "zstd")]
+impl BloomFilter {
+    /// Save the whole filter to `path` zstd-compressed.  `load` reads it
+    /// back as usual.
+    pub fn save_compressed<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.generation += 1;
+        self.write_compressed(path.as_ref())?;
+        self.clear_dirty();
+        Ok(())
+    }
+
+    /// Write the filter to `path` compressed, as the current generation.
+    pub(crate) fn write_compressed(&self, path: &Path) -> io::Result<()> {
+        let mut header = self.checksummed_header();
+        header.flags |= format::FLAG_COMPRESSED;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+
+        let mut writer = io::BufWriter::new(file);
+        header.write(&mut writer)?;
+        write_body(&mut writer, self.filter.as_bytes())?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        self.durability.sync(&file)
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn write_body<W: Write>(mut writer: W, body: &[u8]) -> io::Result<()> {
+    for run in body.chunks(RUN_LEN) {
+        let frame = zstd::bulk::compress(run, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+        writer.write_all(&frame)?;
+    }
+
+    Ok(())
+}
+
+/// Decompress a body from `reader` into `body`, which must be its full
+/// uncompressed length.
+#[cfg(feature = "zstd")]
+pub(crate) fn read_body<R: Read>(mut reader: R, body: &mut [u8]) -> io::Result<()> {
+    let mut frame = vec![];
+
+    for run in body.chunks_mut(RUN_LEN) {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+
+        // don't trust a corrupt length enough to allocate it up front
+        frame.clear();
+        (&mut reader).take(len as u64).read_to_end(&mut frame)?;
+        if frame.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let data = zstd::bulk::decompress(&frame, run.len())?;
+        if data.len() != run.len() {
+            return Err(invalid_data("short compressed run"));
+        }
+        run.copy_from_slice(&data);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn read_body<R: Read>(_reader: R, _body: &mut [u8]) -> io::Result<()> {
+    Err(invalid_data("compressed filters need the zstd feature"))
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_compressed() {
+        let path = "test_compressed.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.001);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+
+        bf.save_compressed(path).unwrap();
+        let len = std::fs::metadata(path).unwrap().len();
+        assert!(len < (format::HEADER_SIZE + bf.memory_bytes() / 4) as u64);
+
+        let mut loaded = BloomFilter::load(path).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+        BloomFilter::verify(path).unwrap();
+
+        // saving over a compressed file keeps it compressed
+        loaded.insert(1000u32);
+        loaded.save(path).unwrap();
+        assert!(std::fs::metadata(path).unwrap().len() < len * 2);
+        assert!(BloomFilter::load(path).unwrap().contains(1000u32));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,413 @@
+/// Counting variant of `BloomFilter`, with an 8-bit saturating counter in place
+/// of each bit.
+///
+/// Counters share the paged layout of the plain filter: each item lands in a
+/// single page of `page_size` counters, chosen by an extra hash.
+///
+/// A counter that reaches 255 sticks there, since we no longer know how many
+/// items it represents and decrementing it could introduce false negatives.
+///
+/// Files share the header of the plain filter with `FLAG_COUNTING` set, and a
+/// body of one byte per counter.
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::{AddAssign, SubAssign};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::paged::{invalid_data, save_paged};
+use crate::{set_all, BloomFilterParams, BloomFilterParamsBuilder, BloomHash};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountingBloomFilter {
+    params: BloomFilterParams,
+    count: u64,
+    pages: u64,
+    dirty: BitVec,
+    counters: Vec<u8>,
+}
+
+impl CountingBloomFilter {
+    pub fn from_params(params: BloomFilterParams) -> Self {
+        let requested_p = params.requested_p.unwrap_or(params.p);
+        let page_slots = u64::from(params.page_size);
+        let params = BloomFilterParamsBuilder::default()
+            .bits(params.m.div_ceil(page_slots) * page_slots)
+            .false_positives(params.p)
+            .page_size(params.page_size)
+            .hash_key(params.hash_key.0, params.hash_key.1)
+            .hash_algorithm(params.hash_algorithm)
+            .to_params()
+            .map(|params| BloomFilterParams {
+                requested_p: Some(requested_p),
+                ..params
+            })
+            .unwrap();
+
+        let pages = params.m / page_slots;
+
+        Self {
+            dirty: BitVec::from_elem(pages as usize, false),
+            counters: vec![0; params.m as usize],
+            count: 0,
+            pages,
+            params,
+        }
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+
+        if !header.is_counting() || header.is_sparse() || header.is_compressed() {
+            return Err(invalid_data("not a counting filter"));
+        }
+
+        let mut counters = vec![0; header.m as usize];
+        reader.read_exact(&mut counters[..])?;
+        header.verify_body(&counters)?;
+
+        let algorithm = header.hash_algorithm();
+        if !algorithm.is_available() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "filter hashes items with {}, not enabled in this build",
+                    algorithm
+                ),
+            ));
+        }
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(header.n)
+            .bits(header.m)
+            .hashes(header.k)
+            .page_size(header.page_size())
+            .hash_key(header.hash_key().0, header.hash_key().1)
+            .hash_algorithm(algorithm)
+            .to_params()
+            .map_err(|e| invalid_data(&format!("invalid parameters: {}", e)))?;
+
+        // slots relies on every page lying within m
+        let page_slots = u64::from(params.page_size);
+        if params.m % page_slots != 0 {
+            return Err(invalid_data("size is not a whole number of pages"));
+        }
+        let pages = params.m / page_slots;
+
+        // counters can't tell us how many distinct items were inserted, but the
+        // number of increments is their sum over k
+        let count =
+            counters.iter().map(|c| u64::from(*c)).sum::<u64>() / u64::from(params.k.max(1));
+
+        Ok(Self {
+            dirty: BitVec::from_elem(pages as usize, false),
+            counters,
+            count,
+            pages,
+            params,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(self.params.n, self.params.m, self.params.k);
+        header.flags = format::FLAG_COUNTING;
+        header.set_page_size(self.params.page_size);
+        header.set_hash_key(self.params.hash_key);
+        header.set_hash_algorithm(self.params.hash_algorithm);
+        header
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        save_paged(
+            path.as_ref(),
+            &self.file_header(),
+            &self.counters[..],
+            &mut self.dirty,
+        )
+    }
+
+    pub fn with_capacity_p(capacity: u64, p: f64) -> Self {
+        Self::from_params(BloomFilterParams::with_capacity_p(capacity, p))
+    }
+
+    fn page_slots(&self) -> u64 {
+        u64::from(self.params.page_size)
+    }
+
+    fn page(&self, hash: BloomHash) -> u64 {
+        if self.pages > 0 {
+            hash.nth(self.params.k + 1) % self.pages
+        } else {
+            0
+        }
+    }
+
+    fn slots(&self, hash: BloomHash) -> impl Iterator<Item = usize> {
+        let page_slots = self.page_slots();
+        let offset = self.page(hash) * page_slots;
+
+        (0..self.params.k).map(move |k| (offset + (hash.nth(k) % page_slots)) as usize)
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&self.params.rehash(item.into()))
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.slots(*hash).all(|slot| self.counters[slot] > 0)
+    }
+
+    /// Insert an item, returning `true` if it was not already present.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        let hash = self.params.rehash(item.into());
+        self.insert_hash(&hash)
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let hash = *hash;
+        let mut added = false;
+
+        for slot in self.slots(hash) {
+            let counter = &mut self.counters[slot];
+            added |= *counter == 0;
+            *counter = counter.saturating_add(1);
+        }
+
+        self.count += 1;
+        self.dirty.set(self.page(hash) as usize, true);
+        added
+    }
+
+    /// Remove an item, returning `false` if it was not present.
+    ///
+    /// Removing an item which was never inserted, but which tests positive,
+    /// will cause false negatives for the items it collided with.
+    pub fn remove<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        let hash = self.params.rehash(item.into());
+
+        if !self.contains_hash(&hash) {
+            return false;
+        }
+
+        for slot in self.slots(hash) {
+            let counter = &mut self.counters[slot];
+            if *counter != u8::MAX {
+                *counter -= 1;
+            }
+        }
+
+        self.count = self.count.saturating_sub(1);
+        self.dirty.set(self.page(hash) as usize, true);
+        true
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Whether `other` has the same geometry and hashing, and can be combined
+    /// with this filter.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.params.m == other.params.m
+            && self.params.k == other.params.k
+            && self.params.page_size == other.params.page_size
+            && self.params.hash_key == other.params.hash_key
+            && self.params.hash_algorithm == other.params.hash_algorithm
+    }
+}
+
+/// Counter-wise saturating addition: union with multiplicity.
+///
+/// Panics if the filters are not compatible.
+impl AddAssign<&CountingBloomFilter> for CountingBloomFilter {
+    fn add_assign(&mut self, other: &CountingBloomFilter) {
+        assert!(self.is_compatible(other), "incompatible filter geometry");
+
+        for (a, b) in self.counters.iter_mut().zip(other.counters.iter()) {
+            *a = a.saturating_add(*b);
+        }
+
+        self.count = self.count.saturating_add(other.count);
+        set_all(&mut self.dirty, true);
+    }
+}
+
+/// Counter-wise subtraction, clamping at zero and leaving saturated counters
+/// untouched.
+///
+/// Panics if the filters are not compatible.
+impl SubAssign<&CountingBloomFilter> for CountingBloomFilter {
+    fn sub_assign(&mut self, other: &CountingBloomFilter) {
+        assert!(self.is_compatible(other), "incompatible filter geometry");
+
+        for (a, b) in self.counters.iter_mut().zip(other.counters.iter()) {
+            if *a != u8::MAX {
+                *a = a.saturating_sub(*b);
+            }
+        }
+
+        self.count = self.count.saturating_sub(other.count);
+        set_all(&mut self.dirty, true);
+    }
+}
+
+impl From<BloomFilterParams> for CountingBloomFilter {
+    fn from(p: BloomFilterParams) -> Self {
+        Self::from_params(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting_whole_pages() {
+        let page_slots = u64::from(format::PAGE_SIZE);
+        let cbf = CountingBloomFilter::with_capacity_p(1024, 0.01);
+        assert_eq!(0, cbf.params.m % page_slots);
+
+        // a size already in whole pages isn't rounded up another
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(1000)
+            .bits(page_slots * 2)
+            .to_params()
+            .unwrap();
+        let cbf = CountingBloomFilter::from_params(params);
+        assert_eq!(page_slots * 2, cbf.params.m);
+        assert_eq!(2, cbf.dirty.len());
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(1000)
+            .bits(page_slots * 2)
+            .page_size(512)
+            .to_params()
+            .unwrap();
+        let cbf = CountingBloomFilter::from_params(params);
+        assert_eq!(page_slots * 2, cbf.params.m);
+        assert_eq!(page_slots as usize * 2 / 512, cbf.dirty.len());
+    }
+
+    #[test]
+    fn counting_add_sub() {
+        let mut a = CountingBloomFilter::with_capacity_p(1024, 0.01);
+        let mut b = CountingBloomFilter::with_capacity_p(1024, 0.01);
+
+        for i in 0..100 {
+            a.insert(i);
+        }
+
+        for i in 50..200 {
+            b.insert(i);
+        }
+
+        let orig = a.clone();
+
+        a += &b;
+        assert!((0..200).all(|i| a.contains(i)));
+
+        a -= &b;
+        assert_eq!(orig.counters, a.counters);
+        assert_eq!(orig.count, a.count);
+        assert!((0..100).all(|i| a.contains(i)));
+    }
+
+    #[test]
+    fn counting_hashes() {
+        let mut cbf = CountingBloomFilter::with_capacity_p(1024, 0.01);
+        let hashes: Vec<BloomHash> = (0..512).map(BloomHash::from).collect();
+
+        for hash in &hashes {
+            cbf.insert_hash(hash);
+        }
+
+        assert!(!cbf.insert_hash(&hashes[0]));
+        assert!(hashes.iter().all(|hash| cbf.contains_hash(hash)));
+        assert!((0..512).all(|i| cbf.contains(i)));
+    }
+
+    #[test]
+    fn counting_remove_save_load() {
+        let path = "test_counting.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut cbf = CountingBloomFilter::with_capacity_p(1024, 0.01);
+        for i in 0..512 {
+            cbf.insert(i);
+        }
+
+        assert!(cbf.remove(0));
+        assert!(!cbf.contains(0));
+        assert!(!cbf.remove(0));
+        assert_eq!(511, cbf.count());
+
+        cbf.save(path).unwrap();
+        let mut cbf = CountingBloomFilter::load(path).unwrap();
+        assert!(!cbf.contains(0));
+        assert!((1..512).all(|i| cbf.contains(i)));
+        assert!(crate::BloomFilter::load(path).is_err());
+
+        assert!(cbf.remove(1));
+        cbf.save(path).unwrap();
+        let cbf = CountingBloomFilter::load(path).unwrap();
+        assert!(!cbf.contains(1));
+        assert!((2..512).all(|i| cbf.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn counting_hash_key_save_load() {
+        let path = "test_counting_key.bf";
+        let _ = std::fs::remove_file(path);
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(1024)
+            .false_positives(0.01)
+            .page_size(512)
+            .hash_key(1, 2)
+            .to_params()
+            .unwrap();
+        let mut cbf = CountingBloomFilter::from_params(params);
+        for i in 0..512 {
+            cbf.insert(i);
+        }
+        assert!(cbf.contains_hash(&cbf.params.rehash(BloomHash::from(0))));
+
+        cbf.save(path).unwrap();
+        let mut loaded = CountingBloomFilter::load(path).unwrap();
+        assert_eq!((1, 2), loaded.params.hash_key);
+        assert_eq!(512, loaded.params.page_size);
+        assert_eq!(cbf.params.hash_algorithm, loaded.params.hash_algorithm);
+        assert!(loaded.is_compatible(&cbf));
+        assert!((0..512).all(|i| loaded.contains(i)));
+
+        assert!(loaded.remove(0));
+        assert!(!loaded.contains(0));
+        loaded.save(path).unwrap();
+        let loaded = CountingBloomFilter::load(path).unwrap();
+        assert!(!loaded.contains(0));
+        assert!((1..512).all(|i| loaded.contains(i)));
+
+        // the same counters with the default key don't match
+        let unkeyed = CountingBloomFilter::from_params(BloomFilterParams {
+            hash_key: (0, 0),
+            ..loaded.params.clone()
+        });
+        assert!(!unkeyed.is_compatible(&loaded));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+/// Count-min sketch, after Cormode and Muthukrishnan, estimating how often
+/// each item has been seen.
+///
+/// Each of `depth` rows of counters is indexed by its own hash of the item;
+/// an increment adds to the item's counter in every row, and the estimate is
+/// the smallest of them.  Estimates never fall short of the true count, and
+/// exceed it by no more than `error()` times the total of all increments,
+/// with probability `confidence()`.
+///
+/// Rows are rounded up to whole pages of 32-bit counters with dirty tracking,
+/// and saved like the other paged filters with `FLAG_COUNT_MIN` set: `m` is
+/// the total number of counters and `k` the number of rows.
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::paged::{invalid_data, save_paged};
+use crate::{BloomHash, CountMinSketchParams, BLOOM_PAGE_SIZE};
+
+const COUNTER_BYTES: usize = 4;
+const PAGE_COUNTERS: u32 = BLOOM_PAGE_SIZE / COUNTER_BYTES as u32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountMinSketch {
+    params: CountMinSketchParams,
+    total: u64,
+    dirty: BitVec,
+    counters: Vec<u8>,
+}
+
+impl CountMinSketch {
+    pub fn from_params(params: CountMinSketchParams) -> Self {
+        assert!(params.width > 0 && params.depth > 0);
+
+        let params = CountMinSketchParams {
+            width: params.width.div_ceil(PAGE_COUNTERS) * PAGE_COUNTERS,
+            ..params
+        };
+        let counters = params.width as usize * params.depth as usize;
+
+        Self {
+            params,
+            total: 0,
+            dirty: BitVec::from_elem(counters / PAGE_COUNTERS as usize, false),
+            counters: vec![0; counters * COUNTER_BYTES],
+        }
+    }
+
+    /// See `CountMinSketchParams::with_error`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        Self::from_params(CountMinSketchParams::with_error(epsilon, delta))
+    }
+
+    pub fn params(&self) -> CountMinSketchParams {
+        self.params
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+
+        if !header.is_count_min() || header.is_sparse() || header.is_compressed() || header.k == 0 {
+            return Err(invalid_data("not a count-min sketch"));
+        }
+
+        let width = u32::try_from(header.m / u64::from(header.k))
+            .map_err(|_| invalid_data("invalid sketch geometry"))?;
+        if width == 0
+            || width % PAGE_COUNTERS != 0
+            || u64::from(width) * u64::from(header.k) != header.m
+        {
+            return Err(invalid_data("invalid sketch geometry"));
+        }
+
+        let mut ret = Self::from_params(CountMinSketchParams {
+            width,
+            depth: header.k,
+        });
+        reader.read_exact(&mut ret.counters[..])?;
+        header.verify_body(&ret.counters)?;
+
+        // every increment lands once in each row
+        ret.total = (0..width as usize).map(|i| u64::from(ret.get(i))).sum();
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(
+            0,
+            u64::from(self.params.width) * u64::from(self.params.depth),
+            self.params.depth,
+        );
+        header.flags = format::FLAG_COUNT_MIN;
+        header
+    }
+
+    /// Save to `path`, rewriting only dirty pages if it already holds this
+    /// sketch.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let header = self.file_header();
+        save_paged(path.as_ref(), &header, &self.counters[..], &mut self.dirty)
+    }
+
+    fn cells(&self, hash: BloomHash) -> impl Iterator<Item = usize> {
+        let width = u64::from(self.params.width);
+
+        (0..self.params.depth)
+            .map(move |row| (u64::from(row) * width + hash.nth(row) % width) as usize)
+    }
+
+    fn get(&self, cell: usize) -> u32 {
+        let i = cell * COUNTER_BYTES;
+        u32::from_be_bytes([
+            self.counters[i],
+            self.counters[i + 1],
+            self.counters[i + 2],
+            self.counters[i + 3],
+        ])
+    }
+
+    fn set(&mut self, cell: usize, value: u32) {
+        let i = cell * COUNTER_BYTES;
+        self.counters[i..i + COUNTER_BYTES].copy_from_slice(&value.to_be_bytes());
+        self.dirty.set(cell / PAGE_COUNTERS as usize, true);
+    }
+
+    pub fn increment<T: Into<BloomHash>>(&mut self, item: T) {
+        self.increment_by(item, 1);
+    }
+
+    /// Add `count` to an item's counters, saturating at `u32::MAX`.
+    pub fn increment_by<T: Into<BloomHash>>(&mut self, item: T, count: u32) {
+        self.increment_hash_by(&item.into(), count);
+    }
+
+    /// Like `increment_by`, with a pre-computed hash, which may be reused
+    /// across sketches.
+    pub fn increment_hash_by(&mut self, hash: &BloomHash, count: u32) {
+        let cells: Vec<usize> = self.cells(*hash).collect();
+
+        for cell in cells {
+            let value = self.get(cell).saturating_add(count);
+            self.set(cell, value);
+        }
+
+        self.total += u64::from(count);
+    }
+
+    /// Estimated number of increments of an item, never less than the true
+    /// count.
+    pub fn estimate<T: Into<BloomHash>>(&self, item: T) -> u32 {
+        self.estimate_hash(&item.into())
+    }
+
+    /// Like `estimate`, with a pre-computed hash.
+    pub fn estimate_hash(&self, hash: &BloomHash) -> u32 {
+        self.cells(*hash)
+            .map(|cell| self.get(cell))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Total of all increments.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Halve every counter, so older increments count for less than newer
+    /// ones.
+    pub(crate) fn halve(&mut self) {
+        for cell in 0..self.counters.len() / COUNTER_BYTES {
+            let value = self.get(cell);
+            if value > 0 {
+                self.set(cell, value / 2);
+            }
+        }
+
+        let width = self.params.width as usize;
+        self.total = (0..width).map(|i| u64::from(self.get(i))).sum();
+    }
+}
+
+impl From<CountMinSketchParams> for CountMinSketch {
+    fn from(p: CountMinSketchParams) -> Self {
+        Self::from_params(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countmin_estimate_save_load() {
+        let path = "test_countmin.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut cms = CountMinSketch::with_error(0.0001, 0.01);
+        for i in 0..10_000u32 {
+            cms.increment_by(i, i % 10 + 1);
+        }
+
+        assert_eq!(
+            cms.estimate(5u32),
+            cms.estimate_hash(&BloomHash::from(5u32))
+        );
+
+        let error = (cms.params().error() * cms.total() as f64) as u32;
+        assert!((0..10_000u32).all(|i| cms.estimate(i) > i % 10));
+
+        // within the error bound at the promised confidence
+        let outside = (0..10_000u32)
+            .filter(|i| cms.estimate(*i) > i % 10 + 1 + error)
+            .count();
+        assert!(outside < 100);
+
+        cms.save(path).unwrap();
+        cms.increment("extra");
+        cms.increment_hash_by(&BloomHash::from("hashed"), 3);
+        assert!(cms.estimate("hashed") >= 3);
+        cms.save(path).unwrap();
+
+        let loaded = CountMinSketch::load(path).unwrap();
+        assert_eq!(cms, loaded);
+        assert_eq!(1, loaded.estimate("extra"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+/// Cuckoo filter, after Fan et al.
+///
+/// Items are stored as 16-bit fingerprints in buckets of four, each with two
+/// candidate buckets derived by partial-key cuckoo hashing.  Unlike a bloom
+/// filter, items may be removed, and at low false-positive rates less space
+/// is needed per item.  The false-positive rate is roughly 8 / 2^16, or about
+/// 0.012%.
+///
+/// The table is split into pages of `PAGE_SIZE` bytes with dirty tracking,
+/// and saved like the other paged filters with `FLAG_CUCKOO` set in the
+/// header.
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::paged::{invalid_data, save_paged};
+use crate::{set_all, BloomHash, BLOOM_PAGE_SIZE};
+
+const BUCKET_SLOTS: usize = 4;
+const FINGERPRINT_BYTES: usize = 2;
+const BUCKET_BYTES: usize = BUCKET_SLOTS * FINGERPRINT_BYTES;
+const PAGE_BUCKETS: usize = BLOOM_PAGE_SIZE as usize / BUCKET_BYTES;
+const MAX_KICKS: usize = 500;
+const LOAD_FACTOR: f64 = 0.95;
+
+#[derive(Debug, Clone)]
+pub struct CuckooFilter {
+    capacity: u32,
+    count: u32,
+    buckets: usize,
+    rng: u64,
+    dirty: BitVec,
+    table: Vec<u8>,
+}
+
+impl CuckooFilter {
+    /// A filter for at least `capacity` items, rounded up to a power-of-two
+    /// number of whole pages.
+    pub fn with_capacity(capacity: u32) -> Self {
+        let wanted = (f64::from(capacity) / (BUCKET_SLOTS as f64 * LOAD_FACTOR)).ceil() as usize;
+        let buckets = wanted.max(PAGE_BUCKETS).next_power_of_two();
+
+        Self::with_buckets(capacity, buckets)
+    }
+
+    fn with_buckets(capacity: u32, buckets: usize) -> Self {
+        Self {
+            capacity,
+            count: 0,
+            buckets,
+            rng: 0x853c_49e6_748f_ea9b,
+            dirty: BitVec::from_elem(buckets / PAGE_BUCKETS, false),
+            table: vec![0; buckets * BUCKET_BYTES],
+        }
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+
+        if !header.is_cuckoo()
+            || header.is_sparse()
+            || header.is_compressed()
+            || header.k as usize != BUCKET_SLOTS
+        {
+            return Err(invalid_data("not a cuckoo filter"));
+        }
+
+        let buckets = header.m as usize / BUCKET_SLOTS;
+        if buckets < PAGE_BUCKETS || !buckets.is_power_of_two() {
+            return Err(invalid_data("invalid bucket count"));
+        }
+
+        let capacity = u32::try_from(header.n).map_err(|_| invalid_data("invalid capacity"))?;
+        let mut ret = Self::with_buckets(capacity, buckets);
+        reader.read_exact(&mut ret.table[..])?;
+        header.verify_body(&ret.table)?;
+
+        ret.count = ret
+            .table
+            .chunks(FINGERPRINT_BYTES)
+            .filter(|fp| fp.iter().any(|b| *b != 0))
+            .count() as u32;
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(
+            u64::from(self.capacity),
+            (self.buckets * BUCKET_SLOTS) as u64,
+            BUCKET_SLOTS as u32,
+        );
+        header.flags = format::FLAG_CUCKOO;
+        header
+    }
+
+    /// Save to `path`, rewriting only dirty pages if it already holds this
+    /// filter.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let header = self.file_header();
+        save_paged(path.as_ref(), &header, &self.table[..], &mut self.dirty)
+    }
+
+    fn fingerprint(hash: BloomHash) -> u16 {
+        // zero marks an empty slot
+        match (hash.h2 >> 48) as u16 {
+            0 => 1,
+            fp => fp,
+        }
+    }
+
+    fn index(&self, hash: BloomHash) -> usize {
+        (hash.h1 as usize) & (self.buckets - 1)
+    }
+
+    fn alt_index(&self, index: usize, fp: u16) -> usize {
+        (index ^ (u64::from(fp).wrapping_mul(0x5bd1_e995) as usize)) & (self.buckets - 1)
+    }
+
+    fn get(&self, bucket: usize, slot: usize) -> u16 {
+        let i = (bucket * BUCKET_SLOTS + slot) * FINGERPRINT_BYTES;
+        u16::from_be_bytes([self.table[i], self.table[i + 1]])
+    }
+
+    fn set(&mut self, bucket: usize, slot: usize, fp: u16) {
+        let i = (bucket * BUCKET_SLOTS + slot) * FINGERPRINT_BYTES;
+        self.table[i..i + FINGERPRINT_BYTES].copy_from_slice(&fp.to_be_bytes());
+        self.dirty.set(bucket / PAGE_BUCKETS, true);
+    }
+
+    fn find(&self, bucket: usize, fp: u16) -> Option<usize> {
+        (0..BUCKET_SLOTS).find(|slot| self.get(bucket, *slot) == fp)
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        let hash = *hash;
+        let fp = Self::fingerprint(hash);
+        let i1 = self.index(hash);
+
+        self.find(i1, fp).is_some() || self.find(self.alt_index(i1, fp), fp).is_some()
+    }
+
+    /// Insert an item, returning `false` if the table is too full to place it.
+    /// The filter is unchanged in that case.
+    ///
+    /// Items are not deduplicated: inserting the same item twice uses two
+    /// slots and requires two removals.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let hash = *hash;
+        let mut fp = Self::fingerprint(hash);
+        let i1 = self.index(hash);
+        let i2 = self.alt_index(i1, fp);
+
+        for bucket in &[i1, i2] {
+            if let Some(slot) = self.find(*bucket, 0) {
+                self.set(*bucket, slot, fp);
+                self.count += 1;
+                return true;
+            }
+        }
+
+        // evict residents to their alternate buckets, remembering each swap
+        // so a failed insert can be unwound
+        let mut bucket = if self.next_random() & 1 == 0 { i1 } else { i2 };
+        let mut swaps = Vec::with_capacity(MAX_KICKS);
+
+        for _ in 0..MAX_KICKS {
+            let slot = (self.next_random() % BUCKET_SLOTS as u64) as usize;
+            let evicted = self.get(bucket, slot);
+            self.set(bucket, slot, fp);
+            swaps.push((bucket, slot, evicted));
+
+            fp = evicted;
+            bucket = self.alt_index(bucket, fp);
+
+            if let Some(slot) = self.find(bucket, 0) {
+                self.set(bucket, slot, fp);
+                self.count += 1;
+                return true;
+            }
+        }
+
+        for (bucket, slot, evicted) in swaps.into_iter().rev() {
+            self.set(bucket, slot, evicted);
+        }
+
+        false
+    }
+
+    /// Remove one copy of an item, returning `false` if it was not present.
+    ///
+    /// Removing an item which was never inserted may remove another item
+    /// sharing its fingerprint.
+    pub fn remove<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        let hash = item.into();
+        let fp = Self::fingerprint(hash);
+        let i1 = self.index(hash);
+
+        for bucket in &[i1, self.alt_index(i1, fp)] {
+            if let Some(slot) = self.find(*bucket, fp) {
+                self.set(*bucket, slot, 0);
+                self.count -= 1;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(0);
+        self.count = 0;
+        set_all(&mut self.dirty, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuckoo_insert_remove() {
+        let mut cf = CuckooFilter::with_capacity(10_000);
+
+        for i in 0..10_000 {
+            assert!(cf.insert(i));
+        }
+        assert_eq!(10_000, cf.count());
+        assert!((0..10_000).all(|i| cf.contains(i)));
+
+        let fp = (10_000..110_000).filter(|i| cf.contains(*i)).count();
+        assert!(fp < 100);
+
+        for i in 0..5000 {
+            assert!(cf.remove(i));
+        }
+        assert!((5000..10_000).all(|i| cf.contains(i)));
+        assert!((0..5000).filter(|i| cf.contains(*i)).count() < 10);
+
+        let hash = BloomHash::from("hashed");
+        assert!(cf.insert_hash(&hash));
+        assert!(cf.contains_hash(&hash));
+        assert!(cf.contains("hashed"));
+    }
+
+    #[test]
+    fn cuckoo_full_and_save_load() {
+        let path = "test_cuckoo.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut cf = CuckooFilter::with_capacity(1);
+        let mut inserted = 0;
+        while cf.insert(inserted) {
+            inserted += 1;
+        }
+
+        // a failed insert leaves everything else in place
+        assert!(inserted as usize > PAGE_BUCKETS * BUCKET_SLOTS * 9 / 10);
+        assert!((0..inserted).all(|i| cf.contains(i)));
+
+        cf.save(path).unwrap();
+        assert!(cf.remove(0));
+        cf.save(path).unwrap();
+
+        let cf = CuckooFilter::load(path).unwrap();
+        assert_eq!(inserted as u32 - 1, cf.count());
+        assert!((1..inserted).all(|i| cf.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
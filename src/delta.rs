@@ -0,0 +1,148 @@
+/// Delta files, for keeping replicas of a filter current by shipping only the
+/// pages which changed.
+///
+/// A delta holds every page dirtied since the filter was last saved, along
+/// with checksums of the whole filter as it stands.  Applying it overwrites
+/// those pages on the replica, after checking the result would match: a
+/// replica which has drifted, or missed changes which have since been saved
+/// on the primary, refuses the delta and is left untouched.
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::format::{self, FileHeader};
+use crate::paged::invalid_data;
+use crate::{header_params, BloomFilter, BloomFilterParams};
+
+impl BloomFilter {
+    /// Write the pages changed since the last save to `path`, for
+    /// `apply_delta` on a copy of the filter.
+    ///
+    /// The pages remain dirty, so a following `save` still writes them, and
+    /// each delta covers everything since then rather than since the
+    /// previous delta.
+    pub fn save_delta<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut header = self.file_header();
+        header.flags |= format::FLAG_SPARSE | format::FLAG_DELTA;
+        header.page_map = Some(self.dirty.iter().collect());
+        header.set_checksums(self.filter.as_bytes());
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut writer = io::BufWriter::new(file);
+        header.write(&mut writer)?;
+
+        let page_size = self.params.page_size as usize;
+        let bytes = self.filter.as_bytes();
+        for (index, _) in self.dirty.iter().enumerate().filter(|(_, bit)| *bit) {
+            writer.write_all(&bytes[index * page_size..(index + 1) * page_size])?;
+        }
+
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        self.durability.sync(&file)
+    }
+
+    /// Apply a delta written by `save_delta` on a filter of the same
+    /// geometry.  Applied pages are marked dirty for the next `save`.
+    pub fn apply_delta<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let mut reader = io::BufReader::new(File::open(path.as_ref())?);
+        let header = FileHeader::read(&mut reader)?;
+        if !header.is_delta() {
+            return Err(invalid_data("not a delta"));
+        }
+
+        let params = header_params(&header)?;
+        let geometry = |p: &BloomFilterParams| {
+            (
+                p.m,
+                p.k,
+                p.layout,
+                p.page_size,
+                p.hash_key,
+                p.hash_algorithm,
+            )
+        };
+        if geometry(&params) != geometry(&self.params) {
+            return Err(invalid_data("delta is for a different filter"));
+        }
+
+        let page_size = self.params.page_size as usize;
+        let mut pages = BTreeMap::new();
+        if let Some(map) = &header.page_map {
+            for (index, _) in map.iter().enumerate().filter(|(_, present)| **present) {
+                let mut page = vec![0; page_size];
+                reader.read_exact(&mut page)?;
+                pages.insert(index, page);
+            }
+        }
+
+        let sums = self
+            .filter
+            .as_bytes()
+            .chunks(page_size)
+            .enumerate()
+            .map(|(index, page)| format::crc32c(pages.get(&index).map_or(page, |p| &p[..])));
+        header
+            .verify_page_checksums(sums)
+            .map_err(|_| invalid_data("delta doesn't apply to this filter"))?;
+
+        self.filter.with_bytes_mut(|buf| {
+            for (index, page) in &pages {
+                buf[index * page_size..(index + 1) * page_size].copy_from_slice(page);
+            }
+        });
+        for index in pages.keys() {
+            self.mark_dirty(*index);
+        }
+        self.count = self.count_estimate();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_delta() {
+        let path = "test_delta.bf";
+        let delta = "test_delta.bf.delta";
+        let _ = std::fs::remove_file(path);
+
+        let mut primary = BloomFilter::with_capacity_p(1_000_000, 0.001);
+        for i in 0..1000u32 {
+            primary.insert(i);
+        }
+        primary.save(path).unwrap();
+        let mut replica = BloomFilter::load(path).unwrap();
+        let mut stale = BloomFilter::load(path).unwrap();
+
+        primary.insert(5000u32);
+        primary.save_delta(delta).unwrap();
+        assert!(BloomFilter::load(delta).is_err());
+
+        replica.apply_delta(delta).unwrap();
+        assert!(replica.contains(5000u32));
+        assert!(replica.is_dirty());
+        assert_eq!(primary.digest(), replica.digest());
+
+        // a replica which missed a saved change can't catch up from a delta
+        primary.save(path).unwrap();
+        primary.insert(6000u32);
+        primary.save_delta(delta).unwrap();
+        assert!(stale.apply_delta(delta).is_err());
+        assert!(!stale.contains(6000u32));
+
+        replica.apply_delta(delta).unwrap();
+        assert_eq!(primary.digest(), replica.digest());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(delta).unwrap();
+    }
+}
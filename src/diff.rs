@@ -0,0 +1,153 @@
+/// In-memory diffs of a filter, for applications replicating it over their
+/// own transport rather than by copying files.
+///
+/// A `FilterDiff` holds every page changed since a given generation,
+/// including changes not yet saved, along with checksums of the whole
+/// filter as it stands.  As with deltas, applying one checks the result
+/// would match, so a replica which has drifted or missed earlier changes
+/// refuses it and is left untouched.
+///
+/// A replica asks for the changes since the generation of the last diff it
+/// applied, starting from the generation of the file it was loaded from or
+/// zero if it began empty.  Pages of a filter loaded from a file count as
+/// changed at that file's generation, so asking such a filter for an earlier
+/// one gets every page.
+use std::io;
+
+use crate::format;
+use crate::paged::invalid_data;
+use crate::BloomFilter;
+
+/// Pages changed in a filter since some generation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterDiff {
+    /// Generation of the filter the diff was taken from, to ask for the next
+    /// diff since.
+    pub generation: u64,
+    /// Size of the filter's pages in bytes.
+    pub page_size: u32,
+    /// Each changed page by index.
+    pub pages: Vec<(usize, Vec<u8>)>,
+    /// CRC-32C of every page of the filter once the diff is applied.
+    pub checksums: Vec<u32>,
+}
+
+impl BloomFilter {
+    /// The pages changed since `generation`, for `apply_diff` on a replica.
+    pub fn diff_since(&self, generation: u64) -> FilterDiff {
+        let page_size = self.params.page_size as usize;
+        let bytes = self.filter.as_bytes();
+
+        FilterDiff {
+            generation: self.generation,
+            page_size: self.params.page_size,
+            pages: self
+                .changed
+                .iter()
+                .enumerate()
+                .filter(|(_, changed)| **changed > generation)
+                .map(|(index, _)| {
+                    (
+                        index,
+                        bytes[index * page_size..(index + 1) * page_size].to_vec(),
+                    )
+                })
+                .collect(),
+            checksums: bytes.chunks(page_size).map(format::crc32c).collect(),
+        }
+    }
+
+    /// Apply a diff taken from a filter of the same geometry.  Applied pages
+    /// are marked dirty for the next `save`.
+    pub fn apply_diff(&mut self, diff: &FilterDiff) -> io::Result<()> {
+        let page_size = self.params.page_size as usize;
+        if diff.page_size != self.params.page_size
+            || diff.checksums.len() != self.pages as usize
+            || diff
+                .pages
+                .iter()
+                .any(|(index, page)| *index >= self.pages as usize || page.len() != page_size)
+        {
+            return Err(invalid_data("diff is for a different filter"));
+        }
+
+        let mut pages: Vec<Option<&[u8]>> = vec![None; self.pages as usize];
+        for (index, page) in &diff.pages {
+            pages[*index] = Some(page);
+        }
+
+        let matches = self
+            .filter
+            .as_bytes()
+            .chunks(page_size)
+            .zip(&pages)
+            .map(|(page, new)| format::crc32c(new.unwrap_or(page)))
+            .eq(diff.checksums.iter().copied());
+        if !matches {
+            return Err(invalid_data("diff doesn't apply to this filter"));
+        }
+
+        self.filter.with_bytes_mut(|buf| {
+            for (index, page) in &diff.pages {
+                buf[index * page_size..(index + 1) * page_size].copy_from_slice(page);
+            }
+        });
+        for (index, _) in &diff.pages {
+            self.mark_dirty(*index);
+        }
+        self.count = self.count_estimate();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_diff() {
+        let path = "test_diff.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut primary = BloomFilter::with_capacity_p(1_000_000, 0.001);
+        for i in 0..1000u32 {
+            primary.insert(i);
+        }
+        primary.save(path).unwrap();
+        let mut replica = BloomFilter::load(path).unwrap();
+        let mut stale = BloomFilter::load(path).unwrap();
+        let mut since = replica.generation();
+
+        // unsaved changes are included
+        primary.insert(5000u32);
+        let diff = primary.diff_since(since);
+        assert_eq!(1, diff.pages.len());
+        replica.apply_diff(&diff).unwrap();
+        assert!(replica.contains(5000u32));
+        assert!(replica.is_dirty());
+        assert_eq!(primary.digest(), replica.digest());
+        since = diff.generation;
+
+        // and sent again once saved, along with anything since
+        primary.save(path).unwrap();
+        primary.insert(6000u32);
+        let diff = primary.diff_since(since);
+        replica.apply_diff(&diff).unwrap();
+        assert_eq!(primary.digest(), replica.digest());
+
+        // a replica which missed changes can't catch up from a later diff
+        assert!(stale.apply_diff(&primary.diff_since(2)).is_err());
+        assert!(!stale.contains(6000u32));
+
+        // but can from the generation it last saw
+        stale.apply_diff(&primary.diff_since(1)).unwrap();
+        assert!(stale.contains(6000u32));
+
+        // asking a loaded filter for changes before it was saved gets them all
+        let loaded = BloomFilter::load(path).unwrap();
+        assert_eq!(loaded.pages as usize, loaded.diff_since(0).pages.len());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+/// Saving and loading with O_DIRECT, bypassing the page cache.
+///
+/// Flushing or reading a huge filter through the page cache evicts
+/// everything else from it, for data which is already held in memory.  Direct
+/// I/O avoids that, at the cost of transfers which must be aligned in memory,
+/// in the file and in length; every transfer here is a whole page through a
+/// page-aligned buffer, which the format's page-aligned body makes natural.
+/// Filters with pages smaller than the device's block size can't be saved
+/// this way, and fail with `InvalidInput`.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+use crate::format::{self, FileHeader};
+use crate::{paged, BloomFilter};
+
+/// Alignment of every buffer, enough for any block size up to a header.
+const ALIGN: usize = format::HEADER_SIZE;
+
+/// A buffer of `len` bytes aligned to `ALIGN`.
+struct AlignedPage {
+    buf: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedPage {
+    fn new(len: usize) -> Self {
+        let buf = vec![0; len + ALIGN];
+        let start = buf.as_ptr().align_offset(ALIGN);
+        Self { buf, start, len }
+    }
+
+    fn get(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.start + self.len]
+    }
+}
+
+/// Sequential reads of a file opened with O_DIRECT, a page at a time.
+struct DirectReader {
+    file: File,
+    page: AlignedPage,
+    offset: u64,
+    pos: usize,
+    len: usize,
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len {
+            self.len = self.file.read_at(self.page.get(), self.offset)?;
+            self.offset += self.len as u64;
+            self.pos = 0;
+        }
+
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.page.get()[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn open_direct(path: &Path, options: &mut OpenOptions) -> io::Result<File> {
+    options.custom_flags(libc::O_DIRECT).open(path)
+}
+
+impl BloomFilter {
+    /// Like `save`, with O_DIRECT so the page cache is left alone.  Sparse
+    /// and other differently formatted files are replaced in full, dense.
+    pub fn save_direct<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let file = open_direct(
+            path.as_ref(),
+            OpenOptions::new().create(true).read(true).write(true),
+        )?;
+        self.generation += 1;
+        let mut head = AlignedPage::new(format::HEADER_SIZE);
+        let page_size = self.params.page_size as usize;
+        let mut page = AlignedPage::new(page_size);
+
+        let len = file.metadata()?.len();
+        let existing = if len >= format::HEADER_SIZE as u64 {
+            file.read_exact_at(head.get(), 0)?;
+            FileHeader::from_bytes(head.get()).ok()
+        } else {
+            None
+        };
+
+        let bytes = self.filter.as_bytes();
+        let offset = |index: usize| (format::HEADER_SIZE + index * page_size) as u64;
+        match existing {
+            Some(mut header)
+                if header.without_checksums() == self.file_header().without_checksums()
+                    && len >= header.file_len() =>
+            {
+                self.stamp(&mut header);
+                let dirty = paged::dirty_pages(&self.dirty);
+                for (at, body) in paged::in_place(&mut header, bytes, &dirty) {
+                    page.get().copy_from_slice(body);
+                    file.write_all_at(page.get(), at)?;
+                }
+
+                header.write(head.get())?;
+                file.write_all_at(head.get(), 0)?;
+            }
+            _ => {
+                file.set_len(0)?;
+                if self.preallocate {
+                    paged::preallocate(&file, (format::HEADER_SIZE + bytes.len()) as u64)?;
+                }
+                self.checksummed_header().write(head.get())?;
+                file.write_all_at(head.get(), 0)?;
+
+                // as with paged::write_sparse, zero pages are left as holes
+                for (index, body) in bytes.chunks(page_size).enumerate() {
+                    if body.iter().any(|b| *b != 0) {
+                        page.get().copy_from_slice(body);
+                        file.write_all_at(page.get(), offset(index))?;
+                    }
+                }
+                file.set_len((format::HEADER_SIZE + bytes.len()) as u64)?;
+            }
+        }
+
+        self.durability.sync(&file)?;
+        self.clear_dirty();
+
+        Ok(())
+    }
+
+    /// Like `load`, with O_DIRECT so the page cache is left alone.
+    pub fn load_direct<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = open_direct(path.as_ref(), OpenOptions::new().read(true))?;
+
+        Self::from_reader(DirectReader {
+            file,
+            page: AlignedPage::new(format::HEADER_SIZE),
+            offset: 0,
+            pos: 0,
+            len: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_direct() {
+        let path = "test_direct.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.001);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+
+        // filesystems such as tmpfs refuse O_DIRECT outright
+        let probe = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path);
+        if probe.is_err() {
+            return;
+        }
+
+        bf.save_direct(path).unwrap();
+
+        let loaded = BloomFilter::load_direct(path).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+
+        bf.insert(5000u32);
+        bf.save_direct(path).unwrap();
+        BloomFilter::verify(path).unwrap();
+        assert!(BloomFilter::load(path).unwrap().contains(5000u32));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
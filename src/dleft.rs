@@ -0,0 +1,300 @@
+/// d-left counting bloom filter, after Bonomi et al.
+///
+/// Rather than a counter per bit, each item stores a 12-bit fingerprint
+/// remainder with a small counter in one of `DLEFT_TABLES` subtables: the
+/// least loaded of its candidate buckets, leftmost on ties.  This balances
+/// load so tightly that buckets rarely overflow, using around half the space
+/// of a `CountingBloomFilter` at a similar false-positive rate.
+///
+/// Each subtable applies its own permutation to the item's full fingerprint,
+/// splitting the result into a bucket index and the stored remainder.  An
+/// item's fingerprint therefore determines its remainder in every subtable,
+/// so a duplicate is always found and counted rather than stored twice, and
+/// removing an inserted item can't leave a stale copy of it behind.
+///
+/// Cells are 16 bits: the remainder above a 4-bit counter, with a zero
+/// counter marking an empty cell.  A counter which reaches 15 sticks there.
+///
+/// Files share the header of the plain filter with `FLAG_DLEFT` set, `m`
+/// cells, `k` subtables, and a body of two big-endian bytes per cell.
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::paged::{invalid_data, save_paged};
+use crate::{BloomHash, BLOOM_PAGE_SIZE};
+
+const DLEFT_TABLES: usize = 4;
+const BUCKET_CELLS: usize = 8;
+const CELL_BYTES: usize = 2;
+const BUCKET_BYTES: usize = BUCKET_CELLS * CELL_BYTES;
+const PAGE_BUCKETS: usize = BLOOM_PAGE_SIZE as usize / BUCKET_BYTES;
+const REMAINDER_BITS: u32 = 12;
+const COUNTER_BITS: u32 = 4;
+const COUNTER_MAX: u16 = (1 << COUNTER_BITS) - 1;
+const LOAD_FACTOR: f64 = 0.75;
+
+/// Odd multipliers for each subtable's fingerprint permutation.
+const PERMUTATIONS: [u64; DLEFT_TABLES] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xbf58_476d_1ce4_e5b9,
+    0x94d0_49bb_1331_11eb,
+    0xd6e8_feb8_6659_fd93,
+];
+
+#[derive(Debug, Clone)]
+pub struct DLeftCountingFilter {
+    capacity: u32,
+    count: u32,
+    /// log2 of the number of buckets in each subtable.
+    bucket_bits: u32,
+    dirty: BitVec,
+    cells: Vec<u8>,
+}
+
+impl DLeftCountingFilter {
+    /// A filter for at least `capacity` items, rounded up to a power-of-two
+    /// number of buckets per subtable and whole pages.
+    pub fn with_capacity(capacity: u32) -> Self {
+        let cells = (f64::from(capacity) / LOAD_FACTOR).ceil() as usize;
+        let buckets = (cells / (DLEFT_TABLES * BUCKET_CELLS) + 1)
+            .max(PAGE_BUCKETS)
+            .next_power_of_two();
+
+        Self::with_bucket_bits(capacity, buckets.trailing_zeros())
+    }
+
+    fn with_bucket_bits(capacity: u32, bucket_bits: u32) -> Self {
+        let buckets = DLEFT_TABLES << bucket_bits;
+
+        Self {
+            capacity,
+            count: 0,
+            bucket_bits,
+            dirty: BitVec::from_elem(buckets / PAGE_BUCKETS, false),
+            cells: vec![0; buckets * BUCKET_BYTES],
+        }
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+
+        if !header.is_dleft()
+            || header.is_sparse()
+            || header.is_compressed()
+            || header.k as usize != DLEFT_TABLES
+        {
+            return Err(invalid_data("not a d-left counting filter"));
+        }
+
+        let buckets = header.m as usize / (DLEFT_TABLES * BUCKET_CELLS);
+        if buckets < PAGE_BUCKETS || !buckets.is_power_of_two() {
+            return Err(invalid_data("invalid bucket count"));
+        }
+
+        let capacity = u32::try_from(header.n).map_err(|_| invalid_data("invalid capacity"))?;
+        let mut ret = Self::with_bucket_bits(capacity, buckets.trailing_zeros());
+        reader.read_exact(&mut ret.cells[..])?;
+        header.verify_body(&ret.cells)?;
+
+        ret.count = (0..ret.cells.len() / CELL_BYTES)
+            .map(|cell| u32::from(ret.get(cell) & COUNTER_MAX))
+            .sum();
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(
+            u64::from(self.capacity),
+            (self.cells.len() / CELL_BYTES) as u64,
+            DLEFT_TABLES as u32,
+        );
+        header.flags = format::FLAG_DLEFT;
+        header
+    }
+
+    /// Save to `path`, rewriting only dirty pages if it already holds this
+    /// filter.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let header = self.file_header();
+        save_paged(path.as_ref(), &header, &self.cells[..], &mut self.dirty)
+    }
+
+    /// The bucket and remainder of an item in each subtable.
+    fn candidates(&self, hash: BloomHash) -> [(usize, u16); DLEFT_TABLES] {
+        let bits = self.bucket_bits + REMAINDER_BITS;
+        let mask = (1u64 << bits) - 1;
+        let fingerprint = hash.h2 & mask;
+
+        let mut ret = [(0, 0); DLEFT_TABLES];
+        for (table, multiplier) in PERMUTATIONS.iter().enumerate() {
+            let permuted = fingerprint
+                .wrapping_mul(*multiplier)
+                .wrapping_add(table as u64)
+                & mask;
+            let bucket = (table << self.bucket_bits) + (permuted >> REMAINDER_BITS) as usize;
+            let remainder = (permuted & ((1 << REMAINDER_BITS) - 1)) as u16;
+            ret[table] = (bucket, remainder);
+        }
+        ret
+    }
+
+    fn get(&self, cell: usize) -> u16 {
+        let i = cell * CELL_BYTES;
+        u16::from_be_bytes([self.cells[i], self.cells[i + 1]])
+    }
+
+    fn set(&mut self, cell: usize, value: u16) {
+        let i = cell * CELL_BYTES;
+        self.cells[i..i + CELL_BYTES].copy_from_slice(&value.to_be_bytes());
+        self.dirty.set(i / BLOOM_PAGE_SIZE as usize, true);
+    }
+
+    /// The cell in `bucket` holding `remainder`, if any.
+    fn find(&self, bucket: usize, remainder: u16) -> Option<usize> {
+        (bucket * BUCKET_CELLS..(bucket + 1) * BUCKET_CELLS).find(|cell| {
+            let value = self.get(*cell);
+            value & COUNTER_MAX != 0 && value >> COUNTER_BITS == remainder
+        })
+    }
+
+    fn find_any(&self, hash: BloomHash) -> Option<usize> {
+        self.candidates(hash)
+            .iter()
+            .find_map(|(bucket, remainder)| self.find(*bucket, *remainder))
+    }
+
+    fn load_of(&self, bucket: usize) -> usize {
+        (bucket * BUCKET_CELLS..(bucket + 1) * BUCKET_CELLS)
+            .filter(|cell| self.get(*cell) & COUNTER_MAX != 0)
+            .count()
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.find_any(*hash).is_some()
+    }
+
+    /// Insert an item, returning `false` if every candidate bucket is full.
+    /// The filter is unchanged in that case.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let hash = *hash;
+
+        if let Some(cell) = self.find_any(hash) {
+            let value = self.get(cell);
+            if value & COUNTER_MAX != COUNTER_MAX {
+                self.set(cell, value + 1);
+            }
+            self.count += 1;
+            return true;
+        }
+
+        // min_by_key returns the first minimum, so ties go left
+        let (bucket, remainder) = *self
+            .candidates(hash)
+            .iter()
+            .min_by_key(|(bucket, _)| self.load_of(*bucket))
+            .unwrap();
+
+        if self.load_of(bucket) == BUCKET_CELLS {
+            return false;
+        }
+
+        let cell = (bucket * BUCKET_CELLS..(bucket + 1) * BUCKET_CELLS)
+            .find(|cell| self.get(*cell) & COUNTER_MAX == 0)
+            .unwrap();
+        self.set(cell, (remainder << COUNTER_BITS) | 1);
+        self.count += 1;
+        true
+    }
+
+    /// Remove one copy of an item, returning `false` if it was not present.
+    ///
+    /// Removing an item which was never inserted may remove another item
+    /// sharing its fingerprint.
+    pub fn remove<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        let cell = match self.find_any(item.into()) {
+            Some(cell) => cell,
+            None => return false,
+        };
+
+        let value = self.get(cell);
+        if value & COUNTER_MAX == 1 {
+            self.set(cell, 0);
+        } else if value & COUNTER_MAX != COUNTER_MAX {
+            self.set(cell, value - 1);
+        }
+
+        self.count = self.count.saturating_sub(1);
+        true
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dleft_insert_remove_save_load() {
+        let path = "test_dleft.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut df = DLeftCountingFilter::with_capacity(100_000);
+        for i in 0..100_000 {
+            assert!(df.insert(i));
+        }
+        assert!(df.insert(0));
+        assert!((0..100_000).all(|i| df.contains(i)));
+
+        let fp = (100_000..200_000).filter(|i| df.contains(*i)).count();
+        assert!(fp < 1000);
+        assert!(df.contains_hash(&BloomHash::from(0)));
+        assert!(df.insert_hash(&BloomHash::from(1)));
+        assert!(df.remove(1));
+
+        df.save(path).unwrap();
+        for i in 0..50_000 {
+            assert!(df.remove(i));
+        }
+        assert!(df.contains(0));
+        assert!(df.remove(0));
+        df.save(path).unwrap();
+
+        let df = DLeftCountingFilter::load(path).unwrap();
+        assert_eq!(50_000, df.count());
+        assert!((50_000..100_000).all(|i| df.contains(i)));
+        assert!((0..50_000).filter(|i| df.contains(*i)).count() < 500);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
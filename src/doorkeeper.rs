@@ -0,0 +1,126 @@
+/// TinyLFU admission control, after Einziger, Friedman and Manes.
+///
+/// A cache which is full must decide whether a newly requested item is worth
+/// evicting an existing one for.  A `Doorkeeper` records every access, and
+/// `admit` lets the candidate in only if it has been requested more often
+/// than the victim it would replace.
+///
+/// The first access to an item only sets it in a bloom filter, the doorkeeper
+/// proper, so the many items seen just once never reach the count-min sketch
+/// of frequencies.  Every `sample_size` accesses the filter is cleared and
+/// the sketch halved, so popularity fades with age.
+use crate::{BloomFilter, BloomHash, CountMinSketch, CountMinSketchParams};
+
+/// Rows in the frequency sketch.
+const DOORKEEPER_DEPTH: u32 = 4;
+
+/// Accesses between resets, as a multiple of the cache's capacity.
+const DOORKEEPER_SAMPLE_FACTOR: u64 = 10;
+
+#[derive(Debug)]
+pub struct Doorkeeper {
+    filter: BloomFilter,
+    sketch: CountMinSketch,
+    sample_size: u64,
+    accesses: u64,
+}
+
+impl Doorkeeper {
+    /// A doorkeeper for a cache of `capacity` items.
+    pub fn new(capacity: u32) -> Self {
+        assert!(capacity > 0);
+
+        Self {
+            filter: BloomFilter::with_capacity_p(u64::from(capacity), 0.01),
+            sketch: CountMinSketch::from_params(CountMinSketchParams {
+                width: capacity,
+                depth: DOORKEEPER_DEPTH,
+            }),
+            sample_size: u64::from(capacity) * DOORKEEPER_SAMPLE_FACTOR,
+            accesses: 0,
+        }
+    }
+
+    /// Number of accesses between each reset.
+    pub fn sample_size(&self) -> u64 {
+        self.sample_size
+    }
+
+    /// Record an access to an item.
+    pub fn record<T: Into<BloomHash>>(&mut self, item: T) {
+        self.record_hash(&item.into());
+    }
+
+    pub fn record_hash(&mut self, hash: &BloomHash) {
+        // already past the doorkeeper, so worth counting
+        if !self.filter.insert_hash(hash) {
+            self.sketch.increment(*hash);
+        }
+
+        self.accesses += 1;
+        if self.accesses >= self.sample_size {
+            self.reset();
+        }
+    }
+
+    /// Estimated number of recent accesses to an item.
+    pub fn frequency<T: Into<BloomHash>>(&self, item: T) -> u32 {
+        self.frequency_hash(&item.into())
+    }
+
+    pub fn frequency_hash(&self, hash: &BloomHash) -> u32 {
+        let seen = u32::from(self.filter.contains_hash(hash));
+        self.sketch.estimate(*hash).saturating_add(seen)
+    }
+
+    /// Whether `candidate` should replace `victim` in the cache, being the
+    /// more frequently accessed of the two.
+    pub fn admit<C, V>(&self, candidate: C, victim: V) -> bool
+    where
+        C: Into<BloomHash>,
+        V: Into<BloomHash>,
+    {
+        self.frequency(candidate) > self.frequency(victim)
+    }
+
+    /// Clear the doorkeeper and halve all frequencies.  This happens
+    /// automatically every `sample_size()` accesses.
+    pub fn reset(&mut self) {
+        self.filter = self.filter.empty_like();
+        self.sketch.halve();
+        self.accesses = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doorkeeper_admit_reset() {
+        let mut dk = Doorkeeper::new(100);
+
+        for _ in 0..5 {
+            dk.record("popular");
+        }
+        dk.record("once");
+
+        assert_eq!(0, dk.frequency("never"));
+        assert_eq!(1, dk.frequency("once"));
+        assert!(dk.frequency("popular") >= 5);
+
+        assert!(dk.admit("popular", "once"));
+        assert!(!dk.admit("once", "popular"));
+        assert!(!dk.admit("never", "once"));
+
+        // the filter forgets one-hit wonders, the sketch fades the rest
+        dk.reset();
+        assert_eq!(0, dk.frequency("once"));
+        assert_eq!(2, dk.frequency("popular"));
+
+        for i in 0..dk.sample_size() {
+            dk.record(i);
+        }
+        assert!(dk.frequency("popular") <= 1);
+    }
+}
@@ -0,0 +1,300 @@
+/// A `BloomFilter` whose dirty pages are written out by a background thread,
+/// so inserting never waits on a save.
+///
+/// The flusher owns the file.  It wakes when its interval elapses, or sooner
+/// once enough pages are dirty, copies the dirty pages out while briefly
+/// holding the filter's lock, and writes them with the lock released.  Page
+/// checksums are kept up to date as it goes; a file too large for a page
+/// checksum table loses its digest until the next full save.
+///
+/// An error writing in the background is kept and returned by the next
+/// `flush` or `close`, and the pages involved are lost from the file until
+/// they're next dirtied, so a failed flush should be followed by a full save.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::format::{self, FileHeader};
+use crate::{paged, BloomFilter, BloomHash};
+
+/// When the background thread writes out dirty pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPolicy {
+    /// Longest time changes wait to be written.
+    pub interval: Duration,
+    /// Number of dirty pages which prompts a flush before the interval is up.
+    pub max_dirty_pages: usize,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            max_dirty_pages: 64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Signal {
+    wake: bool,
+    stop: bool,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: File,
+    header: FileHeader,
+}
+
+#[derive(Debug)]
+struct Shared {
+    filter: Mutex<BloomFilter>,
+    /// Changes since the dirty page count was last checked, only updated
+    /// with the filter locked.
+    pending: AtomicUsize,
+    writer: Mutex<Writer>,
+    signal: Mutex<Signal>,
+    wake: Condvar,
+    error: Mutex<Option<io::Error>>,
+}
+
+#[derive(Debug)]
+pub struct FlushingBloomFilter {
+    shared: Arc<Shared>,
+    policy: FlushPolicy,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Shared {
+    fn flush(&self) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let (pages, count) = {
+            let mut filter = self.filter.lock().unwrap();
+            (filter.take_dirty_pages(), filter.count)
+        };
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        let Writer { file, header } = &mut *writer;
+        let page_size = header.page_size() as usize;
+        paged::write_pages(
+            file,
+            pages
+                .iter()
+                .map(|(index, page)| ((format::HEADER_SIZE + index * page_size) as u64, &page[..])),
+        )?;
+
+        if header.is_checksummed() || header.digest.is_some() {
+            header.update_page_checksums(
+                pages
+                    .iter()
+                    .map(|(index, page)| (*index, format::crc32c(page))),
+            );
+        }
+        // each flush is a save as far as readers of the file can tell, though
+        // counting bits would hold the filter's lock for too long
+        header.set_generation(header.generation() + 1);
+        header.remove_extension(format::EXT_ONES);
+        header.set_count(count);
+        file.seek(io::SeekFrom::Start(0))?;
+        header.write(&mut *file)?;
+
+        file.sync_data()
+    }
+
+    fn run(&self, interval: Duration) {
+        let mut signal = self.signal.lock().unwrap();
+        loop {
+            signal = self
+                .wake
+                .wait_timeout_while(signal, interval, |s| !s.wake && !s.stop)
+                .unwrap()
+                .0;
+            signal.wake = false;
+            let stop = signal.stop;
+            drop(signal);
+
+            if let Err(e) = self.flush() {
+                self.error.lock().unwrap().get_or_insert(e);
+            }
+
+            if stop {
+                return;
+            }
+            signal = self.signal.lock().unwrap();
+        }
+    }
+
+    fn take_error(&self) -> io::Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FlushingBloomFilter {
+    /// Write `filter` out to `path` in full, then hand it to a background
+    /// thread which keeps the file up to date according to `policy`.
+    pub fn new<P: AsRef<Path>>(
+        mut filter: BloomFilter,
+        path: P,
+        policy: FlushPolicy,
+    ) -> io::Result<Self> {
+        // dirty pages are written in place, which needs a dense file
+        filter.save_atomic(path.as_ref())?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+        let header = FileHeader::read(&mut file)?;
+
+        let shared = Arc::new(Shared {
+            filter: Mutex::new(filter),
+            pending: AtomicUsize::new(0),
+            writer: Mutex::new(Writer { file, header }),
+            signal: Mutex::new(Signal::default()),
+            wake: Condvar::new(),
+            error: Mutex::new(None),
+        });
+
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.run(policy.interval))
+        };
+
+        Ok(Self {
+            shared,
+            policy,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn policy(&self) -> FlushPolicy {
+        self.policy
+    }
+
+    pub fn insert<T: Into<BloomHash>>(&self, item: T) -> bool {
+        let hash = self.shared.filter.lock().unwrap().rehash(item.into());
+        self.insert_hash(&hash)
+    }
+
+    pub fn insert_hash(&self, hash: &BloomHash) -> bool {
+        let mut filter = self.shared.filter.lock().unwrap();
+        if !filter.insert_hash(hash) {
+            return false;
+        }
+
+        // counting dirty pages means a scan, so only look once there could
+        // be enough of them
+        let pending = self.shared.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        if pending >= self.policy.max_dirty_pages {
+            self.shared.pending.store(0, Ordering::Relaxed);
+            if filter.dirty_pages() >= self.policy.max_dirty_pages {
+                self.shared.signal.lock().unwrap().wake = true;
+                self.shared.wake.notify_one();
+            }
+        }
+
+        true
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.shared.filter.lock().unwrap().contains(item)
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.shared.filter.lock().unwrap().contains_hash(hash)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.filter.lock().unwrap().is_empty()
+    }
+
+    /// Write out dirty pages now, on the calling thread, returning any error
+    /// from an earlier background flush first.
+    pub fn flush(&self) -> io::Result<()> {
+        self.shared.take_error()?;
+        self.shared.flush()
+    }
+
+    /// Stop the background thread after a final flush, and return the
+    /// filter.
+    pub fn close(mut self) -> io::Result<BloomFilter> {
+        self.stop();
+
+        let shared = Arc::clone(&self.shared);
+        drop(self);
+
+        let shared = Arc::try_unwrap(shared).expect("flusher thread has exited");
+        shared.take_error()?;
+        let mut filter = shared.filter.into_inner().unwrap();
+        filter.generation = shared.writer.into_inner().unwrap().header.generation();
+        Ok(filter)
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shared.signal.lock().unwrap().stop = true;
+            self.shared.wake.notify_one();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for FlushingBloomFilter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flushing_bloomfilter() {
+        let path = "test_flusher.bf";
+        let _ = std::fs::remove_file(path);
+
+        let policy = FlushPolicy {
+            interval: Duration::from_secs(3600),
+            max_dirty_pages: 4,
+        };
+        let bf = BloomFilter::with_capacity_p(1_000_000, 0.001);
+        let flushing = FlushingBloomFilter::new(bf, path, policy).unwrap();
+
+        // enough dirty pages wake the flusher long before its interval
+        for i in 0..100u32 {
+            assert!(flushing.insert(i));
+        }
+        let start = std::time::Instant::now();
+        while BloomFilter::load(path).map_or(true, |bf| !bf.contains(0u32)) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        flushing.insert(1000u32);
+        flushing.flush().unwrap();
+        assert!(BloomFilter::load(path).unwrap().contains(1000u32));
+
+        flushing.insert(2000u32);
+        let bf = flushing.close().unwrap();
+        assert!(!bf.is_dirty());
+        assert!(bf.contains(2000u32));
+
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!((0..100u32).all(|i| loaded.contains(i)));
+        assert_eq!(bf.digest(), loaded.digest());
+        assert_eq!(bf.generation(), loaded.generation());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
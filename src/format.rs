@@ -0,0 +1,1285 @@
+/// On-disk layout of a saved `BloomFilter`.
+///
+/// A file consists of a header page followed by the filter body:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "BLOOMv" followed by a two-digit version
+/// 8       4     n, design capacity (big-endian u32)
+/// 12      4     m, size of the filter in bits (big-endian u32)
+/// 16      4     k, number of hashes (big-endian u32)
+/// 20      4     flags (big-endian u32, v01 onwards)
+/// 24      4     extension area length (big-endian u32, v02 onwards)
+/// 28      ...   extensions (v02 onwards)
+/// ...     ...   page map, if FLAG_SPARSE is set
+/// ...     ...   page checksums, if FLAG_CHECKSUMS is set
+/// ...     ...   zero padding
+/// HEADER_SIZE - 4   digest (big-endian u32), if FLAG_DIGEST is set
+/// HEADER_SIZE   m / 8 bytes of filter body, in pages
+/// ```
+///
+/// Other filter types reuse the header, with a flag marking how `m` and the
+/// body should be interpreted.
+///
+/// Pages are PAGE_SIZE bytes unless an EXT_PAGE_SIZE extension says
+/// otherwise.
+///
+/// Each item's probes all land within a single body page, unless
+/// FLAG_PARTITIONED is set, and pages may be rewritten independently.  With
+/// FLAG_BLOCKED they land within a single 64-byte block of that page.
+///
+/// Sparse files omit pages which are entirely zero.  The page map holds one
+/// bit per page, most significant bit first, set for each page present in the
+/// body; present pages follow the header in order.
+///
+/// Checksummed files follow the page map with a CRC-32C of every body page,
+/// as big-endian u32s, including pages a sparse file omits.  Files too large
+/// for the table to fit in the header are written without one.
+///
+/// The digest covers the whole body: it's the CRC-32C of the big-endian
+/// CRC-32C of every page, so it can be kept up to date from the page
+/// checksums alone as pages are rewritten.
+///
+/// Compressed files replace the body with zstd frames, each holding a run of
+/// up to COMPRESSED_RUN_PAGES default-sized pages, whatever the page size,
+/// preceded by its length as a big-endian u32.  Checksums and digest still
+/// cover the uncompressed pages.
+///
+/// Delta files hold only the pages changed since a filter was last saved,
+/// using the page map of a sparse file to mark which.  Their checksums and
+/// digest describe the whole filter once the delta has been applied.
+///
+/// Segmented filters keep the header in a file of its own, with the body
+/// split across files of SEGMENT_PAGES pages each, the last possibly shorter.
+///
+/// Encrypted files seal each page with XChaCha20-Poly1305, storing it
+/// followed by its ENCRYPTION_TAG_LEN byte tag.  Page i's nonce is the
+/// EXT_NONCE value with i added to its last eight bytes as a big-endian u64.
+/// They carry no checksums, which would reveal something of the plaintext.
+///
+/// Filters whose `n` or `m` don't fit a u32 hold u32::MAX in those fields,
+/// with the real sizes in an EXT_SIZES extension.
+///
+/// Extensions are tag-length-value entries: a big-endian u16 tag and u16
+/// length followed by that many bytes of value.  Readers skip tags they don't
+/// know, unless the tag has `EXT_CRITICAL` set, in which case the file can't
+/// be read correctly without understanding it and is refused.  Headers are
+/// written as v01 unless they carry extensions, so files which don't need
+/// them remain readable by older versions.
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::HashAlgorithm;
+
+/// Default size of a filter page in bytes.
+pub const PAGE_SIZE: u32 = 1024 * 16;
+
+/// Smallest page size, holding one 512-bit block.
+pub const MIN_PAGE_SIZE: u32 = 64;
+
+/// Largest page size.
+pub const MAX_PAGE_SIZE: u32 = 1024 * 1024;
+
+/// The header occupies one full default-sized page so the body remains
+/// aligned for pages of up to that size.
+pub const HEADER_SIZE: usize = PAGE_SIZE as usize;
+
+/// Common prefix of the magic string; the version digits follow.
+pub const MAGIC_PREFIX: &[u8; 6] = b"BLOOMv";
+
+/// The format version written, unless extensions need a later one.
+pub const VERSION: u8 = 1;
+
+/// The latest format version, adding extensions.  Older versions remain
+/// readable.
+pub const MAX_VERSION: u8 = 2;
+
+/// Set on extension tags which readers must understand to read the file.
+pub const EXT_CRITICAL: u16 = 0x8000;
+
+/// Creation time, as big-endian u64 seconds since the Unix epoch.
+pub const EXT_CREATED: u16 = 1;
+
+/// Further flags, as a big-endian u64, for properties safe to ignore.
+pub const EXT_FLAGS: u16 = 2;
+
+/// Key of the hasher items were hashed with, as two big-endian u64s.  Absent,
+/// the key is zero, SipHash's default.
+pub const EXT_HASH_SEED: u16 = EXT_CRITICAL | 3;
+
+/// Body page size in bytes, as a big-endian u32.
+pub const EXT_PAGE_SIZE: u16 = EXT_CRITICAL | 4;
+
+/// Number of times the filter has been saved, as a big-endian u64.  Absent,
+/// the generation is zero.
+pub const EXT_GENERATION: u16 = 5;
+
+/// Number of bits set in the body, as a big-endian u64, so the number of
+/// items can be estimated without reading it.
+pub const EXT_ONES: u16 = 6;
+
+/// Random base of the nonces of an encrypted body, 24 bytes, chosen afresh
+/// for each save.
+pub const EXT_NONCE: u16 = 7;
+
+/// Number of items inserted, as a big-endian u64, exactly rather than as
+/// estimated from the bits set.
+pub const EXT_COUNT: u16 = 8;
+
+/// Where and when the file was saved: seconds since the Unix epoch as a
+/// big-endian u64, then the crate version, hostname and a user-supplied
+/// label, each UTF-8 preceded by its length as a big-endian u16.
+pub const EXT_PROVENANCE: u16 = 9;
+
+/// Hash function items were hashed with, as a single byte: 1 for xxh3 and 2
+/// for wyhash.  Absent, items were hashed with SipHash.
+pub const EXT_HASH_ALGORITHM: u16 = EXT_CRITICAL | 10;
+
+/// Design capacity and size of the filter, as two big-endian u64s `n` and
+/// `m`, for filters too large for the u32 header fields.  Never present
+/// otherwise, and never stored among a `FileHeader`'s extensions: it's
+/// written and read with the fields it widens.
+pub const EXT_SIZES: u16 = EXT_CRITICAL | 11;
+
+/// Extensions describing a particular save rather than the filter.
+const SAVE_EXTENSIONS: &[u16] = &[
+    EXT_GENERATION,
+    EXT_ONES,
+    EXT_NONCE,
+    EXT_COUNT,
+    EXT_PROVENANCE,
+];
+
+/// Longest string recorded in EXT_PROVENANCE, so it can't crowd out the
+/// checksums.
+const MAX_PROVENANCE_STRING: usize = 255;
+
+/// Where and when a filter file was saved, for tracing files distributed
+/// across a fleet back to what built them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+    /// Version of this crate which saved the file.
+    pub crate_version: String,
+    pub hostname: String,
+    pub label: String,
+}
+
+/// Critical extensions this version understands.
+const SUPPORTED_CRITICAL: &[u16] = &[EXT_HASH_SEED, EXT_PAGE_SIZE, EXT_HASH_ALGORITHM, EXT_SIZES];
+
+/// Only pages marked in the page map are present in the body.
+pub const FLAG_SPARSE: u32 = 1;
+
+/// The body holds `m` 8-bit counters rather than `m` bits.
+pub const FLAG_COUNTING: u32 = 1 << 1;
+
+/// The body holds `m` 16-bit big-endian cuckoo fingerprints, in buckets of
+/// `k`.
+pub const FLAG_CUCKOO: u32 = 1 << 2;
+
+/// The body holds `m` 64-bit big-endian quotient filter slots, each with a
+/// `k`-bit remainder; `n` is the number of entries.
+pub const FLAG_QUOTIENT: u32 = 1 << 3;
+
+/// The body holds `m` 16-bit big-endian spectral bloom filter counters.
+pub const FLAG_SPECTRAL: u32 = 1 << 4;
+
+/// Each of the k hashes probes its own m / k bit segment of the body, rather
+/// than all probing one page.
+pub const FLAG_PARTITIONED: u32 = 1 << 5;
+
+/// The body holds `m` 16-bit big-endian d-left counting filter cells, in `k`
+/// subtables.
+pub const FLAG_DLEFT: u32 = 1 << 6;
+
+/// The body holds `m` 32-bit big-endian count-min sketch counters, in `k`
+/// rows.
+pub const FLAG_COUNT_MIN: u32 = 1 << 7;
+
+/// Each item's probes all land in one 512-bit block of its page.
+pub const FLAG_BLOCKED: u32 = 1 << 8;
+
+/// The header holds a checksum of each body page.
+pub const FLAG_CHECKSUMS: u32 = 1 << 9;
+
+/// The header ends with a digest of the whole body.
+pub const FLAG_DIGEST: u32 = 1 << 10;
+
+/// The body is zstd-compressed, in runs of `COMPRESSED_RUN_PAGES` pages of
+/// `PAGE_SIZE`.
+pub const FLAG_COMPRESSED: u32 = 1 << 11;
+
+/// Default-sized pages compressed together into each frame of a compressed
+/// body.
+pub const COMPRESSED_RUN_PAGES: usize = 64;
+
+/// The file holds changed pages to apply to an existing filter, rather than a
+/// whole filter.  Always accompanied by FLAG_SPARSE.
+pub const FLAG_DELTA: u32 = 1 << 12;
+
+/// The body is stored apart from the header, in segment files.
+pub const FLAG_SEGMENTED: u32 = 1 << 13;
+
+/// Pages in each segment file of a segmented filter.
+pub const SEGMENT_PAGES: usize = 1024;
+
+/// Each body page is encrypted, with the nonce scheme in EXT_NONCE.
+pub const FLAG_ENCRYPTED: u32 = 1 << 14;
+
+/// Bytes of authentication tag following each encrypted page.
+pub const ENCRYPTION_TAG_LEN: usize = 16;
+
+/// Flags marking a body which is not a plain bit array.
+const TYPE_FLAGS: u32 =
+    FLAG_COUNTING | FLAG_CUCKOO | FLAG_QUOTIENT | FLAG_SPECTRAL | FLAG_DLEFT | FLAG_COUNT_MIN;
+
+const KNOWN_FLAGS: u32 = FLAG_SPARSE
+    | FLAG_PARTITIONED
+    | FLAG_BLOCKED
+    | FLAG_CHECKSUMS
+    | FLAG_DIGEST
+    | FLAG_COMPRESSED
+    | FLAG_DELTA
+    | FLAG_SEGMENTED
+    | FLAG_ENCRYPTED
+    | TYPE_FLAGS;
+
+const N_OFFSET: usize = 8;
+const M_OFFSET: usize = 12;
+const K_OFFSET: usize = 16;
+const FLAGS_OFFSET: usize = 20;
+const EXT_LEN_OFFSET: usize = 24;
+const EXT_OFFSET: usize = 28;
+const PAGE_MAP_OFFSET: usize = 24;
+const DIGEST_OFFSET: usize = HEADER_SIZE - 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u8,
+    pub n: u64,
+    pub m: u64,
+    pub k: u32,
+    pub flags: u32,
+    /// Which body pages are present, for sparse files.
+    pub page_map: Option<Vec<bool>>,
+    /// CRC-32C of each body page, for checksummed files.
+    pub page_checksums: Option<Vec<u32>>,
+    /// Digest of the whole body.
+    pub digest: Option<u32>,
+    /// Extension values by tag.
+    pub extensions: BTreeMap<u16, Vec<u8>>,
+}
+
+/// Whether `size` is usable as a page size: a power of two from
+/// `MIN_PAGE_SIZE` to `MAX_PAGE_SIZE`.
+pub fn is_valid_page_size(size: u32) -> bool {
+    size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&size)
+}
+
+/// Why a filter file couldn't be read.
+///
+/// Loading returns `io::Error` like any other I/O, but the errors raised for
+/// bad files carry one of these, which `LoadError::from` recovers.  Other
+/// errors convert to `Io`, apart from running out of file, which becomes
+/// `TruncatedFile`.
+#[derive(Debug)]
+pub enum LoadError {
+    /// Not a filter file, or not one in this crate's format.
+    BadMagic,
+    /// Written in a newer version of the format than this crate reads.
+    UnsupportedVersion(u8),
+    /// The file ends before the header or body does.
+    TruncatedFile,
+    /// The header is damaged or describes something this crate can't read.
+    InvalidHeader(String),
+    /// The body doesn't match the checksums in the header.
+    ChecksumMismatch,
+    Io(io::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("bad magic"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported version {}", version),
+            Self::TruncatedFile => f.write_str("truncated file"),
+            Self::InvalidHeader(msg) => f.write_str(msg),
+            Self::ChecksumMismatch => f.write_str("checksum mismatch"),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<LoadError>()) {
+            let inner = e.into_inner().expect("checked above");
+            return *inner.downcast::<LoadError>().expect("checked above");
+        }
+
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => Self::TruncatedFile,
+            _ => Self::Io(e),
+        }
+    }
+}
+
+impl From<LoadError> for io::Error {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Io(e) => e,
+            LoadError::TruncatedFile => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+pub(crate) fn invalid(msg: &str) -> io::Error {
+    LoadError::InvalidHeader(msg.to_string()).into()
+}
+
+/// The digest of a body with the given page checksums.
+pub fn digest_of(sums: &[u32]) -> u32 {
+    let bytes: Vec<u8> = sums
+        .iter()
+        .flat_map(|sum| sum.to_be_bytes().to_vec())
+        .collect();
+    crc32c(&bytes)
+}
+
+/// CRC-32C (Castagnoli), as used by iSCSI and ext4.
+pub fn crc32c(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x82f6_3b78
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    !data.iter().fold(!0, |crc, b| {
+        TABLE[((crc ^ u32::from(*b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+impl FileHeader {
+    pub fn new(n: u64, m: u64, k: u32) -> Self {
+        Self {
+            version: VERSION,
+            n,
+            m,
+            k,
+            flags: 0,
+            page_map: None,
+            page_checksums: None,
+            digest: None,
+            extensions: BTreeMap::new(),
+        }
+    }
+
+    /// Read and validate a full header page.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+        Self::from_bytes(&header[..])
+    }
+
+    /// Parse a header from the start of a buffer.
+    pub fn from_bytes(header: &[u8]) -> io::Result<Self> {
+        if header.len() < PAGE_MAP_OFFSET {
+            return Err(LoadError::TruncatedFile.into());
+        }
+
+        if &header[0..6] != MAGIC_PREFIX {
+            return Err(LoadError::BadMagic.into());
+        }
+
+        let version = std::str::from_utf8(&header[6..8])
+            .ok()
+            .and_then(|v| v.parse::<u8>().ok())
+            .ok_or_else(|| invalid("bad version"))?;
+
+        if version > MAX_VERSION {
+            return Err(LoadError::UnsupportedVersion(version).into());
+        }
+
+        let field =
+            |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+
+        let mut ret = Self {
+            version,
+            n: u64::from(field(N_OFFSET)),
+            m: u64::from(field(M_OFFSET)),
+            k: field(K_OFFSET),
+            flags: 0,
+            page_map: None,
+            page_checksums: None,
+            digest: None,
+            extensions: BTreeMap::new(),
+        };
+
+        if version >= 1 {
+            ret.flags = field(FLAGS_OFFSET);
+        }
+
+        if ret.flags & !KNOWN_FLAGS != 0 {
+            return Err(invalid("unsupported flags"));
+        }
+
+        if version >= 2 {
+            let len = header
+                .get(EXT_LEN_OFFSET..EXT_OFFSET)
+                .ok_or_else(|| invalid("truncated header"))?;
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            let mut area = header
+                .get(EXT_OFFSET..EXT_OFFSET + len)
+                .ok_or_else(|| invalid("truncated extensions"))?;
+
+            while !area.is_empty() {
+                if area.len() < 4 {
+                    return Err(invalid("truncated extension"));
+                }
+
+                let tag = u16::from_be_bytes([area[0], area[1]]);
+                let len = usize::from(u16::from_be_bytes([area[2], area[3]]));
+                let value = area
+                    .get(4..4 + len)
+                    .ok_or_else(|| invalid("truncated extension"))?;
+
+                if tag & EXT_CRITICAL != 0 && !SUPPORTED_CRITICAL.contains(&tag) {
+                    return Err(invalid("unsupported critical extension"));
+                }
+
+                ret.extensions.insert(tag, value.to_vec());
+                area = &area[4 + len..];
+            }
+
+            if let Some(sizes) = ret.extensions.remove(&EXT_SIZES) {
+                if sizes.len() != 16 {
+                    return Err(invalid("invalid sizes"));
+                }
+                ret.n = u64::from_be_bytes(sizes[..8].try_into().unwrap());
+                ret.m = u64::from_be_bytes(sizes[8..].try_into().unwrap());
+            }
+
+            if matches!(ret.extension(EXT_HASH_SEED), Some(key) if key.len() != 16) {
+                return Err(invalid("invalid hash key"));
+            }
+
+            if let Some(algorithm) = ret.extension(EXT_HASH_ALGORITHM) {
+                if !matches!(algorithm, [tag] if HashAlgorithm::from_tag(*tag).is_some()) {
+                    return Err(invalid("unknown hash algorithm"));
+                }
+            }
+
+            if let Some(size) = ret.extension(EXT_PAGE_SIZE) {
+                let size = size.try_into().map(u32::from_be_bytes);
+                if !matches!(size, Ok(size) if is_valid_page_size(size)) {
+                    return Err(invalid("invalid page size"));
+                }
+            }
+        }
+
+        if ret.is_sparse() {
+            let pages = ret.pages() as usize;
+            let offset = ret.page_map_offset();
+            let map = header
+                .get(offset..offset + pages.div_ceil(8))
+                .ok_or_else(|| invalid("truncated page map"))?;
+
+            ret.page_map = Some(
+                (0..pages)
+                    .map(|i| map[i / 8] & (0x80 >> (i % 8)) != 0)
+                    .collect(),
+            );
+        }
+
+        if ret.flags & FLAG_CHECKSUMS != 0 {
+            let offset = ret.checksums_offset();
+            let table = header
+                .get(offset..offset + ret.checksum_count() * 4)
+                .ok_or_else(|| invalid("truncated page checksums"))?;
+
+            ret.page_checksums = Some(
+                table
+                    .chunks(4)
+                    .map(|sum| u32::from_be_bytes(sum.try_into().unwrap()))
+                    .collect(),
+            );
+        }
+
+        if ret.flags & FLAG_DIGEST != 0 {
+            let digest = header
+                .get(DIGEST_OFFSET..DIGEST_OFFSET + 4)
+                .ok_or_else(|| invalid("truncated digest"))?;
+            ret.digest = Some(u32::from_be_bytes(digest.try_into().unwrap()));
+        }
+
+        Ok(ret)
+    }
+
+    /// Write a full, zero-padded header page.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; HEADER_SIZE];
+        header[0..6].copy_from_slice(MAGIC_PREFIX);
+        let version = self.written_version();
+        header[6..8].copy_from_slice(format!("{:02}", version).as_bytes());
+        let narrow = |size: u64| u32::try_from(size).unwrap_or(u32::MAX).to_be_bytes();
+        header[N_OFFSET..N_OFFSET + 4].copy_from_slice(&narrow(self.n));
+        header[M_OFFSET..M_OFFSET + 4].copy_from_slice(&narrow(self.m));
+        header[K_OFFSET..K_OFFSET + 4].copy_from_slice(&self.k.to_be_bytes());
+
+        if version >= 1 {
+            header[FLAGS_OFFSET..FLAGS_OFFSET + 4].copy_from_slice(&self.flags.to_be_bytes());
+        }
+
+        if version >= 2 {
+            let len = self.extensions_len();
+            if EXT_OFFSET + len > HEADER_SIZE {
+                return Err(invalid("extensions exceed header"));
+            }
+
+            header[EXT_LEN_OFFSET..EXT_OFFSET].copy_from_slice(&(len as u32).to_be_bytes());
+            let mut offset = EXT_OFFSET;
+            for (tag, value) in &self.extensions {
+                header[offset..offset + 2].copy_from_slice(&tag.to_be_bytes());
+                header[offset + 2..offset + 4].copy_from_slice(&(value.len() as u16).to_be_bytes());
+                header[offset + 4..offset + 4 + value.len()].copy_from_slice(value);
+                offset += 4 + value.len();
+            }
+
+            if let Some(sizes) = self.sizes_extension() {
+                header[offset..offset + 2].copy_from_slice(&EXT_SIZES.to_be_bytes());
+                header[offset + 2..offset + 4].copy_from_slice(&(sizes.len() as u16).to_be_bytes());
+                header[offset + 4..offset + 4 + sizes.len()].copy_from_slice(&sizes);
+            }
+        }
+
+        if let Some(map) = &self.page_map {
+            let offset = self.page_map_offset();
+            if offset + map.len().div_ceil(8) > HEADER_SIZE {
+                return Err(invalid("page map exceeds header"));
+            }
+
+            for (i, _) in map.iter().enumerate().filter(|(_, present)| **present) {
+                header[offset + i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+
+        if let Some(sums) = &self.page_checksums {
+            let offset = self.checksums_offset();
+            if offset + sums.len() * 4 > HEADER_SIZE {
+                return Err(invalid("page checksums exceed header"));
+            }
+
+            for (i, sum) in sums.iter().enumerate() {
+                header[offset + i * 4..offset + i * 4 + 4].copy_from_slice(&sum.to_be_bytes());
+            }
+        }
+
+        if let Some(digest) = self.digest {
+            if self.variable_len() > DIGEST_OFFSET {
+                return Err(invalid("digest overlaps header"));
+            }
+
+            header[DIGEST_OFFSET..].copy_from_slice(&digest.to_be_bytes());
+        }
+
+        writer.write_all(&header[..])
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        self.flags & FLAG_SPARSE != 0
+    }
+
+    /// Whether the body is the bit array of a plain `BloomFilter`.
+    pub fn is_plain(&self) -> bool {
+        self.flags & TYPE_FLAGS == 0
+    }
+
+    pub fn is_counting(&self) -> bool {
+        self.flags & FLAG_COUNTING != 0
+    }
+
+    pub fn is_cuckoo(&self) -> bool {
+        self.flags & FLAG_CUCKOO != 0
+    }
+
+    pub fn is_quotient(&self) -> bool {
+        self.flags & FLAG_QUOTIENT != 0
+    }
+
+    pub fn is_dleft(&self) -> bool {
+        self.flags & FLAG_DLEFT != 0
+    }
+
+    pub fn is_count_min(&self) -> bool {
+        self.flags & FLAG_COUNT_MIN != 0
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        self.flags & FLAG_PARTITIONED != 0
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.flags & FLAG_BLOCKED != 0
+    }
+
+    pub fn is_spectral(&self) -> bool {
+        self.flags & FLAG_SPECTRAL != 0
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    pub fn is_delta(&self) -> bool {
+        self.flags & FLAG_DELTA != 0
+    }
+
+    pub fn is_segmented(&self) -> bool {
+        self.flags & FLAG_SEGMENTED != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_checksummed(&self) -> bool {
+        self.flags & FLAG_CHECKSUMS != 0
+    }
+
+    /// The version `write` will use: this header's unless extensions need a
+    /// later one.
+    fn written_version(&self) -> u8 {
+        if self.extensions.is_empty() && self.sizes_extension().is_none() {
+            self.version
+        } else {
+            self.version.max(2)
+        }
+    }
+
+    fn extensions_len(&self) -> usize {
+        let sizes = self.sizes_extension().map_or(0, |sizes| 4 + sizes.len());
+        self.extensions
+            .values()
+            .map(|value| 4 + value.len())
+            .sum::<usize>()
+            + sizes
+    }
+
+    /// The EXT_SIZES value, if `n` or `m` is too large for its field.
+    fn sizes_extension(&self) -> Option<[u8; 16]> {
+        if self.n <= u64::from(u32::MAX) && self.m <= u64::from(u32::MAX) {
+            return None;
+        }
+
+        let mut sizes = [0; 16];
+        sizes[..8].copy_from_slice(&self.n.to_be_bytes());
+        sizes[8..].copy_from_slice(&self.m.to_be_bytes());
+        Some(sizes)
+    }
+
+    fn page_map_offset(&self) -> usize {
+        if self.written_version() >= 2 {
+            EXT_OFFSET + self.extensions_len()
+        } else {
+            PAGE_MAP_OFFSET
+        }
+    }
+
+    fn checksums_offset(&self) -> usize {
+        match &self.page_map {
+            Some(map) => self.page_map_offset() + map.len().div_ceil(8),
+            None => self.page_map_offset(),
+        }
+    }
+
+    pub fn extension(&self, tag: u16) -> Option<&[u8]> {
+        self.extensions.get(&tag).map(Vec::as_slice)
+    }
+
+    /// Add or replace an extension.
+    ///
+    /// Panics if the value exceeds the 64 KiB a tag can hold.
+    pub fn set_extension(&mut self, tag: u16, value: &[u8]) {
+        assert!(value.len() <= usize::from(u16::MAX));
+
+        self.extensions.insert(tag, value.to_vec());
+    }
+
+    pub fn remove_extension(&mut self, tag: u16) -> Option<Vec<u8>> {
+        self.extensions.remove(&tag)
+    }
+
+    /// Creation time in seconds since the Unix epoch, if recorded.
+    pub fn created(&self) -> Option<u64> {
+        self.extension(EXT_CREATED)
+            .and_then(|value| value.try_into().ok())
+            .map(u64::from_be_bytes)
+    }
+
+    pub fn set_created(&mut self, secs: u64) {
+        self.set_extension(EXT_CREATED, &secs.to_be_bytes());
+    }
+
+    /// Where and when the file was saved, if recorded.
+    pub fn provenance(&self) -> Option<Provenance> {
+        let value = self.extension(EXT_PROVENANCE)?;
+        let saved_at = u64::from_be_bytes(value.get(..8)?.try_into().ok()?);
+
+        let mut rest = &value[8..];
+        let mut string = || {
+            let len = usize::from(u16::from_be_bytes(rest.get(..2)?.try_into().ok()?));
+            let s = String::from_utf8(rest.get(2..2 + len)?.to_vec()).ok()?;
+            rest = &rest[2 + len..];
+            Some(s)
+        };
+
+        Some(Provenance {
+            saved_at,
+            crate_version: string()?,
+            hostname: string()?,
+            label: string()?,
+        })
+    }
+
+    /// Record `provenance`, truncating its strings to fit.
+    pub fn set_provenance(&mut self, provenance: &Provenance) {
+        let mut value = provenance.saved_at.to_be_bytes().to_vec();
+        for s in [
+            &provenance.crate_version,
+            &provenance.hostname,
+            &provenance.label,
+        ] {
+            let mut len = s.len().min(MAX_PROVENANCE_STRING);
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+            value.extend_from_slice(&(len as u16).to_be_bytes());
+            value.extend_from_slice(&s.as_bytes()[..len]);
+        }
+        self.set_extension(EXT_PROVENANCE, &value);
+    }
+
+    /// Generation of the save which wrote this header.  Of two files holding
+    /// the same filter, the one with the higher generation is newer.
+    pub fn generation(&self) -> u64 {
+        self.extension(EXT_GENERATION)
+            .and_then(|value| value.try_into().ok())
+            .map_or(0, u64::from_be_bytes)
+    }
+
+    pub fn set_generation(&mut self, generation: u64) {
+        if generation == 0 {
+            self.remove_extension(EXT_GENERATION);
+        } else {
+            self.set_extension(EXT_GENERATION, &generation.to_be_bytes());
+        }
+    }
+
+    /// Number of bits set in the body when it was saved, if recorded.
+    pub fn ones(&self) -> Option<u64> {
+        self.extension(EXT_ONES)
+            .and_then(|value| value.try_into().ok())
+            .map(u64::from_be_bytes)
+    }
+
+    pub fn set_ones(&mut self, ones: u64) {
+        self.set_extension(EXT_ONES, &ones.to_be_bytes());
+    }
+
+    /// Number of items inserted when the body was saved, if recorded.
+    pub fn count(&self) -> Option<u64> {
+        self.extension(EXT_COUNT)
+            .and_then(|value| value.try_into().ok())
+            .map(u64::from_be_bytes)
+    }
+
+    pub fn set_count(&mut self, count: u64) {
+        self.set_extension(EXT_COUNT, &count.to_be_bytes());
+    }
+
+    /// Size of the body's pages in bytes.
+    pub fn page_size(&self) -> u32 {
+        self.extension(EXT_PAGE_SIZE)
+            .and_then(|value| value.try_into().ok())
+            .map_or(PAGE_SIZE, u32::from_be_bytes)
+    }
+
+    /// Record a page size, which needs an extension unless it's the default.
+    ///
+    /// Panics if `size` isn't a valid page size.
+    pub fn set_page_size(&mut self, size: u32) {
+        assert!(is_valid_page_size(size), "invalid page size");
+
+        if size == PAGE_SIZE {
+            self.remove_extension(EXT_PAGE_SIZE);
+        } else {
+            self.set_extension(EXT_PAGE_SIZE, &size.to_be_bytes());
+        }
+    }
+
+    /// SipHash key items were hashed with.
+    pub fn hash_key(&self) -> (u64, u64) {
+        match self.extension(EXT_HASH_SEED) {
+            Some(key) if key.len() == 16 => (
+                u64::from_be_bytes(key[..8].try_into().unwrap()),
+                u64::from_be_bytes(key[8..].try_into().unwrap()),
+            ),
+            _ => (0, 0),
+        }
+    }
+
+    /// Record a hash key, which needs an extension unless it's the default.
+    pub fn set_hash_key(&mut self, (k0, k1): (u64, u64)) {
+        if (k0, k1) == (0, 0) {
+            self.remove_extension(EXT_HASH_SEED);
+        } else {
+            let mut key = [0; 16];
+            key[..8].copy_from_slice(&k0.to_be_bytes());
+            key[8..].copy_from_slice(&k1.to_be_bytes());
+            self.set_extension(EXT_HASH_SEED, &key);
+        }
+    }
+
+    /// Hash function items were hashed with.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.extension(EXT_HASH_ALGORITHM)
+            .and_then(|tag| HashAlgorithm::from_tag(*tag.first()?))
+            .unwrap_or_default()
+    }
+
+    /// Record a hash function, which needs an extension unless it's SipHash.
+    pub fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        if algorithm == HashAlgorithm::SipHash {
+            self.remove_extension(EXT_HASH_ALGORITHM);
+        } else {
+            self.set_extension(EXT_HASH_ALGORITHM, &[algorithm.tag()]);
+        }
+    }
+
+    /// Number of page checksums, counting any partial final page.
+    fn checksum_count(&self) -> usize {
+        self.dense_body_len().div_ceil(u64::from(self.page_size())) as usize
+    }
+
+    /// End of the page map and page checksums.
+    fn variable_len(&self) -> usize {
+        let sums = self
+            .page_checksums
+            .as_ref()
+            .map_or(0, |sums| sums.len() * 4);
+        self.checksums_offset() + sums
+    }
+
+    /// Checksum every page of a dense body, and digest the whole, as far as
+    /// they fit in the header; anything which doesn't is dropped.
+    pub fn set_checksums(&mut self, body: &[u8]) {
+        let sums = body.chunks(self.page_size() as usize).map(crc32c).collect();
+        self.set_page_checksums(sums);
+    }
+
+    /// Like `set_checksums`, given the checksum of every page rather than
+    /// the body itself.
+    pub fn set_page_checksums(&mut self, sums: Vec<u32>) {
+        let table_end = self.checksums_offset() + self.checksum_count() * 4;
+
+        self.flags &= !(FLAG_CHECKSUMS | FLAG_DIGEST);
+        self.page_checksums = None;
+        self.digest = None;
+
+        if self.checksums_offset() <= DIGEST_OFFSET {
+            self.flags |= FLAG_DIGEST;
+            self.digest = Some(digest_of(&sums));
+        }
+
+        if table_end <= DIGEST_OFFSET {
+            self.flags |= FLAG_CHECKSUMS;
+            self.page_checksums = Some(sums);
+        }
+    }
+
+    /// Recompute the checksums of the given pages of a dense body, and the
+    /// digest, if there are any.  Without page checksums the digest has to be
+    /// recomputed from the whole body.
+    ///
+    /// Every save which rewrites pages in place calls this, whatever the
+    /// header holds: a body too large for the checksum table still has a
+    /// digest, which would otherwise go stale.
+    pub fn update_checksums<I>(&mut self, body: &[u8], pages: I)
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let page_size = self.page_size() as usize;
+        match &mut self.page_checksums {
+            Some(sums) => {
+                for page in pages {
+                    let end = body.len().min((page + 1) * page_size);
+                    sums[page] = crc32c(&body[page * page_size..end]);
+                }
+
+                if self.digest.is_some() {
+                    self.digest = Some(digest_of(sums));
+                }
+            }
+            None if self.digest.is_some() => {
+                let sums: Vec<u32> = body.chunks(page_size).map(crc32c).collect();
+                self.digest = Some(digest_of(&sums));
+            }
+            None => {}
+        }
+    }
+
+    /// Record fresh checksums of individual pages, given as (page, CRC-32C)
+    /// pairs, without the rest of the body to hand.  Without page checksums
+    /// the digest can't be kept up to date this way, so it's dropped.
+    pub fn update_page_checksums<I>(&mut self, sums: I)
+    where
+        I: IntoIterator<Item = (usize, u32)>,
+    {
+        match &mut self.page_checksums {
+            Some(table) => {
+                for (page, sum) in sums {
+                    table[page] = sum;
+                }
+
+                if self.digest.is_some() {
+                    self.digest = Some(digest_of(table));
+                }
+            }
+            None => {
+                self.flags &= !FLAG_DIGEST;
+                self.digest = None;
+            }
+        }
+    }
+
+    /// This header without checksums or anything else particular to one
+    /// save, to compare with one describing the same filter with a different
+    /// body.
+    pub fn without_checksums(&self) -> Self {
+        let mut extensions = self.extensions.clone();
+        for tag in SAVE_EXTENSIONS {
+            extensions.remove(tag);
+        }
+
+        Self {
+            // a later version only because of extensions is no difference
+            version: self.version.min(VERSION),
+            flags: self.flags & !(FLAG_CHECKSUMS | FLAG_DIGEST),
+            page_checksums: None,
+            digest: None,
+            extensions,
+            ..self.clone()
+        }
+    }
+
+    /// Check a dense body, including pages a sparse file omitted, against any
+    /// page checksums and digest.
+    pub fn verify_body(&self, body: &[u8]) -> io::Result<()> {
+        if self.page_checksums.is_none() && self.digest.is_none() {
+            return Ok(());
+        }
+
+        self.verify_page_checksums(body.chunks(self.page_size() as usize).map(crc32c))
+    }
+
+    /// Check the CRC-32C of each page of the body, in order, against any page
+    /// checksums and digest.
+    pub fn verify_page_checksums<I>(&self, sums: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        let sums: Vec<u32> = sums.into_iter().collect();
+
+        if matches!(&self.page_checksums, Some(expected) if *expected != sums) {
+            return Err(LoadError::ChecksumMismatch.into());
+        }
+
+        if matches!(self.digest, Some(digest) if digest != digest_of(&sums)) {
+            return Err(LoadError::ChecksumMismatch.into());
+        }
+
+        Ok(())
+    }
+
+    /// Read the body following this header page by page, checking it against
+    /// the page checksums and digest without holding it all in memory.
+    pub fn verify_stream<R: Read>(&self, mut reader: R) -> io::Result<()> {
+        if self.page_checksums.is_none() && self.digest.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no checksums to verify",
+            ));
+        }
+
+        let page_size = self.page_size() as usize;
+        let body_len = self.dense_body_len() as usize;
+        let mut page = vec![0; page_size];
+        let mut sums = Vec::with_capacity(self.checksum_count());
+
+        for index in 0..self.checksum_count() {
+            let len = page_size.min(body_len - index * page_size);
+            let present = match &self.page_map {
+                Some(map) => map[index],
+                None => true,
+            };
+
+            if present {
+                reader.read_exact(&mut page[..len])?;
+            } else {
+                page[..len].fill(0);
+            }
+            sums.push(crc32c(&page[..len]));
+        }
+
+        self.verify_page_checksums(sums)
+    }
+
+    /// Number of pages in the filter body.
+    pub fn pages(&self) -> u64 {
+        self.dense_body_len() / u64::from(self.page_size())
+    }
+
+    /// Length of the body with every page present.
+    fn dense_body_len(&self) -> u64 {
+        let m = self.m;
+
+        if self.is_counting() {
+            m
+        } else if self.is_cuckoo() || self.is_spectral() || self.is_dleft() {
+            m * 2
+        } else if self.is_count_min() {
+            m * 4
+        } else if self.is_quotient() {
+            m * 8
+        } else {
+            m / 8
+        }
+    }
+
+    /// Length in bytes of the filter body following the header, or for a
+    /// compressed file its length once decompressed.
+    pub fn body_len(&self) -> u64 {
+        if self.is_encrypted() {
+            let tags = self.pages() * ENCRYPTION_TAG_LEN as u64;
+            return self.dense_body_len() + tags;
+        }
+
+        match &self.page_map {
+            Some(map) => map.iter().filter(|p| **p).count() as u64 * u64::from(self.page_size()),
+            None => self.dense_body_len(),
+        }
+    }
+
+    /// Expected total file length.
+    pub fn file_len(&self) -> u64 {
+        HEADER_SIZE as u64 + self.body_len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let header = FileHeader::new(1024, u64::from(PAGE_SIZE) * 8, 7);
+
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        assert_eq!(HEADER_SIZE, buf.len());
+        assert_eq!(b"BLOOMv01", &buf[0..8]);
+        assert_eq!(header, FileHeader::read(&buf[..]).unwrap());
+
+        buf[7] = b'0';
+        let v0 = FileHeader::read(&buf[..]).unwrap();
+        assert_eq!(0, v0.version);
+        assert_eq!((1024, u64::from(PAGE_SIZE) * 8, 7), (v0.n, v0.m, v0.k));
+
+        buf[0] = b'X';
+        assert!(FileHeader::read(&buf[..]).is_err());
+    }
+
+    #[test]
+    fn header_wide_sizes() {
+        let header = FileHeader::new(1 << 33, 1 << 38, 7);
+
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        assert_eq!(b"BLOOMv02", &buf[0..8]);
+        assert_eq!(&u32::MAX.to_be_bytes(), &buf[N_OFFSET..N_OFFSET + 4]);
+        assert_eq!(&u32::MAX.to_be_bytes(), &buf[M_OFFSET..M_OFFSET + 4]);
+
+        let read = FileHeader::read(&buf[..]).unwrap();
+        assert_eq!((1 << 33, 1 << 38, 7), (read.n, read.m, read.k));
+        assert_eq!(None, read.extension(EXT_SIZES));
+        assert_eq!(1 << 35, read.body_len());
+
+        // sizes which fit leave the header as it always was
+        let mut narrow = vec![];
+        FileHeader::new(1 << 20, 1 << 31, 7)
+            .write(&mut narrow)
+            .unwrap();
+        assert_eq!(b"BLOOMv01", &narrow[0..8]);
+    }
+
+    #[test]
+    fn header_extensions() {
+        let mut header = FileHeader::new(1024, u64::from(PAGE_SIZE) * 8 * 2, 7);
+        header.flags |= FLAG_SPARSE;
+        header.page_map = Some(vec![false, true]);
+        header.set_created(1_600_000_000);
+        header.set_extension(0x7123, b"future");
+
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        assert_eq!(b"BLOOMv02", &buf[0..8]);
+
+        let read = FileHeader::read(&buf[..]).unwrap();
+        assert_eq!(2, read.version);
+        assert_eq!(Some(1_600_000_000), read.created());
+        assert_eq!(Some(&b"future"[..]), read.extension(0x7123));
+        assert_eq!(Some(vec![false, true]), read.page_map);
+
+        // an unknown critical extension can't be skipped
+        header.set_extension(EXT_CRITICAL | 0x7123, b"");
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        assert!(FileHeader::read(&buf[..]).is_err());
+
+        // page sizes are understood, but must be sane
+        header.extensions.clear();
+        header.set_page_size(4096);
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        let read = FileHeader::read(&buf[..]).unwrap();
+        assert_eq!(4096, read.page_size());
+        assert_eq!(8, read.page_map.unwrap().len());
+
+        header.set_extension(EXT_PAGE_SIZE, &1000u32.to_be_bytes());
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        assert!(FileHeader::read(&buf[..]).is_err());
+
+        // without extensions, stay readable by older versions
+        header.extensions.clear();
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        assert_eq!(b"BLOOMv01", &buf[0..8]);
+    }
+
+    #[test]
+    fn header_checksums() {
+        assert_eq!(0xe306_9283, crc32c(b"123456789"));
+
+        let mut body = vec![0; PAGE_SIZE as usize * 4];
+        let mut header = FileHeader::new(1024, u64::from(PAGE_SIZE) * 32, 7);
+        header.set_checksums(&body);
+
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        let read = FileHeader::read(&buf[..]).unwrap();
+        assert_eq!(header, read);
+        assert!(read.is_checksummed());
+        read.verify_body(&body).unwrap();
+
+        body[PAGE_SIZE as usize * 2] = 1;
+        assert!(read.verify_body(&body).is_err());
+        assert!(read.verify_stream(&body[..]).is_err());
+        header.update_checksums(&body, vec![2]);
+        header.verify_body(&body).unwrap();
+        assert_eq!(
+            Some(digest_of(header.page_checksums.as_ref().unwrap())),
+            header.digest
+        );
+        assert_eq!(read.without_checksums(), header.without_checksums());
+
+        // too many pages for the header, but the digest still fits
+        let mut header = FileHeader::new(1024, u64::from(PAGE_SIZE) * 8 * 8192, 7);
+        header.set_checksums(&[]);
+        assert!(!header.is_checksummed());
+        assert_eq!(Some(digest_of(&[])), header.digest);
+    }
+
+    #[test]
+    fn header_page_map() {
+        let mut header = FileHeader::new(1024, u64::from(PAGE_SIZE) * 8 * 10, 7);
+        header.flags = FLAG_SPARSE;
+        header.page_map = Some((0..10).map(|i| i % 3 == 0).collect());
+
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+
+        let read = FileHeader::read(&buf[..]).unwrap();
+        assert_eq!(header, read);
+        assert_eq!(4 * u64::from(PAGE_SIZE), read.body_len());
+    }
+
+    #[test]
+    fn header_load_error() {
+        let mut buf = vec![];
+        FileHeader::new(1024, u64::from(PAGE_SIZE) * 8, 7)
+            .write(&mut buf)
+            .unwrap();
+
+        let mut bad = buf.clone();
+        bad[0] = b'X';
+        let err = FileHeader::read(&bad[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!("bad magic", err.to_string());
+        assert!(matches!(LoadError::from(err), LoadError::BadMagic));
+
+        let mut bad = buf.clone();
+        bad[6..8].copy_from_slice(b"99");
+        assert!(matches!(
+            LoadError::from(FileHeader::read(&bad[..]).unwrap_err()),
+            LoadError::UnsupportedVersion(99)
+        ));
+
+        let err = FileHeader::read(&buf[..100]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        assert!(matches!(LoadError::from(err), LoadError::TruncatedFile));
+        assert!(matches!(
+            LoadError::from(FileHeader::from_bytes(&buf[..8]).unwrap_err()),
+            LoadError::TruncatedFile
+        ));
+
+        let mut bad = buf.clone();
+        bad[FLAGS_OFFSET..FLAGS_OFFSET + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            LoadError::from(FileHeader::read(&bad[..]).unwrap_err()),
+            LoadError::InvalidHeader(_)
+        ));
+
+        let other = io::Error::new(io::ErrorKind::PermissionDenied, "nope");
+        assert!(matches!(LoadError::from(other), LoadError::Io(_)));
+    }
+}
@@ -0,0 +1,150 @@
+/// Offline consistency checks of saved filters, for health checks which want
+/// to know everything wrong with a file rather than the first error `load`
+/// hits.
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::format::{self, FileHeader};
+use crate::BloomFilter;
+
+/// What `BloomFilter::fsck` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckReport {
+    /// The header, if it could be read at all.
+    pub header: Option<FileHeader>,
+    /// Length of the file in bytes.
+    pub file_len: u64,
+    /// Whether the body was checked against page checksums or a digest.
+    pub checksums_verified: bool,
+    /// A description of each problem found.
+    pub problems: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl BloomFilter {
+    /// Check the file at `path` for problems, without loading it.
+    ///
+    /// Only failing to open the file is an error; anything wrong with its
+    /// contents is listed in the report.  The bodies of segmented filters
+    /// live elsewhere and aren't checked.
+    pub fn fsck<P: AsRef<Path>>(path: P) -> io::Result<FsckReport> {
+        let mut file = File::open(path.as_ref())?;
+        let mut report = FsckReport {
+            header: None,
+            file_len: file.metadata()?.len(),
+            checksums_verified: false,
+            problems: vec![],
+        };
+
+        let header = match FileHeader::read(&mut file) {
+            Ok(header) => header,
+            Err(e) => {
+                report.problems.push(format!("unreadable header: {}", e));
+                return Ok(report);
+            }
+        };
+
+        let problems = &mut report.problems;
+        if header.is_plain() {
+            let page_bits = u64::from(header.page_size()) * 8;
+            if header.m == 0 || header.m % page_bits != 0 {
+                problems.push(format!(
+                    "m of {} bits is not a whole number of {} byte pages",
+                    header.m,
+                    header.page_size()
+                ));
+            }
+            if header.k == 0 || u64::from(header.k) > header.m {
+                problems.push(format!("implausible k of {}", header.k));
+            }
+            if header.n == 0 {
+                problems.push("zero capacity".to_string());
+            }
+            if header.is_partitioned() && header.is_blocked() {
+                problems.push("conflicting layout flags".to_string());
+            }
+        }
+
+        // segmented bodies are elsewhere, and compressed ones of any length
+        let sized = !header.is_segmented() && !header.is_compressed();
+        if sized {
+            let expected = header.file_len();
+            if report.file_len < expected {
+                problems.push(format!(
+                    "truncated: expected {} bytes, found {}",
+                    expected, report.file_len
+                ));
+            } else if report.file_len > expected {
+                problems.push(format!(
+                    "{} bytes of trailing data",
+                    report.file_len - expected
+                ));
+            }
+        }
+
+        let checksummed = header.page_checksums.is_some() || header.digest.is_some();
+        let checkable = !header.is_segmented() && !header.is_delta();
+        if checksummed && checkable && report.file_len >= format::HEADER_SIZE as u64 {
+            match BloomFilter::verify(path.as_ref()) {
+                Ok(()) => report.checksums_verified = true,
+                // a short body has already been reported
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && sized => {}
+                Err(e) => problems.push(format!("checksums: {}", e)),
+            }
+        }
+
+        report.header = Some(header);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_fsck() {
+        let path = "test_fsck.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        let report = BloomFilter::fsck(path).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert!(report.checksums_verified);
+
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(format::HEADER_SIZE as u64 + 7))
+            .unwrap();
+        file.write_all(&[0x55]).unwrap();
+        let report = BloomFilter::fsck(path).unwrap();
+        assert_eq!(1, report.problems.len());
+        assert!(report.problems[0].starts_with("checksums"));
+
+        file.set_len(format::HEADER_SIZE as u64 + 100).unwrap();
+        let report = BloomFilter::fsck(path).unwrap();
+        assert_eq!(1, report.problems.len());
+        assert!(report.problems[0].starts_with("truncated"));
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"XXXX").unwrap();
+        let report = BloomFilter::fsck(path).unwrap();
+        assert!(report.header.is_none());
+        assert!(!report.is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
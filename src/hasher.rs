@@ -0,0 +1,419 @@
+/// Pluggable 128-bit hashing of items into `BloomHash`es.
+///
+/// Filters only ever see `BloomHash`es, so any `BuildHasher128` can hash
+/// items for one: pass the result of `hash_one` to `insert` or `contains`,
+/// or give the hasher to `BloomFilter::with_hasher` to have it rehash every
+/// item.  The filter itself only records a SipHash key, so a file saved from
+/// a filter filled through another hasher must be queried through the same
+/// one.  `SipHash128` is the default, and what `BloomHash::from` uses.
+///
+/// A `BloomFilter` built with its own key or `HashAlgorithm` rehashes every
+/// `BloomHash` given to `insert` and `contains` with them, as `rehash`
+/// does, and its `hash` does the same to `BloomHash::from`.  The key and
+/// algorithm are recorded when it's saved.  Besides SipHash, xxh3 and wyhash
+/// are available with the "xxh3" and "wyhash" features.  Both are seeded
+/// from the filter's key, but neither is designed to resist attack.
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use siphasher::sip128::{self, SipHasher, SipHasher13, SipHasher24};
+
+use crate::BloomHash;
+
+/// A `Hasher` which can finish with the 128 bits a `BloomHash` is made
+/// from.
+pub trait Hasher128: Hasher {
+    fn finish128(&self) -> u128;
+}
+
+/// Builds `Hasher128`s, as `BuildHasher` builds `Hasher`s.
+pub trait BuildHasher128 {
+    type Hasher: Hasher128;
+
+    fn build_hasher(&self) -> Self::Hasher;
+
+    /// Hash a single item.
+    fn hash_one<T: Hash>(&self, item: T) -> BloomHash {
+        let mut hasher = self.build_hasher();
+        item.hash(&mut hasher);
+        BloomHash::from_u128(hasher.finish128())
+    }
+
+    /// Hash a canonical byte representation with no additional framing.
+    fn hash_canonical(&self, bytes: &[u8]) -> BloomHash {
+        let mut hasher = self.build_hasher();
+        hasher.write(bytes);
+        BloomHash::from_u128(hasher.finish128())
+    }
+
+    /// Hash a `BloomHash`, such as one from `BloomHash::from`, into one of
+    /// this hasher's own.
+    fn rehash(&self, hash: BloomHash) -> BloomHash {
+        self.hash_canonical(&u128::from(hash).to_be_bytes())
+    }
+}
+
+/// A `BuildHasher128` a filter was given in place of its `HashAlgorithm`,
+/// kept as its `rehash` since the trait can't be made into an object.
+#[derive(Clone)]
+pub(crate) struct CustomHasher(Arc<dyn Fn(BloomHash) -> BloomHash + Send + Sync>);
+
+impl CustomHasher {
+    pub(crate) fn new<B: BuildHasher128 + Send + Sync + 'static>(hasher: B) -> Self {
+        Self(Arc::new(move |hash| hasher.rehash(hash)))
+    }
+
+    pub(crate) fn rehash(&self, hash: BloomHash) -> BloomHash {
+        (self.0)(hash)
+    }
+}
+
+impl fmt::Debug for CustomHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CustomHasher")
+    }
+}
+
+macro_rules! sip_hasher128 {
+    ($($hasher:ty),*) => {$(
+        impl Hasher128 for $hasher {
+            fn finish128(&self) -> u128 {
+                let hash = sip128::Hasher128::finish128(self);
+                (u128::from(hash.h1) << 64) | u128::from(hash.h2)
+            }
+        }
+    )*};
+}
+
+sip_hasher128!(SipHasher, SipHasher13, SipHasher24);
+
+/// 128-bit SipHash-2-4 with a key, by default zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SipHash128 {
+    key: (u64, u64),
+}
+
+impl SipHash128 {
+    pub fn new(k0: u64, k1: u64) -> Self {
+        Self { key: (k0, k1) }
+    }
+
+    pub fn key(&self) -> (u64, u64) {
+        self.key
+    }
+}
+
+impl BuildHasher128 for SipHash128 {
+    type Hasher = SipHasher;
+
+    fn build_hasher(&self) -> SipHasher {
+        SipHasher::new_with_keys(self.key.0, self.key.1)
+    }
+
+    /// With the default key, hashes from `BloomHash::from` are already this
+    /// hasher's own, and are left as they are.
+    fn rehash(&self, hash: BloomHash) -> BloomHash {
+        if self.key == (0, 0) {
+            hash
+        } else {
+            self.hash_canonical(&u128::from(hash).to_be_bytes())
+        }
+    }
+}
+
+/// 128-bit xxh3, seeded with a 64-bit seed.
+#[cfg(feature = "xxh3")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Xxh3Hash128 {
+    seed: u64,
+}
+
+#[cfg(feature = "xxh3")]
+impl Xxh3Hash128 {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+#[cfg(feature = "xxh3")]
+impl Hasher128 for xxhash_rust::xxh3::Xxh3 {
+    fn finish128(&self) -> u128 {
+        self.digest128()
+    }
+}
+
+#[cfg(feature = "xxh3")]
+impl BuildHasher128 for Xxh3Hash128 {
+    type Hasher = xxhash_rust::xxh3::Xxh3;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        xxhash_rust::xxh3::Xxh3::with_seed(self.seed)
+    }
+}
+
+/// wyhash, which only produces 64 bits, run twice with different seeds.
+#[cfg(feature = "wyhash")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WyHash128 {
+    seeds: (u64, u64),
+}
+
+#[cfg(feature = "wyhash")]
+impl WyHash128 {
+    pub fn new(k0: u64, k1: u64) -> Self {
+        // the halves must differ even for the default key of zero
+        Self {
+            seeds: (k0, k1 ^ 0x9e37_79b9_7f4a_7c15),
+        }
+    }
+}
+
+/// A pair of wyhashes fed the same input.
+#[cfg(feature = "wyhash")]
+#[derive(Clone)]
+pub struct WyHasher128(wyhash::WyHash, wyhash::WyHash);
+
+#[cfg(feature = "wyhash")]
+impl Hasher for WyHasher128 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+        self.1.write(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+}
+
+#[cfg(feature = "wyhash")]
+impl Hasher128 for WyHasher128 {
+    fn finish128(&self) -> u128 {
+        (u128::from(self.0.finish()) << 64) | u128::from(self.1.finish())
+    }
+}
+
+#[cfg(feature = "wyhash")]
+impl BuildHasher128 for WyHash128 {
+    type Hasher = WyHasher128;
+
+    fn build_hasher(&self) -> WyHasher128 {
+        WyHasher128(
+            wyhash::WyHash::with_seed(self.seeds.0),
+            wyhash::WyHash::with_seed(self.seeds.1),
+        )
+    }
+}
+
+/// The hash function a `BloomFilter` hashes items with.  Every algorithm
+/// can be named, so files using one can be described, but only those whose
+/// feature is enabled can be used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashAlgorithm {
+    /// 128-bit SipHash-2-4, keyed with the filter's key.
+    #[default]
+    SipHash,
+    /// 128-bit xxh3, seeded with the halves of the key xor'd together.
+    Xxh3,
+    /// Two 64-bit wyhashes, seeded from each half of the key.
+    WyHash,
+}
+
+impl HashAlgorithm {
+    /// Whether this build can hash with the algorithm.
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::SipHash => true,
+            Self::Xxh3 => cfg!(feature = "xxh3"),
+            Self::WyHash => cfg!(feature = "wyhash"),
+        }
+    }
+
+    /// The algorithm's number in `format::EXT_HASH_ALGORITHM`.
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Self::SipHash => 0,
+            Self::Xxh3 => 1,
+            Self::WyHash => 2,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::SipHash),
+            1 => Some(Self::Xxh3),
+            2 => Some(Self::WyHash),
+            _ => None,
+        }
+    }
+
+    /// Rehash a `BloomHash` with the algorithm and `key`, which leaves it
+    /// as it is for SipHash with the default key.
+    ///
+    /// Panics if the algorithm isn't available, which params refuse.
+    pub(crate) fn rehash(self, (k0, k1): (u64, u64), hash: BloomHash) -> BloomHash {
+        match self {
+            Self::SipHash => SipHash128::new(k0, k1).rehash(hash),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => Xxh3Hash128::new(k0 ^ k1).rehash(hash),
+            #[cfg(feature = "wyhash")]
+            Self::WyHash => WyHash128::new(k0, k1).rehash(hash),
+            #[allow(unreachable_patterns)]
+            _ => panic!("{} is not available in this build", self),
+        }
+    }
+
+    /// Hash canonical bytes with the algorithm and `key`.
+    pub(crate) fn hash_canonical(self, (k0, k1): (u64, u64), bytes: &[u8]) -> BloomHash {
+        match self {
+            Self::SipHash => SipHash128::new(k0, k1).hash_canonical(bytes),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => Xxh3Hash128::new(k0 ^ k1).hash_canonical(bytes),
+            #[cfg(feature = "wyhash")]
+            Self::WyHash => WyHash128::new(k0, k1).hash_canonical(bytes),
+            #[allow(unreachable_patterns)]
+            _ => panic!("{} is not available in this build", self),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::SipHash => "SipHash",
+            Self::Xxh3 => "xxh3",
+            Self::WyHash => "wyhash",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BloomFilter;
+
+    /// FNV-1a, widened to 128 bits.
+    #[derive(Default)]
+    struct Fnv128(u128);
+
+    impl Hasher for Fnv128 {
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 ^= u128::from(*byte);
+                self.0 = self.0.wrapping_mul(0x0000000001000000000000000000013b);
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0 as u64
+        }
+    }
+
+    impl Hasher128 for Fnv128 {
+        fn finish128(&self) -> u128 {
+            self.0
+        }
+    }
+
+    struct BuildFnv128;
+
+    impl BuildHasher128 for BuildFnv128 {
+        type Hasher = Fnv128;
+
+        fn build_hasher(&self) -> Fnv128 {
+            Fnv128(0x6c62272e07bb014262b821756295c58d)
+        }
+    }
+
+    #[test]
+    fn hasher_pluggable() {
+        assert_eq!(
+            BloomHash::from("meep"),
+            SipHash128::default().hash_one("meep")
+        );
+
+        let bf = BloomFilter::with_capacity_p(100, 0.01);
+        assert_eq!(bf.hash("meep"), SipHash128::new(0, 0).hash_one("meep"));
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        for i in 0..100u32 {
+            bf.insert(BuildFnv128.hash_one(i));
+        }
+        assert!((0..100u32).all(|i| bf.contains(BuildFnv128.hash_one(i))));
+        assert_ne!(BuildFnv128.hash_one(1u32), BloomHash::from(1u32));
+    }
+
+    #[test]
+    fn hasher_custom() {
+        let path = "test_hash_custom.bf";
+        let _ = std::fs::remove_file(path);
+
+        let params = crate::BloomFilterParams::with_capacity_p(1000, 0.01);
+        let mut bf = BloomFilter::with_hasher(params, BuildFnv128);
+        assert_eq!(bf.hash(1u32), BuildFnv128.rehash(BloomHash::from(1u32)));
+        for i in 0..100u32 {
+            bf.insert(i);
+        }
+        assert!((0..100u32).all(|i| bf.contains(i)));
+        assert!((0..100u32).all(|i| bf.contains_hash(&bf.hash(i))));
+        assert!(bf.snapshot().contains(1u32));
+        bf.save(path).unwrap();
+
+        // the hasher isn't saved, so has to be given again
+        let mut loaded = BloomFilter::load(path).unwrap();
+        assert!(!(0..100u32).all(|i| loaded.contains(i)));
+        loaded.set_hasher(BuildFnv128);
+        assert!((0..100u32).all(|i| loaded.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hasher_algorithms() {
+        let path = "test_hash_algorithm.bf";
+        let _ = std::fs::remove_file(path);
+
+        for algorithm in [
+            HashAlgorithm::SipHash,
+            HashAlgorithm::Xxh3,
+            HashAlgorithm::WyHash,
+        ] {
+            let params = crate::BloomFilterParamsBuilder::default()
+                .capacity(1000)
+                .false_positives(0.01)
+                .hash_algorithm(algorithm)
+                .to_params();
+            if !algorithm.is_available() {
+                assert_eq!(Err(crate::ParamsError::HashAlgorithmUnavailable), params);
+                continue;
+            }
+
+            let mut bf = BloomFilter::from_params(params.unwrap());
+            bf.extend(0..100u32);
+            assert!(bf.insert(100u32));
+            bf.insert_hash(&bf.hash_canonical(b"meep"));
+            assert!((0..=100u32).all(|i| bf.contains(i)));
+            assert_eq!(algorithm, bf.hash_algorithm());
+            assert_eq!(
+                algorithm == HashAlgorithm::SipHash,
+                bf.hash(1u32) == BloomHash::from(1u32)
+            );
+            bf.save(path).unwrap();
+
+            let loaded = BloomFilter::load(path).unwrap();
+            assert_eq!(algorithm, loaded.hash_algorithm());
+            assert!((0..=100u32).all(|i| loaded.contains(i)));
+            assert!(loaded.contains_hash(&loaded.hash_canonical(b"meep")));
+            assert!((0..=100u32).all(|i| loaded.contains_hash(&loaded.hash(i))));
+
+            // other readers would hash items with SipHash
+            assert_eq!(
+                algorithm == HashAlgorithm::SipHash,
+                BloomFilter::view(std::fs::read(path).unwrap()).is_ok()
+            );
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+/// HyperLogLog cardinality sketch, after Flajolet et al.
+///
+/// Estimates the number of distinct items seen, using 2^precision registers
+/// with a standard error of about 1.04 / sqrt(2^precision).  Items are hashed
+/// with `BloomHash`, so the same values can be fed to a filter and a sketch.
+/// Sketches of equal precision merge losslessly into the sketch of the
+/// combined streams.
+///
+/// Registers never exceed 6 bits, so saved files pack four of them into
+/// every three bytes after a short header:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "HYLOGv00"
+/// 8       1     precision
+/// 9       7     zero padding
+/// 16      ...   3 * 2^precision / 4 bytes of packed registers
+/// ```
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::BloomHash;
+
+const HLL_MAGIC: &[u8; 8] = b"HYLOGv00";
+const HLL_HEADER_SIZE: usize = 16;
+
+pub const HLL_MIN_PRECISION: u8 = 4;
+pub const HLL_MAX_PRECISION: u8 = 18;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// A sketch of 2^precision registers.
+    ///
+    /// Panics unless `precision` is between `HLL_MIN_PRECISION` and
+    /// `HLL_MAX_PRECISION`.
+    pub fn new(precision: u8) -> Self {
+        assert!((HLL_MIN_PRECISION..=HLL_MAX_PRECISION).contains(&precision));
+
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// The smallest sketch with a standard error of at most `error`.
+    pub fn with_error(error: f64) -> Self {
+        assert!(error > 0.0 && error < 1.0);
+
+        let precision = (1.04 / error).powi(2).log2().ceil() as u8;
+        Self::new(precision.clamp(HLL_MIN_PRECISION, HLL_MAX_PRECISION))
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// Expected relative standard error of estimates.
+    pub fn error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) {
+        self.insert_hash(&item.into());
+    }
+
+    pub fn insert_hash(&mut self, hash: &BloomHash) {
+        let p = u32::from(self.precision);
+        let index = (hash.h1 >> (64 - p)) as usize;
+        // the sentinel bit bounds the rank when the remaining bits are zero
+        let rank = ((hash.h1 << p) | (1 << (p - 1))).leading_zeros() + 1;
+
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank as u8);
+    }
+
+    /// Estimated number of distinct items inserted.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2.0_f64.powi(-i32::from(*r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        // linear counting is more accurate while many registers are empty
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// Whether `other` has the same precision, and so can be merged.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.precision == other.precision
+    }
+
+    /// Merge `other` into this sketch, which then estimates the distinct
+    /// items of both.
+    ///
+    /// Panics if the sketches are not compatible.
+    pub fn merge(&mut self, other: &Self) {
+        assert!(self.is_compatible(other), "incompatible sketch precision");
+
+        for (a, b) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *a = (*a).max(*b);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.iter().all(|r| *r == 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.registers.fill(0);
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; HLL_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != HLL_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let precision = header[8];
+        if !(HLL_MIN_PRECISION..=HLL_MAX_PRECISION).contains(&precision) {
+            return Err(invalid_data("invalid precision"));
+        }
+
+        let mut ret = Self::new(precision);
+        let mut packed = vec![0; ret.registers.len() / 4 * 3];
+        reader.read_exact(&mut packed[..])?;
+
+        for (registers, bytes) in ret.registers.chunks_mut(4).zip(packed.chunks(3)) {
+            let word = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for (i, register) in registers.iter_mut().enumerate() {
+                *register = (word >> (18 - 6 * i) & 0x3f) as u8;
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; HLL_HEADER_SIZE];
+        header[0..8].copy_from_slice(HLL_MAGIC);
+        header[8] = self.precision;
+
+        let mut packed = Vec::with_capacity(self.registers.len() / 4 * 3);
+        for registers in self.registers.chunks(4) {
+            let word = registers
+                .iter()
+                .fold(0u32, |word, r| (word << 6) | u32::from(*r));
+            packed.extend_from_slice(&word.to_be_bytes()[1..]);
+        }
+
+        writer.write_all(&header[..])?;
+        writer.write_all(&packed[..])
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        self.write_to(&mut file)?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyperloglog_estimate_merge_save_load() {
+        let path = "test_hyperloglog.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut a = HyperLogLog::new(12);
+        let mut b = HyperLogLog::new(12);
+        assert_eq!(0.0, a.estimate());
+
+        for i in 0..100 {
+            a.insert(i);
+        }
+        assert!((a.estimate() - 100.0).abs() < 5.0);
+
+        for i in 0..100_000 {
+            a.insert(i);
+            b.insert(i + 50_000);
+        }
+
+        let within = |hll: &HyperLogLog, n: f64| (hll.estimate() - n).abs() < n * hll.error() * 3.0;
+        assert!(within(&a, 100_000.0));
+
+        a.merge(&b);
+        assert!(within(&a, 150_000.0));
+
+        a.save(path).unwrap();
+        let loaded = HyperLogLog::load(path).unwrap();
+        assert_eq!(a, loaded);
+        assert_eq!(16 + 3 * 1024, std::fs::metadata(path).unwrap().len());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+/// Inverse bloom filter, with false negatives but no false positives.
+///
+/// A fixed-size array of slots, each holding the fingerprint of the last item
+/// hashed to it.  An item tests positive only if its own fingerprint is still
+/// in its slot, so "seen" answers are exact, barring a collision of the full
+/// 128-bit `BloomHash`, while items overwritten by later arrivals are
+/// forgotten.  This suits suppressing duplicates where repeating something is
+/// harmless but dropping something new is not.
+///
+/// Slots are atomics and every operation takes `&self`, so one filter may be
+/// shared between threads without locking.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::BloomHash;
+
+#[derive(Debug)]
+pub struct InverseBloomFilter {
+    slots: Vec<AtomicU64>,
+}
+
+impl InverseBloomFilter {
+    pub fn new(slots: usize) -> Self {
+        assert!(slots > 0);
+
+        Self {
+            slots: (0..slots).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn slot(&self, hash: &BloomHash) -> &AtomicU64 {
+        &self.slots[(hash.h1 % self.slots.len() as u64) as usize]
+    }
+
+    fn fingerprint(hash: &BloomHash) -> u64 {
+        // zero marks an empty slot
+        match hash.h2 {
+            0 => 1,
+            fp => fp,
+        }
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.slot(hash).load(Ordering::Relaxed) == Self::fingerprint(hash)
+    }
+
+    /// Insert an item, returning `true` if it was not already present.
+    ///
+    /// The check and the insert happen atomically, so of several threads
+    /// inserting the same new item at once, exactly one sees `true`.
+    pub fn insert<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&self, hash: &BloomHash) -> bool {
+        let fp = Self::fingerprint(hash);
+
+        self.slot(hash).swap(fp, Ordering::Relaxed) != fp
+    }
+
+    /// Number of slots, and so the most items which can be remembered at once.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Fraction of slots which are occupied.
+    pub fn fill_ratio(&self) -> f64 {
+        self.slots
+            .iter()
+            .filter(|slot| slot.load(Ordering::Relaxed) != 0)
+            .count() as f64
+            / self.slots.len() as f64
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_no_false_positives() {
+        let ibf = InverseBloomFilter::new(1000);
+
+        for i in 0..10_000 {
+            assert!(ibf.insert(i));
+        }
+
+        // never a false positive, but most earlier items are forgotten
+        assert!((10_000..20_000).all(|i| !ibf.contains(i)));
+        let remembered = (0..10_000).filter(|i| ibf.contains(*i)).count();
+        assert!(remembered <= 1000 && remembered > 500);
+
+        assert!(ibf.contains(9999));
+        assert!(!ibf.insert(9999));
+        assert!(ibf.contains_hash(&BloomHash::from(9999)));
+        assert!(!ibf.insert_hash(&BloomHash::from(9999)));
+
+        ibf.clear();
+        assert!(!ibf.contains(9999));
+    }
+
+    #[test]
+    fn inverse_shared_between_threads() {
+        let ibf = std::sync::Arc::new(InverseBloomFilter::new(1 << 16));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let ibf = ibf.clone();
+                std::thread::spawn(move || (0..1000).filter(|i| ibf.insert(*i)).count())
+            })
+            .collect();
+
+        let new: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert!(new >= 1000);
+        assert!((0..1000).filter(|i| ibf.contains(*i)).count() > 980);
+    }
+}
@@ -0,0 +1,364 @@
+/// Filters whose pages are read from their file only when first touched,
+/// for filters much larger than memory or of which only a little is used.
+///
+/// Opening a `LazyBloomFilter` reads just the header.  Each query or insert
+/// reads in the pages its probes fall in, one with `Layout::Paged`, checking
+/// them against the header's page checksums where there are any, and keeps
+/// them, up to an optional memory budget beyond which the least recently
+/// used pages are dropped, written back first if changed.  `save` writes
+/// back the rest of those changed since the last save; until then, pages
+/// written back early may not match the file's checksums.
+///
+/// Only dense files of plain filters can be read lazily, as sparse ones have
+/// no fixed place for each page.
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader, LoadError};
+use crate::paged::{self, invalid_data};
+use crate::{
+    check_plain_hashing, estimate_count, header_params, lsb_first, probe_bits, set_all,
+    BloomFilter, BloomFilterParams, BloomHash,
+};
+
+#[derive(Debug)]
+pub struct LazyBloomFilter {
+    params: BloomFilterParams,
+    pages: u64,
+    count: u64,
+    lsb_first: bool,
+    file: File,
+    header: FileHeader,
+    /// Each page of the body, once read.
+    loaded: Vec<Option<Box<[u8]>>>,
+    /// Number of pages held in `loaded`.
+    resident: usize,
+    /// Bytes of pages to hold at most, if limited.
+    budget: Option<usize>,
+    tick: u64,
+    /// When each page was last used, or zero if it isn't held.
+    used: Vec<u64>,
+    /// Held pages by when they were last used, oldest first.
+    recency: BTreeMap<u64, usize>,
+    dirty: BitVec,
+}
+
+impl BloomFilter {
+    /// Open the filter saved at `path`, reading its pages as they're used.
+    pub fn open_lazy<P: AsRef<Path>>(path: P) -> io::Result<LazyBloomFilter> {
+        LazyBloomFilter::open(path)
+    }
+}
+
+impl LazyBloomFilter {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let header = FileHeader::read(&mut file)?;
+        if header.is_sparse()
+            || header.is_compressed()
+            || header.is_segmented()
+            || header.is_encrypted()
+        {
+            return Err(invalid_data("only dense filters can be read lazily"));
+        }
+
+        let params = header_params(&header)?;
+        check_plain_hashing(&params)?;
+        if file.metadata()?.len() < header.file_len() {
+            return Err(invalid_data("truncated body"));
+        }
+
+        let pages = params.m / (u64::from(params.page_size) * 8);
+        // without a record of the count or bits set, count from zero
+        let count = match header.count() {
+            Some(count) => count,
+            None => header
+                .ones()
+                .map_or(0, |ones| estimate_count(&params, ones)),
+        };
+
+        Ok(Self {
+            params,
+            pages,
+            count,
+            lsb_first: lsb_first(),
+            file,
+            header,
+            loaded: (0..pages).map(|_| None).collect(),
+            resident: 0,
+            budget: None,
+            tick: 0,
+            used: vec![0; pages as usize],
+            recency: BTreeMap::new(),
+            dirty: BitVec::from_elem(pages as usize, false),
+        })
+    }
+
+    pub fn params(&self) -> &BloomFilterParams {
+        &self.params
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    /// Hold at most `bytes` of pages in memory, or with `None` keep every
+    /// page once read.  The page in use is always held, however small the
+    /// budget.
+    pub fn set_memory_budget(&mut self, bytes: Option<usize>) -> io::Result<()> {
+        self.budget = bytes;
+        self.evict(None)
+    }
+
+    /// The page `index`, reading it in if this is its first use.
+    fn page(&mut self, index: usize) -> io::Result<&mut [u8]> {
+        if self.loaded[index].is_none() {
+            let page_size = self.params.page_size as usize;
+            let mut page = vec![0; page_size].into_boxed_slice();
+            self.file.seek(io::SeekFrom::Start(
+                (format::HEADER_SIZE + index * page_size) as u64,
+            ))?;
+            self.file.read_exact(&mut page)?;
+
+            if let Some(sums) = &self.header.page_checksums {
+                if format::crc32c(&page) != sums[index] {
+                    return Err(LoadError::ChecksumMismatch.into());
+                }
+            }
+
+            self.loaded[index] = Some(page);
+            self.resident += 1;
+            self.touch(index);
+            self.evict(Some(index))?;
+        } else {
+            self.touch(index);
+        }
+
+        Ok(self.loaded[index].as_deref_mut().unwrap())
+    }
+
+    fn touch(&mut self, index: usize) {
+        self.tick += 1;
+        self.recency.remove(&self.used[index]);
+        self.used[index] = self.tick;
+        self.recency.insert(self.tick, index);
+    }
+
+    /// Drop the least recently used pages other than `keep` until those
+    /// held fit the budget.
+    fn evict(&mut self, keep: Option<usize>) -> io::Result<()> {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+
+        let page_size = self.params.page_size as usize;
+        while self.resident * page_size > budget {
+            let (tick, victim) = match self
+                .recency
+                .iter()
+                .map(|(tick, index)| (*tick, *index))
+                .find(|(_, index)| Some(*index) != keep)
+            {
+                Some(oldest) => oldest,
+                None => break,
+            };
+
+            if self.dirty.get(victim).expect("within bounds") {
+                self.write_back(victim)?;
+                self.dirty.set(victim, false);
+            }
+
+            self.recency.remove(&tick);
+            self.used[victim] = 0;
+            self.loaded[victim] = None;
+            self.resident -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Write a held page to the file, recording its checksum for the next
+    /// header written.
+    fn write_back(&mut self, index: usize) -> io::Result<()> {
+        let page_size = self.params.page_size as usize;
+        let page = self.loaded[index].as_deref().unwrap();
+        self.file.seek(io::SeekFrom::Start(
+            (format::HEADER_SIZE + index * page_size) as u64,
+        ))?;
+        self.file.write_all(page)?;
+        self.header
+            .update_page_checksums(Some((index, format::crc32c(page))));
+        Ok(())
+    }
+
+    /// The page, byte within it, and mask of a bit of the body.
+    fn position(&self, bit: u64) -> (usize, usize, u8) {
+        let page_bits = u64::from(self.params.page_size) * 8;
+        let shift = (bit % 8) as u8;
+        let mask = if self.lsb_first {
+            1 << shift
+        } else {
+            0x80 >> shift
+        };
+        (
+            (bit / page_bits) as usize,
+            (bit % page_bits / 8) as usize,
+            mask,
+        )
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&mut self, item: T) -> io::Result<bool> {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&mut self, hash: &BloomHash) -> io::Result<bool> {
+        for bit in probe_bits(&self.params, self.pages, *hash, 0) {
+            let (page, byte, mask) = self.position(bit);
+            if self.page(page)?[byte] & mask == 0 {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> io::Result<bool> {
+        self.insert_hash(&item.into())
+    }
+
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> io::Result<bool> {
+        let mut added = false;
+
+        let bits: Vec<u64> = probe_bits(&self.params, self.pages, *hash, 0).collect();
+        for bit in bits {
+            let (page, byte, mask) = self.position(bit);
+            let body = self.page(page)?;
+            if body[byte] & mask == 0 {
+                body[byte] |= mask;
+                self.dirty.set(page, true);
+                added = true;
+            }
+        }
+
+        if added {
+            self.count += 1;
+        }
+
+        Ok(added)
+    }
+
+    /// Write pages changed since the last save back to the file, along with
+    /// their checksums.
+    pub fn save(&mut self) -> io::Result<()> {
+        for index in paged::dirty_pages(&self.dirty) {
+            self.write_back(index)?;
+        }
+
+        // the bits set can't be counted without reading every page
+        self.header.set_generation(self.header.generation() + 1);
+        self.header.remove_extension(format::EXT_ONES);
+        self.header.set_count(self.count);
+        self.file.seek(io::SeekFrom::Start(0))?;
+        self.header.write(&mut self.file)?;
+        self.file.sync_all()?;
+
+        set_all(&mut self.dirty, false);
+        Ok(())
+    }
+
+    /// Number of pages held in memory.
+    pub fn loaded_pages(&self) -> usize {
+        self.resident
+    }
+
+    pub fn count_estimate(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.as_bytes().iter().any(|b| *b != 0)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= self.params.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lazy_bloomfilter() {
+        let path = "test_lazy.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        let mut lazy = BloomFilter::open_lazy(path).unwrap();
+        assert_eq!(0, lazy.loaded_pages());
+        assert!(lazy.contains(0u32).unwrap());
+        assert_eq!(1, lazy.loaded_pages());
+        assert!((0..10u32).all(|i| lazy.contains(i).unwrap()));
+        assert!(lazy.loaded_pages() <= 10);
+        assert!((0..1000u32).all(|i| lazy.contains(i).unwrap()));
+
+        assert!(lazy.insert(5000u32).unwrap());
+        assert!(lazy.is_dirty());
+        lazy.save().unwrap();
+        assert!(!lazy.is_dirty());
+        drop(lazy);
+
+        BloomFilter::verify(path).unwrap();
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!(loaded.contains(5000u32));
+        assert_eq!(2, loaded.generation());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lazy_bloomfilter_budget() {
+        let path = "test_lazy_budget.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+        bf.save(path).unwrap();
+
+        let mut lazy = BloomFilter::open_lazy(path).unwrap();
+        let budget = 4 * lazy.params().page_size as usize;
+        lazy.set_memory_budget(Some(budget)).unwrap();
+        for i in 0..1000u32 {
+            lazy.insert(i).unwrap();
+            assert!(lazy.loaded_pages() <= 4);
+        }
+
+        // evicted pages were written back, and are read in again
+        assert!((0..1000u32).all(|i| lazy.contains(i).unwrap()));
+        lazy.save().unwrap();
+        drop(lazy);
+
+        BloomFilter::verify(path).unwrap();
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,32 +1,142 @@
 /// Prototypical disk-backed Rust bloom filter
 ///
 /// Todo:
-/// * Write log, rather than adding items directly, write serialized BloomHash
-///   structs to a log and apply them when the log hits a size limit, to minimise
-///   write costs.
-///
 /// * Multiple reader/writers with eventual consistency.
 ///
-/// * Scalable filters - multiple filters scaled to maintain a desired false-
-///   positive rate for an unbounded number of items.
-///
 /// * Proper tests.
 ///
 /// For my current purposes I ended up just using the write log idea - 16 bytes
-/// per entry was sufficient and the implementation was dead simple.
-use std::convert::TryInto;
-use std::fs::OpenOptions;
+/// per entry was sufficient and the implementation was dead simple.  It's now
+/// `LoggedBloomFilter`.
+use std::fs::{File, OpenOptions};
 use std::hash::Hash;
 use std::io::{self, Read, Seek, Write};
-use std::path::Path;
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bitvec_rs::BitVec;
-use siphasher::sip128::{Hasher128, SipHasher};
 
+use format::FileHeader;
+use hasher::CustomHasher;
+
+mod ab;
+mod aging;
+#[cfg(feature = "tokio")]
+mod async_io;
+mod attenuated;
+mod bitsliced;
+mod bloomier;
+mod cache;
+mod cascade;
+mod compressed;
+mod counting;
+mod countmin;
+mod cuckoo;
+mod delta;
+mod diff;
+#[cfg(target_os = "linux")]
+mod direct;
+mod dleft;
+mod doorkeeper;
+#[cfg(feature = "encryption")]
+mod encrypted;
+#[cfg(feature = "flusher")]
+mod flusher;
+pub mod format;
+mod fsck;
+mod hasher;
+mod hyperloglog;
+mod inverse;
+mod lazy;
+#[cfg(target_os = "linux")]
+mod lock;
+mod logged;
+#[cfg(feature = "mmap")]
+mod mapped;
+mod minhash;
+mod paged;
 mod params;
+mod quotient;
+mod reload;
+mod ribbon;
+mod rotating;
+mod scalable;
+mod segmented;
+#[cfg(feature = "serde")]
+mod serialize;
+mod setops;
+mod shifting;
+mod snapshot;
+mod spectral;
+mod stable;
+mod stat;
+mod storage;
+mod typed;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod view;
+mod xor;
+pub use aging::*;
+pub use attenuated::*;
+pub use bitsliced::*;
+pub use bloomier::*;
+pub use cache::*;
+pub use cascade::*;
+pub use counting::*;
+pub use countmin::*;
+pub use cuckoo::*;
+pub use diff::*;
+pub use dleft::*;
+pub use doorkeeper::*;
+#[cfg(feature = "flusher")]
+pub use flusher::*;
+pub use format::LoadError;
+pub use fsck::*;
+pub use hasher::*;
+pub use hyperloglog::*;
+pub use inverse::*;
+pub use lazy::*;
+#[cfg(target_os = "linux")]
+pub use lock::*;
+pub use logged::*;
+#[cfg(feature = "mmap")]
+pub use mapped::*;
+pub use minhash::*;
+pub use paged::Durability;
 pub use params::*;
+pub use quotient::*;
+pub use ribbon::*;
+pub use rotating::*;
+pub use scalable::*;
+pub use shifting::*;
+pub use snapshot::*;
+pub use spectral::*;
+pub use stable::*;
+pub use stat::*;
+pub use storage::*;
+pub use typed::*;
+pub use view::*;
+pub use xor::*;
 
-#[derive(Debug, Clone, Copy)]
+/// The pair of 64-bit hashes from which all of an item's probe positions are
+/// derived.
+///
+/// `From<T: Hash>` is convenient, but only as stable as `T`'s `Hash`
+/// implementation.  Integers, `bool`, `char`, `str`, `String`, slices, `Vec`
+/// and tuples of these have well-defined `Hash` output in practice, while
+/// floats don't implement it at all and `HashMap`/`HashSet` hash in iteration
+/// order, which is randomised.  Even for the "stable" types the framing
+/// (length prefixes, `0xff` terminators on `str`) is an implementation detail
+/// of std.
+///
+/// Filters intended to outlive the binary that built them should prefer the
+/// `from_canonical*` constructors, which feed bytes directly to the hasher,
+/// or `BloomFilter::hash_canonical` for a filter with its own key or
+/// algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BloomHash {
     h1: u64,
     h2: u64,
@@ -37,42 +147,147 @@ where
     T: Hash,
 {
     fn from(hashable: T) -> Self {
-        let mut hash = SipHasher::new();
-        hashable.hash(&mut hash);
-        let h = hash.finish128();
-
-        Self { h1: h.h1, h2: h.h2 }
+        SipHash128::default().hash_one(hashable)
     }
 }
 
 impl BloomHash {
+    /// Hash an item with a copy of a caller-configured hasher, allowing for
+    /// custom keys without repeating their setup for every item.
+    pub fn from_hasher<T, H>(hashable: T, hasher: &H) -> Self
+    where
+        T: Hash,
+        H: Hasher128 + Clone,
+    {
+        let mut hash = hasher.clone();
+        hashable.hash(&mut hash);
+        Self::from_u128(hash.finish128())
+    }
+
+    /// Hash a canonical byte representation with no additional framing.
+    pub fn from_canonical(bytes: &[u8]) -> Self {
+        SipHash128::default().hash_canonical(bytes)
+    }
+
+    /// Hash the UTF-8 bytes of a string.
+    pub fn from_canonical_str(s: &str) -> Self {
+        Self::from_canonical(s.as_bytes())
+    }
+
+    /// Hash the little-endian bytes of an integer.
+    pub fn from_canonical_u64(i: u64) -> Self {
+        Self::from_canonical(&i.to_le_bytes())
+    }
+
+    /// Hash the little-endian bytes of an integer.
+    pub fn from_canonical_i64(i: i64) -> Self {
+        Self::from_canonical(&i.to_le_bytes())
+    }
+
+    /// A hash from its two halves, as returned by `h1` and `h2`, for
+    /// applications keeping their own record of hashes.
+    pub fn from_parts(h1: u64, h2: u64) -> Self {
+        Self { h1, h2 }
+    }
+
+    /// A hash from the `u128` it converts to.  This can't be a `From`
+    /// conversion, as `u128` already converts by being hashed.
+    pub fn from_u128(hash: u128) -> Self {
+        Self::from_parts((hash >> 64) as u64, hash as u64)
+    }
+
+    /// The first half of the hash, the base of each probe.
+    pub fn h1(&self) -> u64 {
+        self.h1
+    }
+
+    /// The second half of the hash, the step between probes.
+    pub fn h2(&self) -> u64 {
+        self.h2
+    }
+
     fn nth(&self, i: u32) -> u64 {
         self.h1.wrapping_add(u64::from(i).wrapping_mul(self.h2))
     }
 }
 
+/// `h1` in the high half, `h2` in the low.
+impl From<BloomHash> for u128 {
+    fn from(hash: BloomHash) -> Self {
+        (u128::from(hash.h1) << 64) | u128::from(hash.h2)
+    }
+}
+
+/// When a `BloomFilter` counts as full, for `is_full`, `checked_insert` and
+/// `saturation`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FullnessPolicy {
+    /// Once as many items have been inserted as it was sized for, `n`.
+    #[default]
+    Capacity,
+    /// Once this many items have been inserted.
+    Count(u64),
+    /// Once this fraction of its bits are set, as `fill_ratio` gives.
+    FillRatio(f64),
+    /// Once its false-positive rate reaches this, as
+    /// `effective_false_positive_rate` gives.
+    FalsePositiveRate(f64),
+}
+
 #[derive(Debug)]
 pub struct BloomFilter {
     params: BloomFilterParams,
-    count: u32,
-    pages: u32,
+    count: u64,
+    pages: u64,
     dirty: BitVec,
     filter: BitVec,
+    /// Reserve disk space for the whole file when writing it out in full.
+    preallocate: bool,
+    durability: Durability,
+    /// Number of previous saves `save` keeps copies of.
+    backups: usize,
+    /// Label to record with where and when each save was made, if any.
+    provenance: Option<String>,
+    fullness: FullnessPolicy,
+    /// Number of saves, recorded in each file written.
+    generation: u64,
+    /// Pages of the latest snapshot, while it's alive, for the next to share.
+    snapshot: Weak<Vec<Arc<[u8]>>>,
+    /// The generation of the save each page was last changed for, unsaved
+    /// changes counting towards the next.
+    changed: Vec<u64>,
+    /// Hasher items are rehashed with in place of the params' key and
+    /// algorithm, if any.  It isn't saved.
+    hasher: Option<CustomHasher>,
 }
 
-const BLOOM_PAGE_SIZE: u32 = 1024 * 16;
-const BLOOM_PAGE_BIT_SIZE: u32 = BLOOM_PAGE_SIZE * 8;
+const BLOOM_PAGE_SIZE: u32 = format::PAGE_SIZE;
+/// One cache line, the unit `Layout::Blocked` confines probes to.
+const BLOOM_BLOCK_BIT_SIZE: u64 = 512;
+/// False-positive rate of filters built by `collect`.
+const COLLECT_P: f64 = 0.01;
 
 impl BloomFilter {
     pub fn from_params(params: BloomFilterParams) -> Self {
-        // round to the nearest page size and recalculate our capacity etc
+        // round to the nearest page size and recalculate our capacity etc,
+        // remembering what was originally asked for
+        let requested_p = params.requested_p.unwrap_or(params.p);
+        let page_bits = u64::from(params.page_size) * 8;
         let params = BloomFilterParamsBuilder::default()
-            .bits(params.m + (BLOOM_PAGE_BIT_SIZE - (params.m % BLOOM_PAGE_BIT_SIZE)))
+            .bits(params.m.div_ceil(page_bits) * page_bits)
             .false_positives(params.p)
+            .layout(params.layout)
+            .page_size(params.page_size)
+            .hash_key(params.hash_key.0, params.hash_key.1)
+            .hash_algorithm(params.hash_algorithm)
             .to_params()
+            .map(|params| BloomFilterParams {
+                requested_p: Some(requested_p),
+                ..params
+            })
             .unwrap();
 
-        let pages = params.m / BLOOM_PAGE_BIT_SIZE;
+        let pages = params.m / page_bits;
 
         Self {
             dirty: BitVec::from_elem(pages as usize, false),
@@ -80,224 +295,1712 @@ impl BloomFilter {
             count: 0,
             pages,
             params,
+            preallocate: false,
+            durability: Durability::default(),
+            backups: 0,
+            provenance: None,
+            fullness: FullnessPolicy::default(),
+            generation: 0,
+            snapshot: Weak::new(),
+            changed: vec![0; pages as usize],
+            hasher: None,
         }
     }
 
-    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
-        let mut header = [0; BLOOM_PAGE_SIZE as usize];
-        reader.read_exact(&mut header[..])?;
-        assert!(&header[0..8] == b"BLOOMv00");
-        let n = u32::from_be_bytes(header[8..12].try_into().unwrap());
-        let m = u32::from_be_bytes(header[12..16].try_into().unwrap());
-        let k = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    /// Read a filter hashed with the default key.
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        Self::from_reader_with_key(reader, (0, 0))
+    }
+
+    /// Read a filter, checking it was built with the hash key `key`.
+    pub fn from_reader_with_key<R: Read>(mut reader: R, key: (u64, u64)) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+        let params = loadable_params(&header, key)?;
 
-        let mut filter = vec![0; (m / 8) as usize];
-        reader.read_exact(&mut filter[..])?;
+        // read straight into the BitVec's storage rather than via a temporary
+        let mut filter = BitVec::from_elem((params.m / 8 * 8) as usize, false);
+        let page_size = header.page_size() as usize;
+        filter.with_bytes_mut(|buf| match &header.page_map {
+            _ if header.is_compressed() => compressed::read_body(&mut reader, buf),
+            Some(map) => {
+                for (page, _) in map.iter().enumerate().filter(|(_, present)| **present) {
+                    let start = page * page_size;
+                    reader.read_exact(&mut buf[start..start + page_size])?;
+                }
+                Ok(())
+            }
+            None => reader.read_exact(buf),
+        })?;
+        header.verify_body(filter.as_bytes())?;
 
-        let params = BloomFilterParamsBuilder::default()
-            .capacity(n)
-            .bits(m)
-            .hashes(k)
-            .to_params()
-            .unwrap();
+        Ok(Self::from_loaded(params, filter, &header))
+    }
+
+    /// A clean filter around a bit array read back from storage with
+    /// `header`.
+    fn from_loaded(params: BloomFilterParams, filter: BitVec, header: &FileHeader) -> Self {
+        let mut ret = Self::from_bits(params, filter, header.generation());
+        ret.count = ret.header_count(header);
+        ret
+    }
 
-        let pages = params.m / BLOOM_PAGE_BIT_SIZE;
+    /// A clean filter around a bit array of exactly the size `params` gives,
+    /// as of save `generation`.  The count is left for the caller to set.
+    fn from_bits(params: BloomFilterParams, filter: BitVec, generation: u64) -> Self {
+        let pages = params.m / (u64::from(params.page_size) * 8);
 
-        let mut ret = Self {
+        Self {
             dirty: BitVec::from_elem(pages as usize, false),
-            filter: BitVec::from_bytes(&filter[..]),
+            filter,
             count: 0,
             pages,
             params,
-        };
+            preallocate: false,
+            durability: Durability::default(),
+            backups: 0,
+            provenance: None,
+            fullness: FullnessPolicy::default(),
+            generation,
+            snapshot: Weak::new(),
+            // when the pages last changed isn't known, only that it wasn't
+            // after the filter was saved
+            changed: vec![generation; pages as usize],
+            hasher: None,
+        }
+    }
 
-        ret.count = ret.count_estimate();
-        Ok(ret)
+    /// The count recorded in `header`, describing this filter's body.
+    fn header_count(&self, header: &FileHeader) -> u64 {
+        // files written before the count was recorded have only an estimate
+        header.count().unwrap_or_else(|| self.count_estimate())
     }
 
-    pub fn with_capacity_p(capacity: u32, p: f64) -> Self {
+    pub fn with_capacity_p(capacity: u64, p: f64) -> Self {
         Self::from_params(BloomFilterParams::with_capacity_p(capacity, p))
     }
 
+    /// Like `from_params`, but rehashing items with `hasher` rather than the
+    /// params' key and algorithm.  The hasher isn't saved, so a filter loaded
+    /// from this one's file must be given it again with `set_hasher`.
+    pub fn with_hasher<B>(params: BloomFilterParams, hasher: B) -> Self
+    where
+        B: BuildHasher128 + Send + Sync + 'static,
+    {
+        let mut ret = Self::from_params(params);
+        ret.set_hasher(hasher);
+        ret
+    }
+
+    /// Rehash items with `hasher` from now on, as `with_hasher` does.  Items
+    /// already inserted through another hasher won't be found.
+    pub fn set_hasher<B>(&mut self, hasher: B)
+    where
+        B: BuildHasher128 + Send + Sync + 'static,
+    {
+        self.hasher = Some(CustomHasher::new(hasher));
+    }
+
+    /// Like `from_params`, overriding the params' layout.
+    pub fn with_layout(params: BloomFilterParams, layout: Layout) -> Self {
+        Self::from_params(BloomFilterParams { layout, ..params })
+    }
+
+    /// Load the filter at `path`, first replaying any write log left beside
+    /// it by a `LoggedBloomFilter`.
     pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        Self::from_reader(std::fs::File::open(path.as_ref())?)
+        Self::load_with_key(path, (0, 0))
+    }
+
+    /// Load a filter, checking it was built with the hash key `key`.
+    pub fn load_with_key<P: AsRef<Path>>(path: P, key: (u64, u64)) -> io::Result<Self> {
+        let mut filter = Self::from_reader_with_key(File::open(path.as_ref())?, key)?;
+        logged::recover(path.as_ref(), &mut filter)?;
+        Ok(filter)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(self.params.n, self.params.m, self.params.k);
+        header.set_page_size(self.params.page_size);
+        header.set_hash_key(self.params.hash_key);
+        header.set_hash_algorithm(self.params.hash_algorithm);
+        self.stamp(&mut header);
+        match self.params.layout {
+            Layout::Paged => {}
+            Layout::Partitioned => header.flags |= format::FLAG_PARTITIONED,
+            Layout::Blocked => header.flags |= format::FLAG_BLOCKED,
+        }
+        header
+    }
+
+    /// Record this save's generation, bit count, item count and any
+    /// provenance in `header`.
+    fn stamp(&self, header: &mut FileHeader) {
+        header.set_generation(self.generation);
+        header.set_ones(self.count_ones());
+        header.set_count(self.count);
+        match &self.provenance {
+            Some(label) => header.set_provenance(&format::Provenance {
+                saved_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs()),
+                crate_version: env!("CARGO_PKG_VERSION").to_string(),
+                hostname: paged::hostname(),
+                label: label.clone(),
+            }),
+            None => {
+                header.remove_extension(format::EXT_PROVENANCE);
+            }
+        }
+    }
+
+    /// The header with checksums of every page, for writing out the whole
+    /// filter.
+    fn checksummed_header(&self) -> FileHeader {
+        let mut header = self.file_header();
+        header.set_checksums(self.filter.as_bytes());
+        header
+    }
+
+    pub fn params(&self) -> &BloomFilterParams {
+        &self.params
     }
 
-    fn write_header<W: Write>(&self, mut writer: W) -> io::Result<()> {
-        writer.write_all(b"BLOOMv00")?;
-        writer.write_all(&self.params.n.to_be_bytes())?;
-        writer.write_all(&self.params.m.to_be_bytes())?;
-        writer.write_all(&self.params.k.to_be_bytes())
+    /// Number of items the filter was sized for, `n`.
+    pub fn capacity(&self) -> u64 {
+        self.params.n
+    }
+
+    /// Size of the bit array, `m`.
+    pub fn bits(&self) -> u64 {
+        self.params.m
+    }
+
+    /// Number of probes per item, `k`.
+    pub fn hashes(&self) -> u32 {
+        self.params.k
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.params.layout
+    }
+
+    /// Page size in bytes.
+    pub fn page_size(&self) -> u32 {
+        self.params.page_size
+    }
+
+    pub fn hash_key(&self) -> (u64, u64) {
+        self.params.hash_key
+    }
+
+    /// Hash an item with the filter's key, for `insert_hash` and
+    /// `contains_hash`.  With the default key this is the same as
+    /// `BloomHash::from`.
+    pub fn hash<T: Hash>(&self, item: T) -> BloomHash {
+        self.rehash(BloomHash::from(item))
+    }
+
+    /// Rehash a `BloomHash` with the filter's hasher, or its key and
+    /// algorithm, as `insert` and `contains` do with every item.  With the
+    /// defaults it's returned unchanged.
+    pub fn rehash(&self, hash: BloomHash) -> BloomHash {
+        match &self.hasher {
+            Some(hasher) => hasher.rehash(hash),
+            None => self.params.rehash(hash),
+        }
+    }
+
+    /// Hash canonical bytes with the filter's key and algorithm, as
+    /// `BloomHash::from_canonical` does with the defaults.
+    pub fn hash_canonical(&self, bytes: &[u8]) -> BloomHash {
+        self.params
+            .hash_algorithm
+            .hash_canonical(self.params.hash_key, bytes)
+    }
+
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.params.hash_algorithm
+    }
+
+    /// Number of times the filter has been saved, counting saves of the file
+    /// it was loaded from.  Each save writes the next generation into the
+    /// file's header, so comparing generations tells which of two copies is
+    /// newer and a gap shows a copy missed an update.  A failed save may use
+    /// up a generation.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// How saves sync what they write.  Syncing less makes saving faster,
+    /// at the risk of losing or, for saves in place, tearing the latest
+    /// save in a crash.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    pub fn provenance_label(&self) -> Option<&str> {
+        self.provenance.as_deref()
+    }
+
+    /// With a label, have each save record it in the file along with the
+    /// time, this crate's version and the hostname, as shown by `stat`.
+    pub fn set_provenance_label(&mut self, label: Option<String>) {
+        self.provenance = label;
+    }
+
+    pub fn backups(&self) -> usize {
+        self.backups
+    }
+
+    /// Have `save` first copy the file it's about to overwrite to a backup,
+    /// keeping the `count` most recent as "path.1", "path.2" and so on,
+    /// newest first, so a filter spoiled by bad inserts can be rolled back.
+    pub fn set_backups(&mut self, count: usize) {
+        self.backups = count;
+    }
+
+    /// Path of the `n`th most recent backup `save` kept of the file at
+    /// `path`, counting from 1.
+    pub fn backup_path<P: AsRef<Path>>(path: P, n: usize) -> PathBuf {
+        paged::backup_path(path.as_ref(), n)
+    }
+
+    pub fn fullness_policy(&self) -> FullnessPolicy {
+        self.fullness
+    }
+
+    /// Decide when the filter is full by something other than the number of
+    /// items, such as how many bits are set or the false-positive rate they
+    /// give.  Those are worked out afresh from the whole filter each time
+    /// it's checked, so make `checked_insert` slower than the default.  Like
+    /// durability, the policy isn't saved with the filter.
+    ///
+    /// Panics unless a fill ratio or rate is within (0, 1] and a count is
+    /// non-zero.
+    pub fn set_fullness_policy(&mut self, policy: FullnessPolicy) {
+        match policy {
+            FullnessPolicy::Capacity => {}
+            FullnessPolicy::Count(count) => assert!(count > 0),
+            FullnessPolicy::FillRatio(limit) | FullnessPolicy::FalsePositiveRate(limit) => {
+                assert!(limit > 0.0 && limit <= 1.0)
+            }
+        }
+
+        self.fullness = policy;
+    }
+
+    pub fn preallocate(&self) -> bool {
+        self.preallocate
+    }
+
+    /// Whether saves which write the whole file first reserve disk space for
+    /// all of it, so later saves of dirty pages can't run out of space part
+    /// way through and the file is laid out contiguously.  Otherwise all-zero
+    /// pages are left as holes, taking no space until they're written.
+    pub fn set_preallocate(&mut self, preallocate: bool) {
+        self.preallocate = preallocate;
+    }
+
+    /// Write the whole filter, as `load` or `from_reader` expect, to any
+    /// writer such as a socket or buffer.  Saving to a file is better done
+    /// with `save`, which can rewrite only dirty pages and leaves all-zero
+    /// pages as holes.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.checksummed_header().write(&mut writer)?;
+        writer.write_all(self.filter.as_bytes())
+    }
+
+    /// Write the whole filter to an empty file with `write_to`, leaving
+    /// all-zero pages as holes.
+    fn write_full(&self, file: &mut File) -> io::Result<()> {
+        if self.preallocate {
+            let len = format::HEADER_SIZE + self.filter.as_bytes().len();
+            paged::preallocate(file, len as u64)?;
+        }
+
+        let mut writer = paged::SparseWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.finish()
     }
 
     pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        if let Ok(mut file) = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(path.as_ref())
-        {
-            let mut header = [0; BLOOM_PAGE_SIZE as usize];
-            self.write_header(&mut header[..]).unwrap();
+        self.generation += 1;
+        if self.backups > 0 {
+            paged::rotate_backups(path.as_ref(), self.backups, self.durability)?;
+        }
+
+        self.write_file(path.as_ref())?;
+        self.clear_dirty();
+        Ok(())
+    }
 
-            file.write_all(&header[..])?;
-            file.write_all(self.filter.as_bytes())?;
-            file.sync_all()?;
-            self.clear_dirty();
-            return Ok(());
+    /// Write the filter to `path` as the current generation, rewriting only
+    /// dirty pages if the file already holds an earlier save of it.
+    fn write_file(&self, path: &Path) -> io::Result<()> {
+        if let Ok(mut file) = OpenOptions::new().create_new(true).write(true).open(path) {
+            return self.save_full(&mut file);
         }
 
-        let mut file = OpenOptions::new().write(true).open(path.as_ref())?;
-        let bytes = self.filter.as_bytes();
-        for index in self
-            .dirty
-            .iter()
-            .enumerate()
-            .filter(|(_, bit)| *bit)
-            .map(|(index, _)| index)
-        {
-            file.seek(io::SeekFrom::Start(
-                ((1 + index) * BLOOM_PAGE_SIZE as usize) as u64,
-            ))?;
-            file.write_all(
-                &bytes[(index * BLOOM_PAGE_SIZE as usize)
-                    ..((index * BLOOM_PAGE_SIZE as usize) + BLOOM_PAGE_SIZE as usize)],
-            )?;
-        }
-        file.sync_all()?;
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let len = file.metadata()?.len();
+        let existing = if len >= format::HEADER_SIZE as u64 {
+            Some(FileHeader::read(&mut file)?)
+        } else {
+            None
+        };
+
+        let mut header = match existing {
+            // sparse files have no fixed page offsets to update in place
+            Some(header) if header.is_sparse() => return self.write_compact(path),
+            #[cfg(feature = "zstd")]
+            Some(header) if header.is_compressed() => return self.write_compressed(path),
+            // rather than quietly replace it with plaintext
+            Some(header) if header.is_encrypted() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "encrypted filters must be saved with save_encrypted",
+                ))
+            }
+            Some(header)
+                if header.is_plain()
+                    && header.m == self.params.m
+                    && header.page_size() == self.params.page_size
+                    && header.without_checksums().flags == self.file_header().flags
+                    && header.hash_key() == self.params.hash_key
+                    && header.hash_algorithm() == self.params.hash_algorithm
+                    && len >= header.file_len() =>
+            {
+                header
+            }
+            // a short or mismatched file, perhaps from an interrupted save, would
+            // leave gaps if we only wrote dirty pages
+            _ => {
+                file.set_len(0)?;
+                file.seek(io::SeekFrom::Start(0))?;
+                return self.save_full(&mut file);
+            }
+        };
+
+        self.stamp(&mut header);
+        let dirty = paged::dirty_pages(&self.dirty);
+        paged::write_in_place(&mut file, header, self.filter.as_bytes(), &dirty)?;
+        self.durability.sync(&file)
+    }
+
+    /// Check the file at `path` against its page checksums and digest,
+    /// without loading it as a filter.
+    pub fn verify<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let mut reader = io::BufReader::new(File::open(path.as_ref())?);
+        let header = FileHeader::read(&mut reader)?;
+        if header.is_compressed() {
+            let mut body = vec![0; header.body_len() as usize];
+            compressed::read_body(reader, &mut body)?;
+            return header.verify_body(&body);
+        }
+
+        header.verify_stream(reader)
+    }
+
+    /// Digest of the filter's body, as recorded in its saved header.
+    pub fn digest(&self) -> u32 {
+        let sums: Vec<u32> = self
+            .filter
+            .as_bytes()
+            .chunks(self.params.page_size as usize)
+            .map(format::crc32c)
+            .collect();
+
+        format::digest_of(&sums)
+    }
+
+    /// Save the whole filter to a temporary file and rename it over `path`,
+    /// so a crash part way through leaves the previous save intact.  Slower
+    /// than `save`, which rewrites only dirty pages in place.
+    pub fn save_atomic<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.generation += 1;
+        paged::write_atomic(path.as_ref(), self.durability, |file| self.write_full(file))?;
+
         self.clear_dirty();
+        Ok(())
+    }
+
+    fn save_full(&self, file: &mut File) -> io::Result<()> {
+        self.write_full(file)?;
+        self.durability.sync(file)
+    }
 
+    /// Rewrite the file at `path` in sparse form, omitting pages which are
+    /// entirely zero.
+    ///
+    /// Later calls to `save` on a sparse file will compact it again.
+    pub fn compact<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.generation += 1;
+        self.write_compact(path.as_ref())?;
+        self.clear_dirty();
         Ok(())
     }
 
+    /// Write the filter to `path` in sparse form, as the current generation.
+    fn write_compact(&self, path: &Path) -> io::Result<()> {
+        let pages: Vec<&[u8]> = self
+            .filter
+            .as_bytes()
+            .chunks(self.params.page_size as usize)
+            .collect();
+
+        let mut header = self.file_header();
+        header.flags |= format::FLAG_SPARSE;
+        header.page_map = Some(
+            pages
+                .iter()
+                .map(|page| page.iter().any(|b| *b != 0))
+                .collect(),
+        );
+        header.set_checksums(self.filter.as_bytes());
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+
+        header.write(&mut file)?;
+        for page in pages.iter().filter(|page| page.iter().any(|b| *b != 0)) {
+            file.write_all(page)?;
+        }
+
+        self.durability.sync(&file)
+    }
+
     fn clear_dirty(&mut self) {
-        self.dirty
-            .with_bytes_mut(|buf| buf.iter_mut().for_each(|b| *b = 0));
+        set_all(&mut self.dirty, false);
+    }
+
+    fn mark_dirty(&mut self, page: usize) {
+        self.dirty.set(page, true);
+        self.changed[page] = self.generation + 1;
+    }
+
+    fn mark_all_dirty(&mut self) {
+        set_all(&mut self.dirty, true);
+        self.changed.fill(self.generation + 1);
+    }
+
+    /// An empty filter with exactly the same geometry.  Unlike `from_params`
+    /// with our own params, this doesn't round `m` up again.
+    fn empty_like(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+            count: 0,
+            pages: self.pages,
+            dirty: BitVec::from_elem(self.dirty.len(), false),
+            filter: BitVec::from_elem(self.filter.len(), false),
+            preallocate: self.preallocate,
+            durability: self.durability,
+            backups: self.backups,
+            provenance: self.provenance.clone(),
+            fullness: self.fullness,
+            generation: 0,
+            snapshot: Weak::new(),
+            changed: vec![0; self.changed.len()],
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    #[inline]
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.probe(self.rehash(item.into()))
     }
 
-    pub fn contains<T: Into<BloomHash>>(&mut self, item: T) -> bool {
-        self.check_or_insert(item.into(), false)
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.probe(*hash)
+    }
+
+    /// Check for every item, answering for each in turn.
+    pub fn contains_many<I>(&self, items: I) -> Vec<bool>
+    where
+        I: IntoIterator,
+        I::Item: Into<BloomHash>,
+    {
+        items
+            .into_iter()
+            .map(|item| self.probe(self.rehash(item.into())))
+            .collect()
     }
 
     pub fn checked_insert<T: Into<BloomHash>>(&mut self, item: T) -> Option<bool> {
         if self.is_full() {
             None
         } else {
-            Some(self.check_or_insert(item.into(), true))
+            Some(self.insert_shifted(self.rehash(item.into()), 0))
         }
     }
 
+    #[inline]
     pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
-        self.check_or_insert(item.into(), true)
+        self.insert_shifted(self.rehash(item.into()), 0)
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    ///
+    /// The hash is used as it is, so for a filter with its own key or
+    /// algorithm it should come from `hash`.  With the defaults this is the
+    /// same as passing the `BloomHash` to `insert`.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        self.insert_shifted(*hash, 0)
     }
 
-    fn check_or_insert(&mut self, hash: BloomHash, insert: bool) -> bool {
-        let page = if self.pages > 0 {
-            (hash.nth(self.params.k + 1) % u64::from(self.pages))
+    /// Insert every item, returning how many weren't already present.
+    pub fn insert_many<I>(&mut self, items: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: Into<BloomHash>,
+    {
+        let mut added = 0;
+        for item in items {
+            added += self.insert(item) as usize;
+        }
+        added
+    }
+
+    /// Like `contains`, but also returns the estimated probability that a
+    /// positive answer is a false positive.  Negative answers are always
+    /// certain, so their probability is 0.0.
+    pub fn contains_with_confidence<T: Into<BloomHash>>(&self, item: T) -> (bool, f64) {
+        if self.probe(self.rehash(item.into())) {
+            (true, self.current_false_positive_rate())
         } else {
-            0
-        };
+            (false, 0.0)
+        }
+    }
+
+    /// The bit index of each of an item's k probes, moved `shift` bits along
+    /// within the segment each falls in.  Only `ShiftingBloomFilter` shifts.
+    fn shifted_bits(&self, hash: BloomHash, shift: u64) -> impl Iterator<Item = u64> {
+        probe_bits(&self.params, self.pages, hash, shift)
+    }
 
-        let offset = page * u64::from(BLOOM_PAGE_BIT_SIZE);
+    fn probe(&self, hash: BloomHash) -> bool {
+        self.probe_shifted(hash, 0)
+    }
 
-        assert!(offset + u64::from(BLOOM_PAGE_BIT_SIZE) <= self.filter.len() as u64);
+    fn probe_shifted(&self, hash: BloomHash, shift: u64) -> bool {
+        self.shifted_bits(hash, shift)
+            .all(|bit| self.filter.get(bit as usize).expect("within bounds"))
+    }
 
+    fn insert_shifted(&mut self, hash: BloomHash, shift: u64) -> bool {
         let mut added = false;
 
-        for k in 0..self.params.k {
-            let bit = offset + (hash.nth(k) % u64::from(BLOOM_PAGE_BIT_SIZE));
-
+        for bit in self.shifted_bits(hash, shift) {
             if !self.filter.get(bit as usize).expect("within bounds") {
-                if !insert {
-                    return false;
-                }
-
                 added = true;
 
                 self.filter.set(bit as usize, true);
+                self.mark_dirty((bit / u64::from(self.params.page_size * 8)) as usize);
             }
         }
 
-        if !insert {
-            return true;
-        }
-
         if added {
             self.count += 1;
-            self.dirty.set(page as usize, true);
         }
 
         added
     }
 
-    pub fn count_estimate(&self) -> u32 {
-        -((f64::from(self.params.m) / f64::from(self.params.k))
-            * (1.0 - (f64::from(self.count_ones()) / f64::from(self.params.m))).ln()) as u32
+    pub fn count_estimate(&self) -> u64 {
+        estimate_count(&self.params, self.count_ones())
     }
 
-    fn count_ones(&self) -> u32 {
-        self.filter.as_bytes().iter().map(|b| b.count_ones()).sum()
+    /// The false-positive probability given the bits currently set, rather
+    /// than the design-time `p`, assuming they're spread evenly over the
+    /// filter.
+    pub fn current_false_positive_rate(&self) -> f64 {
+        self.fill_ratio().powf(f64::from(self.params.k))
     }
 
-    pub fn is_full(&self) -> bool {
-        self.count >= self.params.n
-    }
+    /// The false-positive probability given where the bits currently set
+    /// lie, for alarming once a filter degrades past the `p` it was built
+    /// for.  Probes are confined to a page, block or segment according to the
+    /// layout, and unevenly filled ones answer positive more often than
+    /// `current_false_positive_rate` allows for.
+    pub fn effective_false_positive_rate(&self) -> f64 {
+        let k = f64::from(self.params.k);
+        let bytes = self.filter.as_bytes();
 
-    pub fn is_empty(&self) -> bool {
-        self.count == 0
-    }
-}
+        // probes all land in one region, chosen evenly
+        let mean_rate = |region: usize| {
+            let regions = bytes.len() / region;
+            bytes
+                .chunks_exact(region)
+                .map(|r| {
+                    let ones: u32 = r.iter().map(|b| b.count_ones()).sum();
+                    (f64::from(ones) / (region * 8) as f64).powf(k)
+                })
+                .sum::<f64>()
+                / regions as f64
+        };
 
-impl From<BloomFilterParams> for BloomFilter {
-    fn from(p: BloomFilterParams) -> Self {
-        Self::from_params(p)
+        match self.params.layout {
+            Layout::Paged => mean_rate(self.params.page_size as usize),
+            Layout::Blocked => mean_rate((BLOOM_BLOCK_BIT_SIZE / 8) as usize),
+            // each probe lands in its own segment, so all must hit
+            Layout::Partitioned => {
+                let segment = self.params.m / u64::from(self.params.k);
+                (0..u64::from(self.params.k))
+                    .map(|i| {
+                        self.ones_between(i * segment, (i + 1) * segment) as f64 / segment as f64
+                    })
+                    .product()
+            }
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn bloomfilter_looks_reasonable() {
-        let mut bf = BloomFilter::with_capacity_p(400, 0.01);
+    /// Bits set from `start` up to `end`, which needn't fall on byte
+    /// boundaries.
+    fn ones_between(&self, start: u64, end: u64) -> u64 {
+        // whole bytes in the middle, odd bits either side
+        let head = (start.div_ceil(8) * 8).min(end);
+        let tail = (end / 8 * 8).max(head);
+        let get = |bit: u64| u64::from(self.filter.get(bit as usize).expect("within bounds"));
 
-        assert_eq!(false, bf.contains("meep"));
-        assert_eq!(false, bf.contains(BloomHash::from("meep")));
+        (start..head).chain(tail..end).map(get).sum::<u64>()
+            + self.filter.as_bytes()[(head / 8) as usize..(tail / 8) as usize]
+                .iter()
+                .map(|b| u64::from(b.count_ones()))
+                .sum::<u64>()
+    }
 
-        assert_eq!(true, bf.insert("meep"));
-        assert_eq!(false, bf.insert("meep"));
-        assert_eq!(true, bf.contains(BloomHash::from("meep")));
+    /// The fraction of bits set, from 0.0 when empty towards 1.0 as the
+    /// filter saturates.  A filter filled to its capacity sits near 0.5.
+    pub fn fill_ratio(&self) -> f64 {
+        self.count_ones() as f64 / self.params.m as f64
+    }
 
-        assert_eq!(true, bf.insert("moop"));
-        assert_eq!(false, bf.insert("moop"));
-        assert_eq!(true, bf.contains(BloomHash::from("moop")));
-        assert_eq!(2, bf.count_estimate());
+    fn count_ones(&self) -> u64 {
+        self.filter
+            .as_bytes()
+            .iter()
+            .map(|b| u64::from(b.count_ones()))
+            .sum()
     }
 
-    #[test]
-    fn bloomfilter_save_load() {
-        let mut bf = BloomFilter::with_capacity_p(1024, 0.01);
+    /// Approximate heap and inline memory used by the filter.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.filter.as_bytes().len()
+            + self.dirty.as_bytes().len()
+            + self.changed.len() * std::mem::size_of::<u64>()
+    }
 
-        for i in 0..512 {
-            assert_eq!(true, bf.insert(i));
-        }
+    /// Whether there are changes not yet saved.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.as_bytes().iter().any(|b| *b != 0)
+    }
 
-        bf.save("test.bf").unwrap();
+    /// Number of pages changed since the last save.
+    pub fn dirty_pages(&self) -> usize {
+        self.dirty
+            .as_bytes()
+            .iter()
+            .map(|b| b.count_ones() as usize)
+            .sum()
+    }
 
-        let mut bf = BloomFilter::load("test.bf").unwrap();
-        for i in 0..512 {
-            assert_eq!(true, bf.contains(i));
-        }
+    /// Copy out each dirty page with its index, marking them clean.
+    #[cfg(feature = "flusher")]
+    fn take_dirty_pages(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let page_size = self.params.page_size as usize;
+        let bytes = self.filter.as_bytes();
+        let pages = self
+            .dirty
+            .iter()
+            .enumerate()
+            .filter(|(_, bit)| *bit)
+            .map(|(index, _)| {
+                let page = bytes[index * page_size..(index + 1) * page_size].to_vec();
+                (index, page)
+            })
+            .collect();
 
-        assert_eq!(true, bf.insert(513));
-        bf.save("test.bf").unwrap();
+        self.clear_dirty();
+        pages
+    }
 
-        let mut bf = BloomFilter::load("test.bf").unwrap();
-        for i in 0..512 {
-            assert_eq!(true, bf.contains(i));
+    /// Whether the filter is full by its fullness policy, by default once
+    /// it holds as many items as it was sized for.
+    pub fn is_full(&self) -> bool {
+        match self.fullness {
+            FullnessPolicy::Capacity => self.count >= self.params.n,
+            FullnessPolicy::Count(limit) => self.count >= limit,
+            _ => self.saturation() >= 1.0,
         }
-
-        assert_eq!(true, bf.contains(513));
+    }
+
+    /// How near the filter is to full by its fullness policy, from 0.0 when
+    /// empty to 1.0 at the threshold, beyond which it may carry on rising.
+    pub fn saturation(&self) -> f64 {
+        match self.fullness {
+            FullnessPolicy::Capacity => self.count as f64 / self.params.n as f64,
+            FullnessPolicy::Count(limit) => self.count as f64 / limit as f64,
+            FullnessPolicy::FillRatio(limit) => self.fill_ratio() / limit,
+            FullnessPolicy::FalsePositiveRate(limit) => {
+                self.effective_false_positive_rate() / limit
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Number of items in the filter: those inserted which weren't already
+    /// present, so an underestimate by any which were false positives.
+    /// Filters loaded from files which don't record this estimate it from the
+    /// bits set.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Items which can still be inserted before the filter reaches its
+    /// capacity, for deciding when to rotate or grow.
+    pub fn remaining(&self) -> u64 {
+        self.params.n.saturating_sub(self.count)
+    }
+
+    /// Remove every item, keeping the filter's storage and settings.  Every
+    /// page is marked dirty, so the next `save` empties the file too.
+    pub fn clear(&mut self) {
+        set_all(&mut self.filter, false);
+        self.count = 0;
+        self.mark_all_dirty();
+    }
+}
+
+/// The params of a plain filter saved with `header`.
+fn header_params(header: &FileHeader) -> io::Result<BloomFilterParams> {
+    if !header.is_plain() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a plain bloom filter",
+        ));
+    }
+
+    let layout = match (header.is_partitioned(), header.is_blocked()) {
+        (false, false) => Layout::Paged,
+        (true, false) => Layout::Partitioned,
+        (false, true) => Layout::Blocked,
+        (true, true) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "conflicting layout flags",
+            ))
+        }
+    };
+
+    let algorithm = header.hash_algorithm();
+    if !algorithm.is_available() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "filter hashes items with {}, not enabled in this build",
+                algorithm
+            ),
+        ));
+    }
+
+    let params = BloomFilterParamsBuilder::default()
+        .capacity(header.n)
+        .bits(header.m)
+        .hashes(header.k)
+        .layout(layout)
+        .page_size(header.page_size())
+        .hash_key(header.hash_key().0, header.hash_key().1)
+        .hash_algorithm(algorithm)
+        .to_params()
+        .map_err(|e| format::invalid(&e.to_string()))?;
+
+    // probe_bits relies on every probe landing within m
+    let page_bits = u64::from(params.page_size) * 8;
+    if params.m % page_bits != 0 {
+        return Err(format::invalid("size is not a whole number of pages"));
+    }
+    if params.layout == Layout::Partitioned && u64::from(params.k) > params.m {
+        return Err(format::invalid("more hashes than bits to partition"));
+    }
+
+    Ok(params)
+}
+
+/// The params of a filter `from_reader` can load from a file with `header`,
+/// built with the hash key `key`.
+fn loadable_params(header: &FileHeader, key: (u64, u64)) -> io::Result<BloomFilterParams> {
+    if header.is_delta() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a delta can only be applied to a filter",
+        ));
+    }
+    if header.is_segmented() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "segmented filters must be loaded with load_segmented",
+        ));
+    }
+    if header.is_encrypted() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "encrypted filters must be loaded with load_encrypted",
+        ));
+    }
+
+    let params = header_params(header)?;
+    check_hash_key(&params, key)?;
+    Ok(params)
+}
+
+/// Refuse a filter whose items aren't hashed as `BloomHash::from` does, for
+/// readers which don't offer the filter's own `hash`.
+fn check_plain_hashing(params: &BloomFilterParams) -> io::Result<()> {
+    check_hash_key(params, (0, 0))?;
+    if params.hash_algorithm != HashAlgorithm::SipHash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "filter was built with a different hash algorithm",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Refuse a filter built with a hash key other than the one its items will be
+/// hashed with, whose answers would be silently wrong.
+fn check_hash_key(params: &BloomFilterParams, key: (u64, u64)) -> io::Result<()> {
+    if params.hash_key != key {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "filter was built with a different hash key",
+        ));
+    }
+
+    Ok(())
+}
+
+/// The bit index of each of an item's k probes in a filter of `pages` pages,
+/// moved `shift` bits along within the segment each falls in.
+fn probe_bits(
+    params: &BloomFilterParams,
+    pages: u64,
+    hash: BloomHash,
+    shift: u64,
+) -> impl Iterator<Item = u64> {
+    let page = if pages > 0 {
+        hash.nth(params.k + 1) % pages
+    } else {
+        0
+    };
+
+    // probe i lands at offset + i * stride, plus up to segment bits
+    let page_bits = u64::from(params.page_size) * 8;
+    let (offset, stride, segment) = match params.layout {
+        Layout::Paged => (page * page_bits, 0, page_bits),
+        Layout::Blocked => {
+            // the probes' low bits follow from the block's if it's picked
+            // straight from the same double hash, so mix it first
+            let blocks = params.m / BLOOM_BLOCK_BIT_SIZE;
+            let block = xor::mix(hash.nth(params.k + 1), 0) % blocks;
+            (block * BLOOM_BLOCK_BIT_SIZE, 0, BLOOM_BLOCK_BIT_SIZE)
+        }
+        Layout::Partitioned => {
+            let segment = params.m / u64::from(params.k);
+            (0, segment, segment)
+        }
+    };
+
+    assert!(offset + u64::from(params.k - 1) * stride + segment <= params.m);
+
+    (0..params.k)
+        .map(move |k| offset + u64::from(k) * stride + (hash.nth(k) % segment + shift) % segment)
+}
+
+/// Estimated number of items in a filter with `ones` bits set.
+fn estimate_count(params: &BloomFilterParams, ones: u64) -> u64 {
+    -((params.m as f64 / f64::from(params.k)) * (1.0 - (ones as f64 / params.m as f64)).ln()) as u64
+}
+
+/// Set every bit in a `BitVec`, filling whole bytes in bulk.
+fn set_all(bits: &mut BitVec, value: bool) {
+    let whole = bits.len() / 8;
+    let byte = if value { 0xff } else { 0 };
+
+    bits.with_bytes_mut(|buf| buf[..whole].fill(byte));
+
+    for i in whole * 8..bits.len() {
+        bits.set(i, value);
+    }
+}
+
+/// bitvec-rs doesn't document which end of a byte holds its lowest bit, so
+/// ask it, rather than risk disagreeing with files saved through it.
+fn lsb_first() -> bool {
+    let mut bits = BitVec::from_elem(8, false);
+    bits.set(0, true);
+    bits.as_bytes()[0] == 1
+}
+
+impl From<BloomFilterParams> for BloomFilter {
+    fn from(p: BloomFilterParams) -> Self {
+        Self::from_params(p)
+    }
+}
+
+/// Insert every item, hashed with the filter's key.
+impl<T: Hash> Extend<T> for BloomFilter {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            let hash = self.hash(item);
+            self.insert_hash(&hash);
+        }
+    }
+}
+
+/// A filter sized to hold exactly the items collected, with a 1%
+/// false-positive rate.  The items are gathered up front to count them, so
+/// for other sizes or rates, or to avoid holding every item at once, build
+/// the filter first and `extend` it.
+impl<T: Hash> FromIterator<T> for BloomFilter {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let items: Vec<T> = iter.into_iter().collect();
+
+        let mut ret = Self::with_capacity_p((items.len() as u64).max(1), COLLECT_P);
+        ret.extend(items);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use siphasher::sip128::SipHasher;
+
+    use super::*;
+
+    #[test]
+    fn bloomfilter_looks_reasonable() {
+        let mut bf = BloomFilter::with_capacity_p(400, 0.01);
+
+        assert_eq!(false, bf.contains("meep"));
+        assert_eq!(false, bf.contains(BloomHash::from("meep")));
+
+        assert_eq!(true, bf.insert("meep"));
+        assert_eq!(false, bf.insert("meep"));
+        assert_eq!(true, bf.contains(BloomHash::from("meep")));
+
+        assert_eq!(true, bf.insert("moop"));
+        assert_eq!(false, bf.insert("moop"));
+        assert_eq!(true, bf.contains(BloomHash::from("moop")));
+        assert_eq!(2, bf.count_estimate());
+    }
+
+    #[test]
+    fn bloomfilter_save_load() {
+        let mut bf = BloomFilter::with_capacity_p(1024, 0.01);
+
+        for i in 0..512 {
+            assert_eq!(true, bf.insert(i));
+        }
+
+        bf.save("test.bf").unwrap();
+
+        let mut bf = BloomFilter::load("test.bf").unwrap();
+        for i in 0..512 {
+            assert_eq!(true, bf.contains(i));
+        }
+
+        assert_eq!(true, bf.insert(513));
+        bf.save("test.bf").unwrap();
+
+        let bf = BloomFilter::load("test.bf").unwrap();
+        for i in 0..512 {
+            assert_eq!(true, bf.contains(i));
+        }
+
+        assert_eq!(true, bf.contains(513));
+    }
+
+    #[test]
+    fn bloomfilter_from_reader_contents() {
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+
+        for i in 0..100_000 {
+            bf.insert(i);
+        }
+
+        let mut buf = vec![];
+        bf.file_header().write(&mut buf).unwrap();
+        buf.extend_from_slice(bf.filter.as_bytes());
+
+        let loaded = BloomFilter::from_reader(&buf[..]).unwrap();
+        let expected = BitVec::from_bytes(&buf[format::HEADER_SIZE..]);
+        assert_eq!(expected, loaded.filter);
+        assert_eq!(bf.filter, loaded.filter);
+    }
+
+    #[test]
+    fn bloomfilter_write_to() {
+        let path = "test_write_to.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        for i in 0..10_000u32 {
+            bf.insert(i);
+        }
+
+        let mut buf = vec![];
+        bf.write_to(&mut buf).unwrap();
+        assert!(bf.is_dirty());
+
+        // the same bytes a fresh save writes, once it's the same generation
+        bf.save(path).unwrap();
+        buf.clear();
+        bf.write_to(&mut buf).unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), buf);
+
+        let loaded = BloomFilter::from_reader(&buf[..]).unwrap();
+        assert!((0..10_000u32).all(|i| loaded.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_contains_with_confidence() {
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        let mut last = 0.0;
+
+        assert_eq!((false, 0.0), bf.contains_with_confidence("meep"));
+
+        for level in 1..=4 {
+            for i in 0..(level * 5000) {
+                bf.insert(i);
+            }
+
+            let (found, p) = bf.contains_with_confidence(0);
+            assert!(found);
+            assert_eq!(bf.current_false_positive_rate(), p);
+            assert!(p > last);
+            last = p;
+
+            assert_eq!((false, 0.0), bf.contains_with_confidence("meep"));
+        }
+    }
+
+    #[test]
+    fn bloomfilter_set_all() {
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+
+        for i in 0..10_000 {
+            bf.insert(i);
+        }
+
+        assert!(bf.count_ones() > 0);
+        set_all(&mut bf.filter, false);
+        assert_eq!(0, bf.count_ones());
+
+        let mut bits = BitVec::from_elem(13, false);
+        set_all(&mut bits, true);
+        assert!(bits.iter().all(|b| b));
+        assert_eq!(13, bits.iter().count());
+        set_all(&mut bits, false);
+        assert!(bits.iter().all(|b| !b));
+    }
+
+    #[test]
+    fn bloomfilter_save_clears_dirty() {
+        let path = "test_clears_dirty.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        let saves: [fn(&mut BloomFilter, &str) -> io::Result<()>; 4] = [
+            |bf, path| bf.save(path),
+            |bf, path| bf.compact(path),
+            // a sparse file is rewritten in full
+            |bf, path| bf.save(path),
+            |bf, path| bf.save_atomic(path),
+        ];
+
+        for (i, save) in saves.iter().enumerate() {
+            bf.insert(i);
+            assert!(bf.is_dirty());
+            save(&mut bf, path).unwrap();
+            assert!(!bf.is_dirty());
+        }
+
+        // as is one holding a different filter
+        let mut other = BloomFilter::with_capacity_p(1000, 0.01);
+        other.insert(1);
+        other.save(path).unwrap();
+        assert!(!other.is_dirty());
+        assert!(BloomFilter::load(path).unwrap().contains(1));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomhash_from_hasher() {
+        let a = SipHasher::new_with_keys(1, 2);
+        let b = SipHasher::new_with_keys(3, 4);
+
+        assert_eq!(
+            BloomHash::from_hasher("meep", &a),
+            BloomHash::from_hasher("meep", &a)
+        );
+        assert_ne!(
+            BloomHash::from_hasher("meep", &a),
+            BloomHash::from_hasher("meep", &b)
+        );
+        assert_eq!(
+            BloomHash::from("meep"),
+            BloomHash::from_hasher("meep", &SipHasher::new())
+        );
+    }
+
+    #[test]
+    fn bloomhash_canonical() {
+        let bytes = b"meep".to_vec();
+        let h = BloomHash::from_canonical(b"meep");
+
+        assert_eq!(h, BloomHash::from_canonical(&bytes[..]));
+        assert_eq!(h, BloomHash::from_canonical_str("meep"));
+        assert_eq!(h, BloomHash::from_canonical_str(&String::from("meep")));
+        assert_ne!(h, BloomHash::from("meep"));
+
+        assert_eq!(
+            BloomHash::from_canonical_u64(42),
+            BloomHash::from_canonical(&42u64.to_le_bytes())
+        );
+        assert_eq!(
+            BloomHash::from_canonical_i64(-1),
+            BloomHash::from_canonical_u64(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn bloomfilter_hash_canonical() {
+        let path = "test_hash_canonical.bf";
+        let _ = std::fs::remove_file(path);
+
+        let bf = BloomFilter::with_capacity_p(100, 0.01);
+        assert_eq!(
+            BloomHash::from_canonical(b"meep"),
+            bf.hash_canonical(b"meep")
+        );
+
+        // canonical hashes use a filter's key, so survive saving it
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(100)
+            .false_positives(0.01)
+            .hash_key(1, 2)
+            .to_params()
+            .unwrap();
+        let mut bf = BloomFilter::from_params(params);
+        let hash = bf.hash_canonical(b"meep");
+        assert_ne!(BloomHash::from_canonical(b"meep"), hash);
+        bf.insert_hash(&hash);
+        bf.save(path).unwrap();
+
+        let loaded = BloomFilter::load_with_key(path, (1, 2)).unwrap();
+        assert!(loaded.contains_hash(&loaded.hash_canonical(b"meep")));
+        assert!(!loaded.contains_hash(&loaded.hash_canonical(b"moop")));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_requested_p() {
+        let bf = BloomFilter::with_capacity_p(100, 0.01);
+
+        // rounding up to a whole page grows capacity rather than improving p,
+        // so the achieved rate only differs by rounding of n and k
+        assert_eq!(Some(0.01), bf.params.requested_p);
+        assert!(bf.params.n > 100);
+        assert!((bf.params.p - 0.01).abs() < 0.001);
+    }
+
+    #[test]
+    fn bloomfilter_save_short_file() {
+        let path = "test_short.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1024, 0.01);
+        for i in 0..512 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        // as if a full write was interrupted after the header
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(format::HEADER_SIZE as u64)
+            .unwrap();
+        assert!(BloomFilter::load(path).is_err());
+
+        bf.insert(1000);
+        bf.save(path).unwrap();
+
+        let bf = BloomFilter::load(path).unwrap();
+        for i in 0..512 {
+            assert!(bf.contains(i));
+        }
+        assert!(bf.contains(1000));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_hash_methods() {
+        let mut a = BloomFilter::with_capacity_p(1024, 0.01);
+        let mut b = BloomFilter::with_capacity_p(1024, 0.01);
+
+        for i in 0..512 {
+            let hash = BloomHash::from(i);
+            assert_eq!(a.insert(hash), b.insert_hash(&hash));
+            assert!(b.contains_hash(&hash));
+        }
+
+        assert_eq!(a.filter, b.filter);
+        assert!(!b.contains_hash(&BloomHash::from("meep")));
+    }
+
+    #[test]
+    fn bloomfilter_compact() {
+        let path = "test_compact.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+        for i in 0..4 {
+            bf.insert(i);
+        }
+
+        bf.save(path).unwrap();
+        let full = std::fs::metadata(path).unwrap().len();
+
+        bf.compact(path).unwrap();
+        let compacted = std::fs::metadata(path).unwrap().len();
+        assert!(compacted <= u64::from(BLOOM_PAGE_SIZE) * 5);
+        assert!(compacted < full / 10);
+
+        let mut bf = BloomFilter::load(path).unwrap();
+        for i in 0..4 {
+            assert!(bf.contains(i));
+        }
+        assert!(!bf.contains(4));
+
+        bf.insert(4);
+        bf.save(path).unwrap();
+        assert!(std::fs::metadata(path).unwrap().len() < full / 10);
+
+        let bf = BloomFilter::load(path).unwrap();
+        for i in 0..5 {
+            assert!(bf.contains(i));
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bloomfilter_save_sparse_file() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = "test_sparse_file.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(10_000_000, 0.001);
+        bf.insert(1);
+        bf.save(path).unwrap();
+
+        let meta = std::fs::metadata(path).unwrap();
+        assert_eq!(
+            (format::HEADER_SIZE + bf.filter.as_bytes().len()) as u64,
+            meta.len()
+        );
+        assert!(meta.blocks() * 512 < meta.len() / 4);
+
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!(loaded.contains(1));
+        assert_eq!(bf.digest(), loaded.digest());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_page_size() {
+        let path = "test_page_size.bf";
+        let _ = std::fs::remove_file(path);
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(100)
+            .false_positives(0.01)
+            .page_size(512)
+            .to_params()
+            .unwrap();
+        let mut bf = BloomFilter::from_params(params);
+        assert_eq!(512, bf.page_size());
+        assert_eq!(512 * 8, bf.params.m);
+        for i in 0..100u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+        assert_eq!(
+            (format::HEADER_SIZE + 512) as u64,
+            std::fs::metadata(path).unwrap().len()
+        );
+
+        let mut loaded = BloomFilter::load(path).unwrap();
+        assert_eq!(512, loaded.page_size());
+        assert!((0..100u32).all(|i| loaded.contains(i)));
+
+        // dirty pages are written back at their own size
+        loaded.insert(1000u32);
+        loaded.save(path).unwrap();
+        BloomFilter::verify(path).unwrap();
+        assert!(BloomFilter::load(path).unwrap().contains(1000u32));
+
+        // a file of the same size in other pages is replaced, not patched
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(100)
+            .bits(512 * 8)
+            .hashes(bf.params.k)
+            .page_size(256)
+            .to_params()
+            .unwrap();
+        let mut other = BloomFilter::from_params(params);
+        other.insert(2000u32);
+        other.save(path).unwrap();
+        BloomFilter::verify(path).unwrap();
+        let loaded = BloomFilter::load(path).unwrap();
+        assert_eq!(256, loaded.page_size());
+        assert!(loaded.contains(2000u32));
+        assert!(!loaded.contains(1000u32));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_generation() {
+        let path = "test_generation.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        assert_eq!(0, bf.generation());
+        bf.save(path).unwrap();
+        assert_eq!(1, bf.generation());
+
+        let mut loaded = BloomFilter::load(path).unwrap();
+        assert_eq!(1, loaded.generation());
+
+        // in place saves bump the header too, dirty pages or not
+        bf.insert(1u32);
+        bf.save(path).unwrap();
+        bf.save(path).unwrap();
+        let header = FileHeader::read(File::open(path).unwrap()).unwrap();
+        assert_eq!(3, header.generation());
+
+        loaded.save_atomic(path).unwrap();
+        assert_eq!(2, BloomFilter::load(path).unwrap().generation());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_durability() {
+        let path = "test_durability.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        assert_eq!(Durability::Full, bf.durability());
+        bf.set_durability(Durability::Unsynced);
+        bf.insert(1u32);
+        bf.save(path).unwrap();
+        bf.set_durability(Durability::Data);
+        bf.insert(2u32);
+        bf.save(path).unwrap();
+        bf.save_atomic(path).unwrap();
+
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!(loaded.contains(1u32) && loaded.contains(2u32));
+        assert_eq!(Durability::Full, loaded.durability());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_backups() {
+        let path = "test_backups.bf";
+        let backup = |n| BloomFilter::backup_path(path, n);
+        let _ = std::fs::remove_file(path);
+        for n in 1..=3 {
+            let _ = std::fs::remove_file(backup(n));
+        }
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        bf.set_backups(2);
+        for i in 1..=4u32 {
+            bf.insert(i);
+            bf.save(path).unwrap();
+        }
+
+        assert_eq!(4, BloomFilter::load(path).unwrap().generation());
+        let previous = BloomFilter::load(backup(1)).unwrap();
+        assert_eq!(3, previous.generation());
+        assert!(previous.contains(3u32) && !previous.contains(4u32));
+        assert_eq!(2, BloomFilter::load(backup(2)).unwrap().generation());
+        assert!(!backup(3).exists());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(backup(1)).unwrap();
+        std::fs::remove_file(backup(2)).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_exact_count() {
+        let path = "test_exact_count.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        let loaded = BloomFilter::load(path).unwrap();
+        assert_eq!(bf.count, loaded.count);
+        assert_eq!(bf.is_full(), loaded.is_full());
+
+        // files without a count fall back to the estimate
+        let mut header = FileHeader::read(File::open(path).unwrap()).unwrap();
+        header.remove_extension(format::EXT_COUNT);
+        let mut buf = vec![];
+        header.write(&mut buf).unwrap();
+        buf.extend_from_slice(bf.filter.as_bytes());
+        let loaded = BloomFilter::from_reader(&buf[..]).unwrap();
+        assert_eq!(bf.count_estimate(), loaded.count);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_hash_key() {
+        let path = "test_hash_key.bf";
+        let _ = std::fs::remove_file(path);
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(100)
+            .false_positives(0.01)
+            .hash_key(1, 2)
+            .to_params()
+            .unwrap();
+        let mut bf = BloomFilter::from_params(params.clone());
+        let hash = bf.hash("meep");
+        assert_ne!(hash, BloomHash::from("meep"));
+        bf.insert_hash(&hash);
+        bf.save(path).unwrap();
+
+        // an unkeyed load would hash items differently
+        assert!(BloomFilter::load(path).is_err());
+        assert!(BloomFilter::load_with_key(path, (2, 1)).is_err());
+
+        let loaded = BloomFilter::load_with_key(path, (1, 2)).unwrap();
+        assert_eq!((1, 2), loaded.hash_key());
+        assert!(loaded.contains_hash(&loaded.hash("meep")));
+        assert!(loaded.contains("meep"));
+
+        // items given directly are rehashed with the key too
+        let mut bf = BloomFilter::from_params(params);
+        bf.extend(["a"]);
+        assert!(bf.insert("b"));
+        assert_eq!(Some(false), bf.checked_insert("b"));
+        assert_eq!(1, bf.insert_many(["a", "c"]));
+        assert_eq!(vec![true, true, true], bf.contains_many(["a", "b", "c"]));
+        assert!(bf.insert(BloomHash::from("d")));
+        assert!(bf.contains_hash(&bf.hash("d")));
+        assert!(!bf.contains_hash(&BloomHash::from("d")));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bloomfilter_save_preallocated() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = "test_preallocated.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.001);
+        bf.set_preallocate(true);
+        bf.insert(1);
+        bf.save(path).unwrap();
+
+        let meta = std::fs::metadata(path).unwrap();
+        assert!(meta.blocks() * 512 >= meta.len());
+        assert!(BloomFilter::load(path).unwrap().contains(1));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_page_checksums() {
+        let path = "test_checksums.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        bf.insert(1);
+        bf.save(path).unwrap();
+
+        // checksums follow pages rewritten in place
+        bf.insert(2);
+        bf.save(path).unwrap();
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!(loaded.contains(1) && loaded.contains(2));
+        BloomFilter::verify(path).unwrap();
+
+        let header = FileHeader::read(File::open(path).unwrap()).unwrap();
+        assert_eq!(Some(bf.digest()), header.digest);
+
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(io::SeekFrom::Start(format::HEADER_SIZE as u64 + 100))
+            .unwrap();
+        file.write_all(&[0x55]).unwrap();
+        drop(file);
+
+        let err = BloomFilter::load(path).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(matches!(LoadError::from(err), LoadError::ChecksumMismatch));
+        assert!(BloomFilter::verify(path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_load_invalid_params() {
+        let page_bits = u64::from(format::PAGE_SIZE) * 8;
+        let read = |header: FileHeader| {
+            let mut buf = vec![];
+            header.write(&mut buf).unwrap();
+            buf.resize(buf.len() + (header.m / 8) as usize, 0);
+            BloomFilter::from_reader(&buf[..])
+        };
+
+        let mut partitioned = FileHeader::new(100, page_bits, page_bits as u32 + 1);
+        partitioned.flags |= format::FLAG_PARTITIONED;
+        for header in [
+            FileHeader::new(0, page_bits, 7),
+            FileHeader::new(100, 0, 7),
+            FileHeader::new(100, page_bits, 0),
+            FileHeader::new(100, 512, 7),
+            FileHeader::new(100, page_bits + 512, 7),
+            partitioned,
+        ] {
+            let err = read(header).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            assert!(matches!(LoadError::from(err), LoadError::InvalidHeader(_)));
+        }
+
+        assert!(read(FileHeader::new(100, page_bits, 7)).is_ok());
+    }
+
+    #[test]
+    fn bloomfilter_save_atomic() {
+        let path = "test_atomic.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        bf.insert(1);
+        bf.save_atomic(path).unwrap();
+        bf.insert(2);
+        bf.save_atomic(path).unwrap();
+        assert!(!bf.is_dirty());
+        assert!(!Path::new("test_atomic.bf.tmp").exists());
+
+        let bf = BloomFilter::load(path).unwrap();
+        assert!(bf.contains(1) && bf.contains(2));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_partitioned() {
+        let path = "test_partitioned.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        bf.insert(0);
+        bf.save(path).unwrap();
+
+        let params = BloomFilterParams::with_capacity_p(100_000, 0.01);
+        let mut bf = BloomFilter::with_layout(params, Layout::Partitioned);
+        for i in 0..50_000 {
+            bf.insert(i);
+        }
+        assert!((0..50_000).all(|i| bf.contains(i)));
+        assert!((50_000..60_000).filter(|i| bf.contains(*i)).count() < 100);
+
+        // a paged file of the same size must be rewritten, not patched
+        bf.save(path).unwrap();
+        let header = FileHeader::read(File::open(path).unwrap()).unwrap();
+        assert!(header.is_partitioned());
+
+        let bf = BloomFilter::load(path).unwrap();
+        assert_eq!(Layout::Partitioned, bf.layout());
+        assert!((0..50_000).all(|i| bf.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_blocked() {
+        let path = "test_blocked.bf";
+        let _ = std::fs::remove_file(path);
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(100_000)
+            .false_positives(0.01)
+            .layout(Layout::Blocked)
+            .to_params()
+            .unwrap();
+        let mut bf = BloomFilter::from_params(params);
+        assert_eq!(Layout::Blocked, bf.layout());
+
+        for i in 0..50_000 {
+            bf.insert(i);
+        }
+        assert!((0..50_000).all(|i| bf.contains(i)));
+        assert!((50_000..60_000).filter(|i| bf.contains(*i)).count() < 100);
+
+        // every probe within one cache line
+        for i in 0..100 {
+            let bits: Vec<u64> = bf.shifted_bits(BloomHash::from(i), 0).collect();
+            let block = bits[0] / BLOOM_BLOCK_BIT_SIZE;
+            assert!(bits.iter().all(|bit| bit / BLOOM_BLOCK_BIT_SIZE == block));
+        }
+
+        bf.save(path).unwrap();
+        let header = FileHeader::read(File::open(path).unwrap()).unwrap();
+        assert!(header.is_blocked());
+
+        let bf = BloomFilter::load(path).unwrap();
+        assert_eq!(Layout::Blocked, bf.layout());
+        assert!((0..50_000).all(|i| bf.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
     }
 
     #[test]
@@ -315,4 +2018,246 @@ mod tests {
 
         assert!(found < ((lim as f32) * 0.01) as u32);
     }
+
+    #[test]
+    fn bloomfilter_shared_reads() {
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+
+        let bf = Arc::new(bf);
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let bf = Arc::clone(&bf);
+                std::thread::spawn(move || (0..1000u32).all(|i| bf.contains(i)))
+            })
+            .collect();
+        assert!(readers.into_iter().all(|r| r.join().unwrap()));
+        assert!(bf.count_estimate() > 900);
+    }
+
+    #[test]
+    fn bloomfilter_clear() {
+        let path = "test_clear.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        bf.clear();
+        assert!(bf.is_empty());
+        assert_eq!(0, bf.count_estimate());
+        assert!(!bf.contains(0u32));
+        assert!(bf.is_dirty());
+
+        bf.save(path).unwrap();
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!(loaded.is_empty());
+        assert!(!(0..1000u32).any(|i| loaded.contains(i)));
+
+        // and it can be filled again
+        bf.insert(5u32);
+        assert!(bf.contains(5u32));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_collect() {
+        let bf: BloomFilter = (0..1000u32).collect();
+        assert!(bf.params.n >= 1000);
+        assert!((0..1000u32).all(|i| bf.contains(i)));
+
+        // the same bits as extending a filter of the same size
+        let mut extended = BloomFilter::with_capacity_p(1000, COLLECT_P);
+        extended.extend(0..1000u32);
+        assert_eq!(extended.filter, bf.filter);
+
+        let empty: BloomFilter = std::iter::empty::<u32>().collect();
+        assert!(empty.is_empty());
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(1000)
+            .false_positives(0.01)
+            .hash_key(1, 2)
+            .to_params()
+            .unwrap();
+        let mut keyed = BloomFilter::from_params(params);
+        keyed.extend(["meep", "moop"].iter());
+        assert!(keyed.contains_hash(&keyed.hash("meep")));
+        assert!(keyed.contains_hash(&keyed.hash("moop")));
+        assert_eq!(2, keyed.count);
+    }
+
+    #[test]
+    fn bloomfilter_insert_many() {
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.001);
+        assert_eq!(1000, bf.insert_many(0..1000u32));
+        assert_eq!(bf.count, 1000);
+        assert!((0..1000u32).all(|i| bf.contains(i)));
+
+        // only new items count
+        assert_eq!(500, bf.insert_many(500..1500u32));
+        assert_eq!(0, bf.insert_many(vec![BloomHash::from(1u32)]));
+    }
+
+    #[test]
+    fn bloomfilter_contains_many() {
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.001);
+        bf.insert_many((0..1000u32).step_by(2));
+
+        let found = bf.contains_many(0..1000u32);
+        assert_eq!(1000, found.len());
+        assert!(found.iter().step_by(2).all(|f| *f));
+        assert!(found.iter().skip(1).step_by(2).filter(|f| **f).count() < 10);
+        assert!(bf.contains_many(Vec::<u32>::new()).is_empty());
+    }
+
+    #[test]
+    fn bloomfilter_fill_ratio() {
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        assert_eq!(0.0, bf.fill_ratio());
+
+        bf.insert_many(0..bf.params.n);
+        assert!((bf.fill_ratio() - 0.5).abs() < 0.05, "{}", bf.fill_ratio());
+
+        bf.insert_many(bf.params.n..bf.params.n * 10);
+        assert!(bf.fill_ratio() > 0.95);
+    }
+
+    #[test]
+    fn bloomfilter_effective_false_positive_rate() {
+        for layout in [Layout::Paged, Layout::Partitioned, Layout::Blocked] {
+            let params = BloomFilterParamsBuilder::default()
+                .capacity(10_000)
+                .false_positives(0.01)
+                .layout(layout)
+                .to_params()
+                .unwrap();
+            let mut bf = BloomFilter::from_params(params);
+            assert_eq!(0.0, bf.effective_false_positive_rate());
+
+            bf.insert_many(0..bf.params.n);
+            let rate = bf.effective_false_positive_rate();
+            assert!(rate > 0.002 && rate < 0.03, "{:?} {}", layout, rate);
+
+            // measured against items never inserted
+            let hits = bf.contains_many(1_000_000..1_100_000u32);
+            let measured = hits.iter().filter(|h| **h).count() as f64 / hits.len() as f64;
+            assert!(
+                (measured - rate).abs() < rate * 0.3,
+                "{:?} {} {}",
+                layout,
+                rate,
+                measured
+            );
+
+            // an overloaded filter shows it
+            bf.insert_many(bf.params.n..bf.params.n * 3);
+            assert!(bf.effective_false_positive_rate() > 0.1);
+        }
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        bf.insert_many(0..500u32);
+        for (start, end) in [(3, 3), (3, 5), (3, 29), (8, 64), (13, 1000)] {
+            let naive = (start..end)
+                .filter(|bit| bf.filter.get(*bit as usize).unwrap())
+                .count() as u64;
+            assert_eq!(naive, bf.ones_between(start, end));
+        }
+    }
+
+    #[test]
+    fn bloomfilter_params_getters() {
+        let path = "test_params_getters.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        bf.save(path).unwrap();
+        assert!(bf.capacity() >= 10_000);
+        assert_eq!(Some(0.01), bf.params().requested_p);
+
+        // all but the rate originally asked for survive a save
+        let loaded = BloomFilter::load(path).unwrap();
+        assert_eq!(bf.capacity(), loaded.capacity());
+        assert_eq!(bf.bits(), loaded.bits());
+        assert_eq!(bf.hashes(), loaded.hashes());
+        assert_eq!(bf.params().p, loaded.params().p);
+        assert_eq!(loaded.params().m, loaded.bits());
+        assert_eq!(loaded.params().k, loaded.hashes());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bloomfilter_fullness_policy() {
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        assert_eq!(FullnessPolicy::Capacity, bf.fullness_policy());
+        assert_eq!(0.0, bf.saturation());
+
+        bf.set_fullness_policy(FullnessPolicy::Count(100));
+        bf.insert_many(0..99u32);
+        assert_eq!(Some(true), bf.checked_insert(99u32));
+        assert!(bf.is_full());
+        assert_eq!(1.0, bf.saturation());
+        assert_eq!(None, bf.checked_insert(100u32));
+
+        bf.set_fullness_policy(FullnessPolicy::FillRatio(0.1));
+        assert!(!bf.is_full());
+        let mut i = 100u32;
+        while bf.checked_insert(i).is_some() {
+            i += 1;
+        }
+        assert!(bf.fill_ratio() >= 0.1);
+        assert!(bf.saturation() >= 1.0);
+
+        bf.set_fullness_policy(FullnessPolicy::FalsePositiveRate(0.01));
+        while bf.checked_insert(i).is_some() {
+            i += 1;
+        }
+        assert!(bf.effective_false_positive_rate() >= 0.01);
+        assert!(bf.is_full());
+
+        // the policy carries over to an empty copy
+        assert!(!bf.empty_like().is_full());
+        assert_eq!(bf.fullness_policy(), bf.empty_like().fullness_policy());
+    }
+
+    #[test]
+    fn bloomfilter_len_remaining() {
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        let capacity = bf.capacity();
+        assert_eq!(0, bf.len());
+        assert_eq!(capacity, bf.remaining());
+
+        bf.insert_many(0..100u32);
+        bf.insert(0u32);
+        assert_eq!(100, bf.len());
+        assert_eq!(capacity - 100, bf.remaining());
+
+        bf.insert_many(100..capacity + 100);
+        assert!(bf.is_full());
+        assert_eq!(0, bf.remaining());
+        assert!(bf.len() <= capacity + 100);
+
+        bf.clear();
+        assert_eq!(capacity, bf.remaining());
+    }
+
+    #[test]
+    fn bloomhash_parts() {
+        let hash = BloomHash::from("meep");
+        assert_eq!(hash, BloomHash::from_parts(hash.h1(), hash.h2()));
+
+        let raw = u128::from(hash);
+        assert_eq!(hash.h1(), (raw >> 64) as u64);
+        assert_eq!(hash, BloomHash::from_u128(raw));
+
+        // distinct from hashing the integer itself
+        assert_ne!(hash, BloomHash::from(raw));
+    }
 }
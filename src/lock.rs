@@ -0,0 +1,151 @@
+/// Advisory locks on saved filters, so processes sharing a file don't save
+/// over each other, or load it half way through another's save.
+///
+/// The lock is taken on a file beside the filter with ".lock" appended to
+/// its name, rather than the filter itself, so it holds across saves which
+/// replace the file.  Like any advisory lock it only excludes processes
+/// which also take it.  The lock file is left in place afterwards, as
+/// removing it could let two processes each lock a different one.
+///
+/// Available on Linux, using flock.
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::BloomFilter;
+
+/// An exclusive lock on a filter's path, released when dropped.
+#[derive(Debug)]
+pub struct FilterLock {
+    file: File,
+    path: PathBuf,
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+impl FilterLock {
+    fn acquire(path: &Path, wait: bool) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path(path))?;
+
+        Self::lock_file(&file, wait).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} is locked by another process", path.display()),
+            ),
+            _ => e,
+        })?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn lock_file(file: &File, wait: bool) -> io::Result<()> {
+        if wait {
+            flock(file, libc::LOCK_EX)
+        } else {
+            flock(file, libc::LOCK_EX | libc::LOCK_NB)
+        }
+    }
+
+    /// The path of the locked filter.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FilterLock {
+    fn drop(&mut self) {
+        // closing the file releases the lock anyway, but not if it's been
+        // inherited by a child process
+        let _ = flock(&self.file, libc::LOCK_UN);
+    }
+}
+
+impl BloomFilter {
+    /// Lock the filter at `path`, waiting for any other process holding the
+    /// lock to release it.
+    pub fn lock<P: AsRef<Path>>(path: P) -> io::Result<FilterLock> {
+        FilterLock::acquire(path.as_ref(), true)
+    }
+
+    /// Lock the filter at `path`, failing with `WouldBlock` if another
+    /// process holds the lock.
+    pub fn try_lock<P: AsRef<Path>>(path: P) -> io::Result<FilterLock> {
+        FilterLock::acquire(path.as_ref(), false)
+    }
+
+    /// `save` while holding the lock on `path`.
+    pub fn save_locked<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let _lock = Self::lock(path.as_ref())?;
+        self.save(path)
+    }
+
+    /// `load` while holding the lock on `path`, which is exclusive as
+    /// loading can save the filter while replaying a write log.
+    pub fn load_locked<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let _lock = Self::lock(path.as_ref())?;
+        Self::load(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomfilter_lock() {
+        let path = Path::new("test_lock.bf");
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(1000, 0.01);
+        bf.insert(1u32);
+        bf.save_locked(path).unwrap();
+
+        let lock = BloomFilter::try_lock(path).unwrap();
+        assert_eq!(path, lock.path());
+        let err = BloomFilter::try_lock(path).unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+        assert!(err.to_string().contains("locked by another process"));
+
+        // a waiting save goes ahead once the lock is released
+        let saver = std::thread::spawn(move || {
+            bf.insert(2u32);
+            bf.save_locked(path).unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!BloomFilter::load(path).unwrap().contains(2u32));
+        drop(lock);
+        saver.join().unwrap();
+
+        assert!(BloomFilter::load_locked(path).unwrap().contains(2u32));
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(lock_path(path)).unwrap();
+    }
+}
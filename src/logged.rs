@@ -0,0 +1,489 @@
+/// A `BloomFilter` whose inserts are appended to a write-ahead log of hashes,
+/// rather than rewriting filter pages each time it's saved.
+///
+/// Each new item costs a 16-byte log entry: its `BloomHash` as two
+/// big-endian u64s.  Once the log grows past its limit it's compacted, the
+/// filter being saved alongside it and the log emptied.  On opening, any
+/// entries left in the log are replayed into the filter, so nothing logged
+/// is lost if compaction is interrupted; replaying an entry already in the
+/// filter is harmless.  A partial entry at the end of the log, from a crash
+/// mid-append, is ignored.
+///
+/// The log lives next to the filter, with ".log" appended to its name.
+/// `BloomFilter::load` looks for it too, folding any entries into the filter
+/// it loads and saving that before emptying the log, so inserts logged
+/// before a crash survive however the filter is next opened.
+///
+/// A `GroupCommitBloomFilter` shares one among threads whose inserts must be
+/// durable before they return.  Rather than each syncing the log itself, the
+/// first to need a sync waits briefly for others to append, then syncs once
+/// for all of them.
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::{BloomFilter, BloomFilterParams, BloomHash, Durability};
+
+const LOG_ENTRY_SIZE: u64 = 16;
+
+/// Default log limit, after which it's compacted into the filter.
+pub const DEFAULT_LOG_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct LoggedBloomFilter {
+    filter: BloomFilter,
+    path: PathBuf,
+    log: File,
+    log_len: u64,
+    log_limit: u64,
+    append_durability: Durability,
+}
+
+fn log_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".log");
+    PathBuf::from(name)
+}
+
+/// Insert every whole entry of `log` into `filter`, returning the length of
+/// those entries and of the whole log, which may end in a torn one.
+fn replay(log: &mut File, filter: &mut BloomFilter) -> io::Result<(u64, u64)> {
+    let mut buf = vec![];
+    log.read_to_end(&mut buf)?;
+    for entry in buf.chunks_exact(LOG_ENTRY_SIZE as usize) {
+        filter.insert_hash(&BloomHash::from_u128(u128::from_be_bytes(
+            entry.try_into().unwrap(),
+        )));
+    }
+
+    let len = buf.len() as u64;
+    Ok((len / LOG_ENTRY_SIZE * LOG_ENTRY_SIZE, len))
+}
+
+/// Fold any log left beside the filter at `path` into `filter`, just loaded
+/// from it, then save the filter and empty the log.
+pub(crate) fn recover(path: &Path, filter: &mut BloomFilter) -> io::Result<()> {
+    let mut log = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(log_path(path))
+    {
+        Ok(log) => log,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let (entries, len) = replay(&mut log, filter)?;
+    if entries > 0 {
+        filter.save(path)?;
+    }
+    if len > 0 {
+        log.set_len(0)?;
+        log.sync_all()?;
+    }
+    Ok(())
+}
+
+impl LoggedBloomFilter {
+    /// Open the filter at `path` and replay its log, or create a new one with
+    /// `params` if there's no filter there yet.
+    pub fn open<P: AsRef<Path>>(path: P, params: BloomFilterParams) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        // the log is replayed below rather than folded in by load
+        let mut filter = match File::open(&path).and_then(BloomFilter::from_reader) {
+            Ok(filter) => filter,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut filter = BloomFilter::from_params(params);
+                filter.save(&path)?;
+                filter
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(log_path(&path))?;
+
+        // drop any torn entry so later appends stay aligned
+        let (log_len, len) = replay(&mut log, &mut filter)?;
+        if log_len != len {
+            log.set_len(log_len)?;
+        }
+
+        Ok(Self {
+            filter,
+            path,
+            log,
+            log_len,
+            log_limit: DEFAULT_LOG_LIMIT,
+            append_durability: Durability::Unsynced,
+        })
+    }
+
+    pub fn log_limit(&self) -> u64 {
+        self.log_limit
+    }
+
+    /// Set the log size in bytes beyond which it's compacted.  Smaller limits
+    /// make reopening faster, larger ones make inserting cheaper.
+    pub fn set_log_limit(&mut self, bytes: u64) {
+        self.log_limit = bytes.max(LOG_ENTRY_SIZE);
+    }
+
+    pub fn append_durability(&self) -> Durability {
+        self.append_durability
+    }
+
+    /// How each insert syncs its log entry, by default not at all, leaving
+    /// it to `sync`.
+    pub fn set_append_durability(&mut self, durability: Durability) {
+        self.append_durability = durability;
+    }
+
+    /// Current size of the log in bytes.
+    pub fn log_len(&self) -> u64 {
+        self.log_len
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.filter.contains(item)
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.filter.contains_hash(hash)
+    }
+
+    /// Insert an item, logging it if it's new.  The entry is synced as
+    /// `append_durability` says.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> io::Result<bool> {
+        let hash = self.filter.rehash(item.into());
+        self.insert_hash(&hash)
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters, as
+    /// `insert` does.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> io::Result<bool> {
+        let hash = *hash;
+        if !self.filter.insert_hash(&hash) {
+            return Ok(false);
+        }
+
+        self.log.write_all(&u128::from(hash).to_be_bytes())?;
+        self.append_durability.sync(&self.log)?;
+        self.log_len += LOG_ENTRY_SIZE;
+
+        if self.log_len >= self.log_limit {
+            self.compact()?;
+        }
+
+        Ok(true)
+    }
+
+    /// Sync the log to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.log.sync_data()
+    }
+
+    /// Save the filter and empty the log.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.filter.save(&self.path)?;
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.log_len = 0;
+        Ok(())
+    }
+
+    /// The filter, including any logged items.
+    pub fn filter(&self) -> &BloomFilter {
+        &self.filter
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty()
+    }
+}
+
+/// How long a `GroupCommitBloomFilter` holds a sync open for more entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitPolicy {
+    /// Longest an insert waits for others to join its sync.
+    pub max_latency: Duration,
+    /// Number of waiting entries which prompts a sync without waiting longer.
+    pub max_batch: u64,
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        Self {
+            max_latency: Duration::from_millis(2),
+            max_batch: 256,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CommitState {
+    filter: LoggedBloomFilter,
+    /// Entries appended to the log so far.
+    appended: u64,
+    /// Entries known to be on disk.
+    synced: u64,
+    /// Whether a thread is gathering or syncing a batch.
+    syncing: bool,
+    /// The last entry of the latest batch whose sync failed, and why.
+    failed: Option<(u64, io::ErrorKind)>,
+    commits: u64,
+}
+
+#[derive(Debug)]
+pub struct GroupCommitBloomFilter {
+    state: Mutex<CommitState>,
+    wake: Condvar,
+    /// A second handle on the log, to sync without holding the lock.
+    log: File,
+    policy: CommitPolicy,
+}
+
+impl GroupCommitBloomFilter {
+    pub fn new(filter: LoggedBloomFilter, policy: CommitPolicy) -> io::Result<Self> {
+        let log = filter.log.try_clone()?;
+
+        Ok(Self {
+            state: Mutex::new(CommitState {
+                filter,
+                appended: 0,
+                synced: 0,
+                syncing: false,
+                failed: None,
+                commits: 0,
+            }),
+            wake: Condvar::new(),
+            log,
+            policy,
+        })
+    }
+
+    pub fn policy(&self) -> CommitPolicy {
+        self.policy
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.state.lock().unwrap().filter.contains(item)
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.state.lock().unwrap().filter.contains_hash(hash)
+    }
+
+    /// Insert an item, returning once it's durably logged.
+    pub fn insert<T: Into<BloomHash>>(&self, item: T) -> io::Result<bool> {
+        self.insert_with(|filter| filter.insert(item))
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters, as
+    /// `insert` does.
+    pub fn insert_hash(&self, hash: &BloomHash) -> io::Result<bool> {
+        self.insert_with(|filter| filter.insert_hash(hash))
+    }
+
+    /// Insert with `insert`, then wait for its log entry to be synced.
+    fn insert_with<F>(&self, insert: F) -> io::Result<bool>
+    where
+        F: FnOnce(&mut LoggedBloomFilter) -> io::Result<bool>,
+    {
+        let mut state = self.state.lock().unwrap();
+        if !insert(&mut state.filter)? {
+            return Ok(false);
+        }
+
+        state.appended += 1;
+        let entry = state.appended;
+        if state.filter.log_len() == 0 {
+            // compaction saved the filter, everything logged included
+            state.synced = state.appended;
+        }
+        self.wake.notify_all();
+
+        loop {
+            if state.synced >= entry {
+                return Ok(true);
+            }
+            if let Some((through, kind)) = state.failed {
+                if entry <= through {
+                    return Err(io::Error::new(kind, "group commit failed"));
+                }
+            }
+
+            if state.syncing {
+                state = self.wake.wait(state).unwrap();
+                continue;
+            }
+
+            // lead a batch, giving others a chance to join it first
+            state.syncing = true;
+            let batch = self.policy.max_batch;
+            state = self
+                .wake
+                .wait_timeout_while(state, self.policy.max_latency, |s| {
+                    s.appended - s.synced < batch
+                })
+                .unwrap()
+                .0;
+
+            let through = state.appended;
+            drop(state);
+            let result = self.log.sync_data();
+            state = self.state.lock().unwrap();
+
+            state.syncing = false;
+            state.commits += 1;
+            match result {
+                Ok(()) => state.synced = state.synced.max(through),
+                Err(e) => state.failed = Some((through, e.kind())),
+            }
+            self.wake.notify_all();
+        }
+    }
+
+    /// Number of syncs performed, each covering one or more inserts.
+    pub fn commits(&self) -> u64 {
+        self.state.lock().unwrap().commits
+    }
+
+    pub fn into_inner(self) -> LoggedBloomFilter {
+        self.state.into_inner().unwrap().filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logged_replay_compact() {
+        let path = Path::new("test_logged.bf");
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(log_path(path));
+
+        let params = BloomFilterParams::with_capacity_p(10_000, 0.01);
+        let mut lbf = LoggedBloomFilter::open(path, params.clone()).unwrap();
+        lbf.set_log_limit(LOG_ENTRY_SIZE * 100);
+
+        for i in 0..50 {
+            assert!(lbf.insert(i).unwrap());
+        }
+        assert!(!lbf.insert(0).unwrap());
+        assert!(!lbf.insert_hash(&BloomHash::from(0)).unwrap());
+        assert!(lbf.contains_hash(&BloomHash::from(49)));
+        assert_eq!(LOG_ENTRY_SIZE * 50, lbf.log_len());
+        lbf.sync().unwrap();
+        drop(lbf);
+
+        // a torn append is discarded
+        OpenOptions::new()
+            .append(true)
+            .open(log_path(path))
+            .unwrap()
+            .write_all(b"torn")
+            .unwrap();
+
+        let mut lbf = LoggedBloomFilter::open(path, params.clone()).unwrap();
+        lbf.set_log_limit(LOG_ENTRY_SIZE * 100);
+        assert_eq!(LOG_ENTRY_SIZE * 50, lbf.log_len());
+        assert!((0..50).all(|i| lbf.contains(i)));
+        assert!(BloomFilter::from_reader(File::open(path).unwrap())
+            .unwrap()
+            .is_empty());
+
+        for i in 50..100 {
+            lbf.insert(i).unwrap();
+        }
+        assert_eq!(0, lbf.log_len());
+        drop(lbf);
+
+        let bf = BloomFilter::load(path).unwrap();
+        assert!((0..100).all(|i| bf.contains(i)));
+        assert_eq!(0, std::fs::metadata(log_path(path)).unwrap().len());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(log_path(path)).unwrap();
+    }
+
+    #[test]
+    fn logged_group_commit() {
+        let path = Path::new("test_group_commit.bf");
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(log_path(path));
+
+        let params = BloomFilterParams::with_capacity_p(10_000, 0.01);
+        let lbf = LoggedBloomFilter::open(path, params.clone()).unwrap();
+        let policy = CommitPolicy {
+            max_latency: Duration::from_millis(20),
+            max_batch: 64,
+        };
+        let gc = std::sync::Arc::new(GroupCommitBloomFilter::new(lbf, policy).unwrap());
+
+        let threads: Vec<_> = (0..8u32)
+            .map(|t| {
+                let gc = std::sync::Arc::clone(&gc);
+                std::thread::spawn(move || {
+                    for i in 0..20 {
+                        assert!(gc.insert(t * 100 + i).unwrap());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(gc.contains_hash(&BloomHash::from(0u32)));
+        assert!(!gc.insert_hash(&BloomHash::from(0u32)).unwrap());
+
+        // threads shared syncs rather than each paying for their own
+        assert!(gc.commits() < 160);
+        let lbf = std::sync::Arc::try_unwrap(gc).unwrap().into_inner();
+        assert_eq!(LOG_ENTRY_SIZE * 160, lbf.log_len());
+        drop(lbf);
+
+        let lbf = LoggedBloomFilter::open(path, params).unwrap();
+        assert!((0..8u32).all(|t| (0..20).all(|i| lbf.contains(t * 100 + i))));
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(log_path(path)).unwrap();
+    }
+
+    #[test]
+    fn logged_load_recovers() {
+        let path = Path::new("test_logged_recover.bf");
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(log_path(path));
+
+        let params = BloomFilterParams::with_capacity_p(10_000, 0.01);
+        let mut lbf = LoggedBloomFilter::open(path, params).unwrap();
+        for i in 0..50 {
+            lbf.insert(i).unwrap();
+        }
+        lbf.sync().unwrap();
+        drop(lbf);
+
+        // logged inserts are folded into the file, and the log emptied
+        let bf = BloomFilter::load(path).unwrap();
+        assert!((0..50).all(|i| bf.contains(i)));
+        assert_eq!(0, std::fs::metadata(log_path(path)).unwrap().len());
+        let bf = BloomFilter::from_reader(File::open(path).unwrap()).unwrap();
+        assert!((0..50).all(|i| bf.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(log_path(path)).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+/// Filters whose bits live in a shared memory map of their file, rather than
+/// in a heap copy.
+///
+/// A `MappedBloomFilter` modifies the mapping directly on insert, and the OS
+/// writes changed pages back in its own time; `save` forces out those
+/// dirtied since the last save.  A `ReadOnlyBloomFilter` only answers
+/// queries, so any number of processes can share one copy of the filter in
+/// the page cache.
+///
+/// Only dense files of plain filters can be mapped, as sparse ones have no
+/// fixed place for each page.
+///
+/// Mappings are advised on opening that they'll be accessed at random, as
+/// probes are, so the OS doesn't waste I/O reading ahead; `advise` changes
+/// that, say to read a filter in eagerly or let the OS drop its pages.
+///
+/// Available with the `mmap` feature.
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+use memmap2::{Advice, Mmap, MmapMut, UncheckedAdvice};
+
+use crate::format::{self, FileHeader};
+use crate::paged;
+use crate::view::{bit_position, check_dense};
+use crate::{lsb_first, probe_bits, set_all, BloomFilter, BloomFilterParams, BloomHash};
+
+#[derive(Debug)]
+pub struct MappedBloomFilter {
+    params: BloomFilterParams,
+    pages: u64,
+    count: u64,
+    lsb_first: bool,
+    dirty: BitVec,
+    map: MmapMut,
+}
+
+#[derive(Debug)]
+pub struct ReadOnlyBloomFilter {
+    params: BloomFilterParams,
+    pages: u64,
+    count: u64,
+    lsb_first: bool,
+    map: Mmap,
+}
+
+/// How a mapped filter's pages are expected to be used, for the OS to plan
+/// its I/O around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapAdvice {
+    /// No particular pattern, the OS's default.
+    Normal,
+    /// Scattered accesses, so reading ahead is wasted.
+    Random,
+    /// Accesses in order, so read ahead aggressively.
+    Sequential,
+    /// The whole filter will be needed soon, so start reading it in.
+    WillNeed,
+    /// The filter won't be needed for a while, so its pages may be dropped
+    /// from memory.  Changes aren't lost; they remain to be written back.
+    DontNeed,
+    /// Back the mapping with transparent huge pages where possible, for
+    /// fewer TLB misses on large filters.  Only available on Linux.
+    HugePage,
+}
+
+fn advise(
+    advice: MapAdvice,
+    checked: impl FnOnce(Advice) -> io::Result<()>,
+    dont_need: impl FnOnce() -> io::Result<()>,
+) -> io::Result<()> {
+    match advice {
+        MapAdvice::Normal => checked(Advice::Normal),
+        MapAdvice::Random => checked(Advice::Random),
+        MapAdvice::Sequential => checked(Advice::Sequential),
+        MapAdvice::WillNeed => checked(Advice::WillNeed),
+        MapAdvice::DontNeed => dont_need(),
+        #[cfg(target_os = "linux")]
+        MapAdvice::HugePage => checked(Advice::HugePage),
+        #[cfg(not(target_os = "linux"))]
+        MapAdvice::HugePage => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "huge page advice is only available on Linux",
+        )),
+    }
+}
+
+impl BloomFilter {
+    /// Map the filter saved at `path` for reading and writing in place.
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> io::Result<MappedBloomFilter> {
+        MappedBloomFilter::open(path)
+    }
+
+    /// Map the filter saved at `path` for reading only.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<ReadOnlyBloomFilter> {
+        ReadOnlyBloomFilter::open(path)
+    }
+}
+
+impl MappedBloomFilter {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        // as with any mapping, the file mustn't be truncated while it's open
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let (params, count) = check_dense(&map)?;
+        // probes go anywhere; this is only a hint, so failing is harmless
+        let _ = map.advise(Advice::Random);
+        let pages = params.m / (u64::from(params.page_size) * 8);
+
+        Ok(Self {
+            params,
+            pages,
+            count,
+            lsb_first: lsb_first(),
+            dirty: BitVec::from_elem(pages as usize, false),
+            map,
+        })
+    }
+
+    pub fn params(&self) -> &BloomFilterParams {
+        &self.params
+    }
+
+    /// Advise the OS how the filter will be used.
+    pub fn advise(&self, advice: MapAdvice) -> io::Result<()> {
+        advise(
+            advice,
+            |advice| self.map.advise(advice),
+            // the mapping is shared, so dropping pages loses nothing
+            || unsafe { self.map.unchecked_advise(UncheckedAdvice::DontNeed) },
+        )
+    }
+
+    fn get(&self, bit: u64) -> bool {
+        let (byte, mask) = bit_position(bit, self.lsb_first);
+        self.map[byte] & mask != 0
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        probe_bits(&self.params, self.pages, *hash, 0).all(|bit| self.get(bit))
+    }
+
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let mut added = false;
+
+        for bit in probe_bits(&self.params, self.pages, *hash, 0) {
+            let (byte, mask) = bit_position(bit, self.lsb_first);
+            if self.map[byte] & mask == 0 {
+                self.map[byte] |= mask;
+                self.dirty
+                    .set((bit / u64::from(self.params.page_size * 8)) as usize, true);
+                added = true;
+            }
+        }
+
+        if added {
+            self.count += 1;
+        }
+
+        added
+    }
+
+    /// Flush pages changed since the last save to disk, along with their
+    /// checksums.
+    pub fn save(&mut self) -> io::Result<()> {
+        let pages = paged::dirty_pages(&self.dirty);
+        if !pages.is_empty() {
+            let mut header = FileHeader::from_bytes(&self.map[..format::HEADER_SIZE])?;
+            let body_len = (self.params.m / 8) as usize;
+            let body = &self.map[format::HEADER_SIZE..format::HEADER_SIZE + body_len];
+            // the pages are already in place, and only need flushing
+            for (offset, page) in paged::in_place(&mut header, body, &pages) {
+                self.map.flush_range(offset as usize, page.len())?;
+            }
+
+            // the bits set would take a pass over the whole map to count
+            header.remove_extension(format::EXT_ONES);
+            header.set_count(self.count);
+
+            let mut buf = Vec::with_capacity(format::HEADER_SIZE);
+            header.write(&mut buf)?;
+            self.map[..format::HEADER_SIZE].copy_from_slice(&buf);
+            self.map.flush_range(0, format::HEADER_SIZE)?;
+        }
+
+        set_all(&mut self.dirty, false);
+        Ok(())
+    }
+
+    pub fn count_estimate(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.as_bytes().iter().any(|b| *b != 0)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= self.params.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl ReadOnlyBloomFilter {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path.as_ref())?;
+
+        // as with any mapping, the file mustn't be truncated while it's open
+        let map = unsafe { Mmap::map(&file)? };
+        let (params, count) = check_dense(&map)?;
+        // probes go anywhere; this is only a hint, so failing is harmless
+        let _ = map.advise(Advice::Random);
+
+        Ok(Self {
+            pages: params.m / (u64::from(params.page_size) * 8),
+            params,
+            count,
+            lsb_first: lsb_first(),
+            map,
+        })
+    }
+
+    pub fn params(&self) -> &BloomFilterParams {
+        &self.params
+    }
+
+    /// Advise the OS how the filter will be used.
+    pub fn advise(&self, advice: MapAdvice) -> io::Result<()> {
+        advise(
+            advice,
+            |advice| self.map.advise(advice),
+            // the mapping is shared, so dropping pages loses nothing
+            || unsafe { self.map.unchecked_advise(UncheckedAdvice::DontNeed) },
+        )
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        probe_bits(&self.params, self.pages, *hash, 0).all(|bit| {
+            let (byte, mask) = bit_position(bit, self.lsb_first);
+            self.map[byte] & mask != 0
+        })
+    }
+
+    pub fn count_estimate(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_insert_save_load() {
+        let path = "test_mapped.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        for i in 0..1000 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        let mut mapped = BloomFilter::open_mmap(path).unwrap();
+        assert!((0..1000).all(|i| mapped.contains(i)));
+        assert!(!mapped.insert(0));
+        for i in 1000..2000 {
+            mapped.insert(i);
+        }
+        assert!(mapped.is_dirty());
+        mapped.save().unwrap();
+        assert!(!mapped.is_dirty());
+        drop(mapped);
+
+        let bf = BloomFilter::load(path).unwrap();
+        assert!((0..2000).all(|i| bf.contains(i)));
+        assert!((2000..3000).filter(|i| bf.contains(*i)).count() < 50);
+
+        let ro = BloomFilter::open_read_only(path).unwrap();
+        ro.advise(MapAdvice::WillNeed).unwrap();
+        ro.advise(MapAdvice::DontNeed).unwrap();
+        assert!((0..3000).all(|i| ro.contains(i) == bf.contains(i)));
+        assert_eq!(bf.count, ro.count_estimate());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
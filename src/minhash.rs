@@ -0,0 +1,198 @@
+/// MinHash signatures, after Broder, for estimating the similarity of sets.
+///
+/// A signature keeps the minimum of each of `k` hash functions over every
+/// item seen.  Two signatures agree in any one position with probability
+/// equal to the Jaccard similarity of their sets, so the fraction of
+/// positions which agree estimates it with a standard error of about
+/// 1 / sqrt(k).
+///
+/// Saved files are a short header followed by the signature:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "MINHSv00"
+/// 8       4     k, number of hashes (big-endian u32)
+/// 12      4     zero padding
+/// 16      ...   k big-endian u64 minimums
+/// ```
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::xor::mix;
+use crate::BloomHash;
+
+const MINHASH_MAGIC: &[u8; 8] = b"MINHSv00";
+const MINHASH_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinHash {
+    signature: Vec<u64>,
+}
+
+impl MinHash {
+    /// An empty signature of `k` hashes.
+    pub fn new(k: usize) -> Self {
+        assert!(k > 0);
+
+        Self {
+            signature: vec![u64::MAX; k],
+        }
+    }
+
+    /// The signature of a stream of items.
+    pub fn from_items<I, T>(k: usize, items: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<BloomHash>,
+    {
+        let mut ret = Self::new(k);
+        for item in items {
+            ret.insert(item);
+        }
+        ret
+    }
+
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) {
+        self.insert_hash(&item.into());
+    }
+
+    pub fn insert_hash(&mut self, hash: &BloomHash) {
+        for (i, min) in self.signature.iter_mut().enumerate() {
+            // double hashing alone is too linear; mix each value
+            let h = mix(hash.nth(i as u32), i as u64);
+            *min = (*min).min(h);
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.signature.len()
+    }
+
+    pub fn signature(&self) -> &[u64] {
+        &self.signature
+    }
+
+    /// Whether `other` has the same number of hashes, and so can be
+    /// compared or merged.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.k() == other.k()
+    }
+
+    /// Estimated Jaccard similarity of the two sets.
+    ///
+    /// Panics if the signatures are not compatible.
+    pub fn jaccard(&self, other: &Self) -> f64 {
+        assert!(self.is_compatible(other), "incompatible signature length");
+
+        let agree = self
+            .signature
+            .iter()
+            .zip(other.signature.iter())
+            .filter(|(a, b)| a == b && **a != u64::MAX)
+            .count();
+
+        agree as f64 / self.k() as f64
+    }
+
+    /// Merge `other` into this signature, which then describes the union of
+    /// both sets.
+    ///
+    /// Panics if the signatures are not compatible.
+    pub fn merge(&mut self, other: &Self) {
+        assert!(self.is_compatible(other), "incompatible signature length");
+
+        for (a, b) in self.signature.iter_mut().zip(other.signature.iter()) {
+            *a = (*a).min(*b);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signature.iter().all(|min| *min == u64::MAX)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; MINHASH_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != MINHASH_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let k = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+        if k == 0 {
+            return Err(invalid_data("invalid signature length"));
+        }
+
+        let mut buf = vec![0; k * 8];
+        reader.read_exact(&mut buf[..])?;
+
+        Ok(Self {
+            signature: buf
+                .chunks(8)
+                .map(|min| u64::from_be_bytes(min.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; MINHASH_HEADER_SIZE];
+        header[0..8].copy_from_slice(MINHASH_MAGIC);
+        header[8..12].copy_from_slice(&(self.k() as u32).to_be_bytes());
+
+        let buf: Vec<u8> = self
+            .signature
+            .iter()
+            .flat_map(|min| min.to_be_bytes().to_vec())
+            .collect();
+
+        writer.write_all(&header[..])?;
+        writer.write_all(&buf[..])
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        self.write_to(&mut file)?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minhash_jaccard_save_load() {
+        let path = "test_minhash.bf";
+        let _ = std::fs::remove_file(path);
+
+        // |A ∩ B| = 500, |A ∪ B| = 1500
+        let a = MinHash::from_items(256, 0..1000);
+        let b = MinHash::from_items(256, 500..1500);
+        let c = MinHash::from_items(256, 5000..6000);
+
+        assert_eq!(1.0, a.jaccard(&a));
+        assert!((a.jaccard(&b) - 1.0 / 3.0).abs() < 0.1);
+        assert!(a.jaccard(&c) < 0.05);
+
+        let mut union = a.clone();
+        union.merge(&b);
+        assert_eq!(MinHash::from_items(256, 0..1500), union);
+
+        a.save(path).unwrap();
+        assert_eq!(a, MinHash::load(path).unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
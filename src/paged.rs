@@ -0,0 +1,359 @@
+/// Shared save path for filters with a dense paged body.
+///
+/// If the file at `path` already holds a filter with the same header, only
+/// pages marked dirty are rewritten, along with their checksums.  Otherwise,
+/// including when the file is short or missing, the whole filter is written
+/// out.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::set_all;
+
+pub(crate) fn save_paged(
+    path: &Path,
+    header: &FileHeader,
+    body: &[u8],
+    dirty: &mut BitVec,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    let len = file.metadata()?.len();
+    let existing = if len >= format::HEADER_SIZE as u64 {
+        FileHeader::read(&mut file).ok()
+    } else {
+        None
+    };
+
+    match existing {
+        Some(existing) if existing.without_checksums() == *header && len >= header.file_len() => {
+            write_in_place(&mut file, existing, body, &dirty_pages(dirty))?;
+        }
+        _ => {
+            let mut header = header.clone();
+            header.set_checksums(body);
+
+            file.set_len(0)?;
+            file.seek(io::SeekFrom::Start(0))?;
+            header.write(&mut file)?;
+            write_sparse(&mut file, body)?;
+        }
+    }
+
+    file.sync_all()?;
+    set_all(dirty, false);
+
+    Ok(())
+}
+
+/// Indexes of the pages marked in `dirty`.
+pub(crate) fn dirty_pages(dirty: &BitVec) -> Vec<usize> {
+    dirty
+        .iter()
+        .enumerate()
+        .filter(|(_, bit)| *bit)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Prepare to rewrite `pages` of a dense `body` in place over a file whose
+/// existing header is `header`, returning each page with its offset.
+///
+/// `header` gets the pages' new checksums and digest, and is to be written
+/// over the old one once the pages are.  Every in-place save goes through
+/// this, whatever it writes with; `write_in_place` does the writing for a
+/// plain `File`.
+pub(crate) fn in_place<'a>(
+    header: &mut FileHeader,
+    body: &'a [u8],
+    pages: &[usize],
+) -> Vec<(u64, &'a [u8])> {
+    header.update_checksums(body, pages.iter().copied());
+
+    let page_size = header.page_size() as usize;
+    pages
+        .iter()
+        .map(|index| {
+            (
+                (format::HEADER_SIZE + index * page_size) as u64,
+                &body[index * page_size..(index + 1) * page_size],
+            )
+        })
+        .collect()
+}
+
+/// Rewrite `pages` of a dense `body` in `file`, followed by its `header`,
+/// as `in_place` prepares them.
+pub(crate) fn write_in_place(
+    file: &mut File,
+    mut header: FileHeader,
+    body: &[u8],
+    pages: &[usize],
+) -> io::Result<()> {
+    let writes = in_place(&mut header, body, pages);
+    write_pages(file, writes)?;
+    file.seek(io::SeekFrom::Start(0))?;
+    header.write(file)
+}
+
+/// Write each `(offset, data)` pair to `file`, as a single batch through
+/// io_uring where it's enabled and available.
+pub(crate) fn write_pages<'a, I>(file: &mut File, writes: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = (u64, &'a [u8])>,
+{
+    let writes: Vec<(u64, &[u8])> = writes.into_iter().collect();
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if let Some(result) = crate::uring::write_at(file, &writes) {
+        return result;
+    }
+
+    for (offset, data) in writes {
+        file.seek(io::SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+/// Write a dense body to an empty file from its current position, seeking
+/// over pages which are entirely zero rather than writing them.
+///
+/// The skipped pages read back as zero but, on filesystems with sparse file
+/// support, take up no disk blocks until something is written to them, so a
+/// fresh filter costs little more space than the pages it has set bits in.
+pub(crate) fn write_sparse(file: &mut File, body: &[u8]) -> io::Result<()> {
+    let mut writer = SparseWriter::new(file);
+    writer.write_all(body)?;
+    writer.finish()
+}
+
+/// Writes to an empty file as `write_sparse` does, for encoders which take
+/// any `Write`.  Each write is split into pages from its own start, so a
+/// body should be written on its own, from a page boundary.
+pub(crate) struct SparseWriter<'a> {
+    file: &'a mut File,
+}
+
+impl<'a> SparseWriter<'a> {
+    pub(crate) fn new(file: &'a mut File) -> Self {
+        Self { file }
+    }
+
+    /// Set the file's length to the end of what was written.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        // a trailing run of zero pages still has to be part of the file
+        let len = self.file.stream_position()?;
+        self.file.set_len(len)
+    }
+}
+
+impl Write for SparseWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for page in buf.chunks(format::PAGE_SIZE as usize) {
+            if page.iter().all(|b| *b == 0) {
+                self.file.seek(io::SeekFrom::Current(page.len() as i64))?;
+            } else {
+                self.file.write_all(page)?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Reserve disk blocks for the first `len` bytes of an empty file.
+#[cfg(target_os = "linux")]
+pub(crate) fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // posix_fallocate returns the error rather than setting errno
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Without fallocate the best we can do is set the length, which at least
+/// fails early on filesystems with a file size limit.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    file.set_len(len)
+}
+
+/// How thoroughly a save makes sure what it wrote survives a crash.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Sync data and metadata, with `sync_all`.
+    #[default]
+    Full,
+    /// Sync data and only the metadata needed to read it back, such as the
+    /// file's length, with `sync_data`.
+    Data,
+    /// Leave writing back to the OS, for bulk builds which would start
+    /// again after a crash anyway.
+    Unsynced,
+}
+
+impl Durability {
+    pub(crate) fn sync(self, file: &File) -> io::Result<()> {
+        match self {
+            Durability::Full => file.sync_all(),
+            Durability::Data => file.sync_data(),
+            Durability::Unsynced => Ok(()),
+        }
+    }
+}
+
+/// Replace the file at `path` in one step, so a crash leaves either the old
+/// file or the new one and never a torn mix.
+///
+/// `write` fills a temporary file beside `path`, which is synced and renamed
+/// over it, and the directory is then synced so the rename itself is durable.
+/// Without syncing, the rename is still atomic but may not survive a crash.
+pub(crate) fn write_atomic<F>(path: &Path, durability: Durability, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    let tmp = PathBuf::from(name);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&tmp)?;
+
+    write(&mut file)?;
+    durability.sync(&file)?;
+    drop(file);
+
+    fs::rename(&tmp, path)?;
+    match durability {
+        Durability::Unsynced => Ok(()),
+        _ => sync_dir(path),
+    }
+}
+
+/// This machine's name, or empty if it can't be found.
+#[cfg(target_os = "linux")]
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn hostname() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default()
+}
+
+/// Path of the `n`th most recent backup of the file at `path`.
+pub(crate) fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Copy the file at `path`, if any, to its first backup, shifting older
+/// backups along and dropping any beyond the `keep` most recent.
+pub(crate) fn rotate_backups(path: &Path, keep: usize, durability: Durability) -> io::Result<()> {
+    let mut current = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for n in (1..keep).rev() {
+        match fs::rename(backup_path(path, n), backup_path(path, n + 1)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    write_atomic(&backup_path(path, 1), durability, |file| {
+        io::copy(&mut current, file).map(|_| ())
+    })
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)?.sync_all()
+}
+
+/// There's no portable way to sync a directory elsewhere; the rename is still
+/// atomic, if not yet certain to survive a crash.
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paged_write_in_place() {
+        let path = Path::new("test_in_place.bf");
+
+        // the second has too many pages for the checksum table, so only the
+        // digest is kept
+        for (page_size, pages) in [(format::PAGE_SIZE, 4), (512, 5000)] {
+            let mut body = vec![0; page_size as usize * pages];
+            let mut header = FileHeader::new(1000, body.len() as u64 * 8, 7);
+            header.set_page_size(page_size);
+            header.set_checksums(&body);
+            assert_eq!(pages == 4, header.page_checksums.is_some());
+            assert!(header.digest.is_some());
+
+            let mut file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(path)
+                .unwrap();
+            header.write(&mut file).unwrap();
+            file.write_all(&body).unwrap();
+
+            body[page_size as usize * 3] = 0x80;
+            write_in_place(&mut file, header, &body, &[3]).unwrap();
+            drop(file);
+
+            assert_eq!(body, fs::read(path).unwrap()[format::HEADER_SIZE..]);
+            crate::BloomFilter::verify(path).unwrap();
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -3,57 +3,252 @@
 /// This has gone something like JS -> PHP -> JS -> PHP -> Ruby -> Rust
 /// Some losses in transit may have ocurred, and I'm not really to be trusted
 /// with maths at the best of times.
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
 
-#[derive(Debug, Default, Clone)]
+use crate::format;
+use crate::{BloomHash, HashAlgorithm};
+
+/// Why a `BloomFilterParamsBuilder` couldn't produce params.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsError {
+    /// Too few of bits, capacity, hashes and false-positive rate were given
+    /// to size a filter.  Any of capacity with a rate or a budget, bits with
+    /// capacity or a rate, or bits, capacity and hashes will do.
+    MissingFields,
+    /// More were given than can be honoured together.
+    ConflictingFields,
+    /// A capacity of zero items.
+    ZeroCapacity,
+    /// Zero bits or hashes, given or as worked out from the rest.
+    ZeroSize,
+    /// A false-positive rate outside (0, 1], given or as worked out from the
+    /// rest.
+    FalsePositiveRateOutOfRange,
+    /// More bits than `MAX_BITS`, or hashes than fit in a `u32`.
+    TooLarge,
+    /// A memory budget too small for the capacity, or for the capacity at
+    /// the false-positive rate asked for.
+    BudgetTooSmall,
+    /// A hash algorithm whose feature isn't enabled in this build.
+    HashAlgorithmUnavailable,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MissingFields => "not enough parameters to size a filter",
+            Self::ConflictingFields => "parameters conflict",
+            Self::ZeroCapacity => "capacity is zero",
+            Self::ZeroSize => "zero bits or hashes",
+            Self::FalsePositiveRateOutOfRange => "false-positive rate out of range",
+            Self::TooLarge => "filter too large",
+            Self::BudgetTooSmall => "memory budget too small for capacity",
+            Self::HashAlgorithmUnavailable => "hash algorithm not available in this build",
+        })
+    }
+}
+
+impl Error for ParamsError {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BloomFilterParams {
-    pub m: u32,
-    pub n: u32,
+    pub m: u64,
+    pub n: u64,
     pub k: u32,
+    /// The false-positive rate these parameters achieve.
     pub p: f64,
+    /// The false-positive rate originally asked for, if any, which may differ
+    /// from `p` after rounding.
+    pub requested_p: Option<f64>,
+    /// How each item's probes are spread over the filter.
+    pub layout: Layout,
+    /// Bytes per page, the unit of dirty tracking and of `Layout::Paged`
+    /// probing.  Only `BloomFilter` and `CountingBloomFilter` honour anything
+    /// but the default.
+    pub page_size: u32,
+    /// SipHash key items are hashed with.  The default of zero is the key
+    /// `BloomHash::from` uses.
+    pub hash_key: (u64, u64),
+    /// Hash function items are hashed with, SipHash unless chosen otherwise.
+    pub hash_algorithm: HashAlgorithm,
+}
+
+impl Default for BloomFilterParams {
+    fn default() -> Self {
+        Self {
+            m: 0,
+            n: 0,
+            k: 0,
+            p: 0.0,
+            requested_p: None,
+            layout: Layout::default(),
+            page_size: format::PAGE_SIZE,
+            hash_key: (0, 0),
+            hash_algorithm: HashAlgorithm::default(),
+        }
+    }
+}
+
+/// How an item's k probes are spread over a `BloomFilter`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Layout {
+    /// All probes land in a single page chosen by an extra hash, so an insert
+    /// dirties at most one page.
+    #[default]
+    Paged,
+    /// Each probe lands in its own m / k bit segment, so probes never collide
+    /// with each other, at the cost of touching up to k pages.
+    Partitioned,
+    /// All probes land in a single 64-byte block, so a lookup touches one
+    /// cache line, at the cost of a slightly higher false-positive rate.
+    Blocked,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct BloomFilterParamsBuilder {
-    m: Option<u32>,
-    n: Option<u32>,
+    m: Option<u64>,
+    n: Option<u64>,
     k: Option<u32>,
     p: Option<f64>,
+    layout: Layout,
+    page_size: Option<u32>,
+    max_bytes: Option<u64>,
+    hash_key: (u64, u64),
+    hash_algorithm: HashAlgorithm,
 }
 
 impl BloomFilterParams {
-    pub fn with_capacity_p(capacity: u32, p: f64) -> Self {
+    pub fn with_capacity_p(capacity: u64, p: f64) -> Self {
         BloomFilterParamsBuilder::default()
             .capacity(capacity)
             .false_positives(p)
             .to_params()
             .unwrap()
     }
+
+    /// Rehash `hash` with the key and algorithm, as the filter does with
+    /// every item.
+    pub(crate) fn rehash(&self, hash: BloomHash) -> BloomHash {
+        self.hash_algorithm.rehash(self.hash_key, hash)
+    }
+}
+
+/// Quotient filter geometry: 2^q slots, each holding an r-bit remainder.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QuotientFilterParams {
+    pub q: u32,
+    pub r: u32,
+}
+
+/// Target load factor, beyond which quotient filter clusters grow long.
+pub const QUOTIENT_MAX_LOAD: f64 = 0.75;
+
+impl QuotientFilterParams {
+    /// The smallest filter holding `capacity` items at no more than
+    /// `QUOTIENT_MAX_LOAD`, with a false-positive rate of at most `p` when
+    /// full.  A remainder of r bits gives a rate of about load / 2^r.
+    pub fn with_capacity_p(capacity: u32, p: f64) -> Self {
+        assert!(p > 0.0 && p < 1.0);
+
+        let q = (f64::from(capacity.max(1)) / QUOTIENT_MAX_LOAD)
+            .log2()
+            .ceil()
+            .max(1.0) as u32;
+        let r = (QUOTIENT_MAX_LOAD / p).log2().ceil().max(1.0) as u32;
+
+        Self { q, r }
+    }
+
+    pub fn slots(&self) -> u64 {
+        1 << self.q
+    }
+
+    pub fn capacity(&self) -> u64 {
+        (self.slots() as f64 * QUOTIENT_MAX_LOAD) as u64
+    }
+
+    /// Expected false-positive rate with `n` items.
+    pub fn false_positive_rate(&self, n: u64) -> f64 {
+        let load = n as f64 / self.slots() as f64;
+        1.0 - (-load / 2.0_f64.powi(self.r as i32)).exp()
+    }
+}
+
+/// Count-min sketch geometry: `depth` rows of `width` counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CountMinSketchParams {
+    pub width: u32,
+    pub depth: u32,
+}
+
+impl CountMinSketchParams {
+    /// The smallest sketch whose estimates exceed the true count by at most
+    /// `epsilon` times the total of all increments, with probability at least
+    /// `1 - delta`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        assert!(epsilon > 0.0 && epsilon < 1.0);
+        assert!(delta > 0.0 && delta < 1.0);
+
+        let width = (std::f64::consts::E / epsilon).ceil() as u32;
+        let depth = (1.0 / delta).ln().ceil().max(1.0) as u32;
+
+        Self { width, depth }
+    }
+
+    /// Overestimate as a fraction of the total of all increments.
+    pub fn error(&self) -> f64 {
+        std::f64::consts::E / f64::from(self.width)
+    }
+
+    /// Probability that an estimate is within `error()`.
+    pub fn confidence(&self) -> f64 {
+        1.0 - (-f64::from(self.depth)).exp()
+    }
 }
 
 impl BloomFilterParamsBuilder {
-    pub fn capacity(&mut self, capacity: u32) -> &mut Self {
+    pub fn capacity(&mut self, capacity: u64) -> &mut Self {
         self.n = Some(capacity);
         self
     }
 
-    pub fn bits(&mut self, bits: u32) -> &mut Self {
+    pub fn bits(&mut self, bits: u64) -> &mut Self {
         self.m = Some(bits);
         self
     }
 
-    pub fn bytes(&mut self, bytes: u32) -> &mut Self {
+    pub fn bytes(&mut self, bytes: u64) -> &mut Self {
         self.bits(bytes * 8)
     }
 
+    /// Size the filter to fit in `bytes`, rounded down to whole pages, for a
+    /// `BloomFilter` which has to live within a fixed amount of memory.
+    ///
+    /// With a capacity alone, the whole budget is used and the hashes and
+    /// false-positive rate follow from it.  With a false-positive rate as
+    /// well, the filter is as small as that rate allows, provided it fits.
+    /// Either way, `to_params` fails with `ParamsError::BudgetTooSmall` if
+    /// the budget can't hold the capacity.  Bits and hashes can't be given
+    /// alongside a budget.
+    pub fn max_bytes(&mut self, bytes: u64) -> &mut Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
     pub fn hashes(&mut self, hashes: u32) -> &mut Self {
         self.k = Some(hashes);
         self
     }
 
+    /// The false-positive rate to aim for, or its reciprocal if above 1.
+    /// `to_params` refuses rates of zero, below zero or NaN with
+    /// `ParamsError::FalsePositiveRateOutOfRange`.
     pub fn false_positives(&mut self, fp: f64) -> &mut Self {
-        assert!(fp.is_normal());
-        assert!(fp.is_sign_positive());
-
         if fp > 1.0 {
             self.p = Some(1.0 / fp);
         } else {
@@ -63,67 +258,245 @@ impl BloomFilterParamsBuilder {
         self
     }
 
+    pub fn layout(&mut self, layout: Layout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Page size in bytes.  Smaller pages waste less space rounding up small
+    /// filters, and make each dirty page cheaper to write back.
+    ///
+    /// Panics unless `bytes` is a power of two from `format::MIN_PAGE_SIZE` to
+    /// `format::MAX_PAGE_SIZE`.
+    pub fn page_size(&mut self, bytes: u32) -> &mut Self {
+        assert!(format::is_valid_page_size(bytes));
+
+        self.page_size = Some(bytes);
+        self
+    }
+
+    /// Hash items with a SipHash key of `(k0, k1)` rather than the default,
+    /// so their bit positions can't be predicted without it.
+    ///
+    /// Every item's `BloomHash` is rehashed with the key, so hashes given to
+    /// `insert_hash` and `contains_hash` should come from the filter's `hash`.
+    pub fn hash_key(&mut self, k0: u64, k1: u64) -> &mut Self {
+        self.hash_key = (k0, k1);
+        self
+    }
+
+    /// Hash items with `algorithm` rather than SipHash, which is recorded in
+    /// saved files.  Like a key, it rehashes every item's `BloomHash`.
+    pub fn hash_algorithm(&mut self, algorithm: HashAlgorithm) -> &mut Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+
+    /// Hash items with a SipHash key drawn at random, which is saved with
+    /// the filter and needed to load it again.
+    pub fn random_hash_key(&mut self) -> &mut Self {
+        // std seeds each RandomState from the OS
+        let state = RandomState::new();
+        self.hash_key(state.hash_one(0u8), state.hash_one(1u8))
+    }
+
     #[allow(clippy::many_single_char_names)]
-    pub fn to_params(&self) -> Result<BloomFilterParams, ()> {
+    pub fn to_params(&self) -> Result<BloomFilterParams, ParamsError> {
         use std::f64::consts::LN_2;
 
-        match *self {
+        if self.n == Some(0) {
+            return Err(ParamsError::ZeroCapacity);
+        }
+        if let Some(p) = self.p {
+            if !p.is_finite() || p <= 0.0 || p > 1.0 {
+                return Err(ParamsError::FalsePositiveRateOutOfRange);
+            }
+        }
+        if !self.hash_algorithm.is_available() {
+            return Err(ParamsError::HashAlgorithmUnavailable);
+        }
+        if let Some(bytes) = self.max_bytes {
+            return self.within_budget(bytes);
+        }
+
+        let params = match *self {
             BloomFilterParamsBuilder {
                 m: Some(m),
                 n: Some(n),
                 k: Some(k),
                 p: None,
+                ..
             } => {
-                let r = f64::from(m) / f64::from(n);
+                let r = m as f64 / n as f64;
                 let q = f64::exp(-f64::from(k) / r);
                 let p = (1.0 - q).powf(f64::from(k));
 
-                Ok(BloomFilterParams { m, n, k, p })
+                validated(m, n, k, p)
             }
             BloomFilterParamsBuilder {
                 m: None,
                 n: Some(n),
                 k: None,
                 p: Some(p),
+                ..
             } => {
-                let m = (f64::from(n) * p.ln() / (1.0 / 2.0_f64.powf(LN_2)).ln()).ceil() as u32;
-                let r = f64::from(m) / f64::from(n);
-                let k = (LN_2 * r).round() as u32;
+                let m = to_size((n as f64 * p.ln() / (1.0 / 2.0_f64.powf(LN_2)).ln()).ceil())?;
+                let r = m as f64 / n as f64;
+                let k = to_u32((LN_2 * r).round())?;
                 let q = f64::exp(-f64::from(k) / r);
                 let p = (1.0 - q).powf(f64::from(k));
 
-                Ok(BloomFilterParams { m, n, k, p })
+                validated(m, n, k, p)
             }
             BloomFilterParamsBuilder {
                 m: Some(m),
                 n: Some(n),
                 k: None,
                 p: None,
+                ..
             } => {
-                let r = f64::from(m) / f64::from(n);
-                let k = (LN_2 * r).round() as u32;
+                let r = m as f64 / n as f64;
+                let k = to_u32((LN_2 * r).round())?;
                 let q = f64::exp(-f64::from(k) / r);
                 let p = (1.0 - q).powf(f64::from(k));
 
-                Ok(BloomFilterParams { m, n, k, p })
+                validated(m, n, k, p)
             }
             BloomFilterParamsBuilder {
                 m: Some(m),
                 n: None,
                 k: None,
                 p: Some(p),
+                ..
             } => {
-                let n = ((f64::from(m) * (1.0 / 2.0_f64.powf(LN_2)).ln()) / p.ln()).ceil() as u32;
-                let r = f64::from(m) / f64::from(n);
-                let k = (LN_2 * r).round() as u32;
+                let n = to_size(((m as f64 * (1.0 / 2.0_f64.powf(LN_2)).ln()) / p.ln()).ceil())?;
+                let r = m as f64 / n as f64;
+                let k = to_u32((LN_2 * r).round())?;
                 let q = f64::exp(-f64::from(k) / r);
                 let p = (1.0 - q).powf(f64::from(k));
 
-                Ok(BloomFilterParams { m, n, k, p })
+                validated(m, n, k, p)
             }
-            _ => Err(()),
+            _ => Err(self.unusable()),
+        }?;
+
+        Ok(BloomFilterParams {
+            requested_p: self.p,
+            layout: self.layout,
+            page_size: self.page_size.unwrap_or(format::PAGE_SIZE),
+            hash_key: self.hash_key,
+            hash_algorithm: self.hash_algorithm,
+            ..params
+        })
+    }
+
+    /// Params for `max_bytes`: those the rest of the builder gives, with the
+    /// budget standing in for the bits unless a rate decides them.
+    fn within_budget(&self, bytes: u64) -> Result<BloomFilterParams, ParamsError> {
+        if self.m.is_some() || self.k.is_some() {
+            return Err(ParamsError::ConflictingFields);
+        }
+        if self.n.is_none() {
+            return Err(ParamsError::MissingFields);
+        }
+
+        // whole pages, so BloomFilter has nothing to round up
+        let page_bits = u64::from(self.page_size.unwrap_or(format::PAGE_SIZE)) * 8;
+        let budget = bytes.saturating_mul(8) / page_bits * page_bits;
+        if budget == 0 {
+            return Err(ParamsError::BudgetTooSmall);
+        }
+
+        let mut unbudgeted = self.clone();
+        unbudgeted.max_bytes = None;
+        if self.p.is_none() {
+            unbudgeted.bits(budget);
         }
+
+        // too few bits per item rounds the hashes down to zero
+        let params = unbudgeted.to_params().map_err(|e| match e {
+            ParamsError::ZeroSize => ParamsError::BudgetTooSmall,
+            e => e,
+        })?;
+        if params.m.div_ceil(page_bits) * page_bits > budget {
+            return Err(ParamsError::BudgetTooSmall);
+        }
+
+        Ok(params)
+    }
+
+    /// Whether a combination `to_params` has no case for lacks something or
+    /// has too much.
+    fn unusable(&self) -> ParamsError {
+        let given = [
+            self.m.is_some(),
+            self.n.is_some(),
+            self.k.is_some(),
+            self.p.is_some(),
+        ]
+        .iter()
+        .filter(|given| **given)
+        .count();
+
+        // hashes follow from the rate, so can't be given alongside it
+        if given >= 3 || (self.k.is_some() && self.p.is_some()) {
+            ParamsError::ConflictingFields
+        } else {
+            ParamsError::MissingFields
+        }
+    }
+}
+
+/// Largest filter size in bits, such that the body's length in bytes fits
+/// a u64 with room to spare for the wider bodies of other filter types.
+pub const MAX_BITS: u64 = 1 << 56;
+
+/// Convert a computed size, rejecting anything that doesn't fit or would be
+/// meaningless as a filter dimension.
+fn to_size(x: f64) -> Result<u64, ParamsError> {
+    if !x.is_finite() || x > MAX_BITS as f64 {
+        Err(ParamsError::TooLarge)
+    } else if x < 1.0 {
+        Err(ParamsError::ZeroSize)
+    } else {
+        Ok(x as u64)
+    }
+}
+
+/// Convert a computed hash count, rejecting anything that doesn't fit or
+/// would be meaningless.
+fn to_u32(x: f64) -> Result<u32, ParamsError> {
+    if !x.is_finite() || x > f64::from(u32::MAX) {
+        Err(ParamsError::TooLarge)
+    } else if x < 1.0 {
+        Err(ParamsError::ZeroSize)
+    } else {
+        Ok(x as u32)
+    }
+}
+
+#[allow(clippy::many_single_char_names)]
+fn validated(m: u64, n: u64, k: u32, p: f64) -> Result<BloomFilterParams, ParamsError> {
+    if n == 0 {
+        return Err(ParamsError::ZeroCapacity);
+    }
+    if m == 0 || k == 0 {
+        return Err(ParamsError::ZeroSize);
     }
+    if m > MAX_BITS {
+        return Err(ParamsError::TooLarge);
+    }
+    if !p.is_finite() || p <= 0.0 || p > 1.0 {
+        return Err(ParamsError::FalsePositiveRateOutOfRange);
+    }
+
+    Ok(BloomFilterParams {
+        m,
+        n,
+        k,
+        p,
+        ..Default::default()
+    })
 }
 
 #[cfg(test)]
@@ -142,4 +515,217 @@ mod tests {
         assert_eq!(13, prm.k);
         assert!(prm.p < 0.00012 && prm.p > 0.00009);
     }
+
+    #[test]
+    fn params_quotient() {
+        let prm = QuotientFilterParams::with_capacity_p(1000, 0.01);
+        assert_eq!(11, prm.q);
+        assert_eq!(7, prm.r);
+        assert!(prm.capacity() >= 1000);
+        assert!(prm.false_positive_rate(1000) < 0.01);
+    }
+
+    #[test]
+    fn params_count_min() {
+        let prm = CountMinSketchParams::with_error(0.001, 0.01);
+        assert_eq!(2719, prm.width);
+        assert_eq!(5, prm.depth);
+        assert!(prm.error() <= 0.001);
+        assert!(prm.confidence() >= 0.99);
+    }
+
+    #[test]
+    fn params_requested_p() {
+        let prm = BloomFilterParams::with_capacity_p(100, 0.01);
+        assert_eq!(Some(0.01), prm.requested_p);
+        assert_ne!(prm.p, 0.01);
+
+        let prm = BloomFilterParamsBuilder::default()
+            .bits(959)
+            .capacity(100)
+            .to_params()
+            .unwrap();
+        assert_eq!(None, prm.requested_p);
+    }
+
+    #[test]
+    fn params_reject_degenerate() {
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::ZeroSize),
+            b.bits(1024).capacity(100).hashes(0).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::ZeroCapacity),
+            b.bits(1024).capacity(0).hashes(7).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::ZeroCapacity),
+            b.bits(1024).capacity(0).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::ZeroCapacity),
+            b.capacity(0).false_positives(0.01).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::TooLarge),
+            b.capacity(1 << 50).false_positives(1e-300).to_params()
+        );
+
+        for fp in [0.0, -0.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let mut b = BloomFilterParamsBuilder::default();
+            assert_eq!(
+                Err(ParamsError::FalsePositiveRateOutOfRange),
+                b.capacity(100).false_positives(fp).to_params()
+            );
+
+            let mut b = BloomFilterParamsBuilder::default();
+            assert_eq!(
+                Err(ParamsError::FalsePositiveRateOutOfRange),
+                b.bits(1024).false_positives(fp).to_params()
+            );
+        }
+    }
+
+    #[test]
+    fn params_wide() {
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(10_000_000_000)
+            .false_positives(0.01)
+            .to_params()
+            .unwrap();
+        assert!(params.m > u64::from(u32::MAX));
+        assert_eq!(10_000_000_000, params.n);
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::TooLarge),
+            b.bits(MAX_BITS + 1).capacity(1000).hashes(7).to_params()
+        );
+    }
+
+    #[test]
+    fn params_budget() {
+        let budget = 1024 * 1024;
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(500_000)
+            .max_bytes(budget)
+            .to_params()
+            .unwrap();
+        assert_eq!(budget * 8, params.m);
+        assert_eq!(500_000, params.n);
+        assert!(params.k > 1);
+        assert!(params.p > 0.0 && params.p < 0.1);
+
+        // the filter fits the budget exactly, rather than rounding up
+        let bf = crate::BloomFilter::from_params(params.clone());
+        assert_eq!(budget * 8, bf.bits());
+
+        // a rate within reach takes only as much as it needs
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(10_000)
+            .false_positives(0.01)
+            .max_bytes(budget)
+            .to_params()
+            .unwrap();
+        assert!(params.m < budget * 8);
+        assert_eq!(Some(0.01), params.requested_p);
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::BudgetTooSmall),
+            b.capacity(500_000)
+                .false_positives(0.0001)
+                .max_bytes(budget)
+                .to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::BudgetTooSmall),
+            b.capacity(100_000_000).max_bytes(budget).to_params()
+        );
+
+        // less than a page
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::BudgetTooSmall),
+            b.capacity(10).max_bytes(1000).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::ConflictingFields),
+            b.capacity(10).bits(8192).max_bytes(budget).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(
+            Err(ParamsError::MissingFields),
+            b.max_bytes(budget).to_params()
+        );
+    }
+
+    #[test]
+    fn params_reject_combinations() {
+        let mut b = BloomFilterParamsBuilder::default();
+        assert_eq!(Err(ParamsError::MissingFields), b.to_params());
+        assert_eq!(Err(ParamsError::MissingFields), b.capacity(100).to_params());
+        assert_eq!(Err(ParamsError::MissingFields), b.hashes(7).to_params());
+        assert_eq!(
+            Err(ParamsError::ConflictingFields),
+            b.false_positives(0.01).to_params()
+        );
+
+        let mut b = BloomFilterParamsBuilder::default();
+        let err = b
+            .bits(1024)
+            .capacity(100)
+            .false_positives(0.01)
+            .to_params()
+            .unwrap_err();
+        assert_eq!(ParamsError::ConflictingFields, err);
+        assert_eq!("parameters conflict", err.to_string());
+    }
+
+    #[test]
+    fn params_random_hash_key() {
+        let a = BloomFilterParamsBuilder::default()
+            .capacity(100)
+            .false_positives(0.01)
+            .random_hash_key()
+            .to_params()
+            .unwrap();
+        let b = BloomFilterParamsBuilder::default()
+            .capacity(100)
+            .false_positives(0.01)
+            .random_hash_key()
+            .to_params()
+            .unwrap();
+
+        assert_ne!((0, 0), a.hash_key);
+        assert_ne!(a.hash_key, b.hash_key);
+        assert_eq!((a.m, a.k), (b.m, b.k));
+
+        let path = "test_random_hash_key.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = crate::BloomFilter::from_params(a.clone());
+        assert!(bf.insert("meep"));
+        assert!(bf.contains("meep"));
+        bf.save(path).unwrap();
+
+        let loaded = crate::BloomFilter::load_with_key(path, a.hash_key).unwrap();
+        assert!(loaded.contains("meep"));
+
+        std::fs::remove_file(path).unwrap();
+    }
 }
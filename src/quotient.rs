@@ -0,0 +1,460 @@
+/// Quotient filter, after Bender et al.
+///
+/// Each item is reduced to a (q + r)-bit fingerprint, split into a q-bit
+/// quotient selecting a slot and an r-bit remainder stored in it, with three
+/// metadata bits tracking how runs of remainders sharing a quotient have been
+/// shifted along by linear probing.
+///
+/// Because the full fingerprint can be recovered from the table, filters can
+/// be resized, trading a remainder bit for a quotient bit, and merged, without
+/// access to the original items.
+///
+/// Files use the standard header with `FLAG_QUOTIENT` set, `m` slots of `k`
+/// remainder bits, and `n` entries.
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::format::{self, FileHeader};
+use crate::paged::invalid_data;
+use crate::{BloomHash, QuotientFilterParams};
+
+const OCCUPIED: u64 = 1;
+const CONTINUATION: u64 = 1 << 1;
+const SHIFTED: u64 = 1 << 2;
+const METADATA: u64 = OCCUPIED | CONTINUATION | SHIFTED;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotientFilter {
+    q: u32,
+    r: u32,
+    entries: u64,
+    slots: Vec<u64>,
+}
+
+fn is_empty(slot: u64) -> bool {
+    slot & METADATA == 0
+}
+
+fn is_cluster_start(slot: u64) -> bool {
+    slot & OCCUPIED != 0 && slot & (CONTINUATION | SHIFTED) == 0
+}
+
+fn is_run_start(slot: u64) -> bool {
+    slot & CONTINUATION == 0 && slot & (OCCUPIED | SHIFTED) != 0
+}
+
+impl QuotientFilter {
+    pub fn from_params(params: QuotientFilterParams) -> Self {
+        assert!(params.q > 0 && params.r > 0);
+        assert!(params.q + params.r <= 64);
+        assert!(params.r <= 61);
+
+        Self {
+            q: params.q,
+            r: params.r,
+            entries: 0,
+            slots: vec![0; params.slots() as usize],
+        }
+    }
+
+    pub fn with_capacity_p(capacity: u32, p: f64) -> Self {
+        Self::from_params(QuotientFilterParams::with_capacity_p(capacity, p))
+    }
+
+    pub fn params(&self) -> QuotientFilterParams {
+        QuotientFilterParams {
+            q: self.q,
+            r: self.r,
+        }
+    }
+
+    fn fingerprint(&self, hash: &BloomHash) -> u64 {
+        hash.h1 >> (64 - (self.q + self.r))
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn incr(&self, i: usize) -> usize {
+        (i + 1) & self.mask()
+    }
+
+    fn decr(&self, i: usize) -> usize {
+        i.wrapping_sub(1) & self.mask()
+    }
+
+    fn remainder(&self, slot: u64) -> u64 {
+        slot >> 3
+    }
+
+    fn run_start(&self, fq: usize) -> usize {
+        // back up to the start of the cluster
+        let mut b = fq;
+        while self.slots[b] & SHIFTED != 0 {
+            b = self.decr(b);
+        }
+
+        // then walk forward run by run until reaching fq's
+        let mut s = b;
+        while b != fq {
+            loop {
+                s = self.incr(s);
+                if self.slots[s] & CONTINUATION == 0 {
+                    break;
+                }
+            }
+
+            loop {
+                b = self.incr(b);
+                if self.slots[b] & OCCUPIED != 0 {
+                    break;
+                }
+            }
+        }
+
+        s
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.contains_fingerprint(self.fingerprint(hash))
+    }
+
+    fn contains_fingerprint(&self, f: u64) -> bool {
+        let fq = (f >> self.r) as usize;
+        let fr = f & ((1 << self.r) - 1);
+
+        if self.slots[fq] & OCCUPIED == 0 {
+            return false;
+        }
+
+        let mut s = self.run_start(fq);
+        loop {
+            let rem = self.remainder(self.slots[s]);
+            if rem == fr {
+                return true;
+            } else if rem > fr {
+                return false;
+            }
+
+            s = self.incr(s);
+            if self.slots[s] & CONTINUATION == 0 {
+                return false;
+            }
+        }
+    }
+
+    /// Insert an item, returning `false` if it was already present or the
+    /// filter is full.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let f = self.fingerprint(hash);
+        self.insert_fingerprint(f)
+    }
+
+    fn insert_fingerprint(&mut self, f: u64) -> bool {
+        if self.entries >= self.slots.len() as u64 {
+            return false;
+        }
+
+        let fq = (f >> self.r) as usize;
+        let fr = f & ((1 << self.r) - 1);
+        let canonical = self.slots[fq];
+        let mut entry = fr << 3;
+
+        if is_empty(canonical) {
+            self.slots[fq] = entry | OCCUPIED;
+            self.entries += 1;
+            return true;
+        }
+
+        self.slots[fq] |= OCCUPIED;
+
+        let start = self.run_start(fq);
+        let mut s = start;
+
+        if canonical & OCCUPIED != 0 {
+            // find our place within the sorted run
+            loop {
+                let rem = self.remainder(self.slots[s]);
+                if rem == fr {
+                    return false;
+                } else if rem > fr {
+                    break;
+                }
+
+                s = self.incr(s);
+                if self.slots[s] & CONTINUATION == 0 {
+                    break;
+                }
+            }
+
+            if s == start {
+                // the old head of the run will be shifted along behind us
+                self.slots[start] |= CONTINUATION;
+            } else {
+                entry |= CONTINUATION;
+            }
+        }
+
+        if s != fq {
+            entry |= SHIFTED;
+        }
+
+        self.insert_at(s, entry);
+        self.entries += 1;
+        true
+    }
+
+    // shift everything from s onwards up a slot to make room for entry
+    fn insert_at(&mut self, mut s: usize, entry: u64) {
+        let mut curr = entry;
+
+        loop {
+            let mut prev = self.slots[s];
+            let empty = is_empty(prev);
+
+            if !empty {
+                // occupied bits stay with their canonical slot
+                prev |= SHIFTED;
+                if prev & OCCUPIED != 0 {
+                    curr |= OCCUPIED;
+                    prev &= !OCCUPIED;
+                }
+            }
+
+            self.slots[s] = curr;
+            curr = prev;
+            s = self.incr(s);
+
+            if empty {
+                break;
+            }
+        }
+    }
+
+    /// Every stored fingerprint, in ascending order of quotient.
+    pub fn fingerprints(&self) -> Vec<u64> {
+        let mut ret = Vec::with_capacity(self.entries as usize);
+
+        let mut index = match self.slots.iter().position(|s| is_cluster_start(*s)) {
+            Some(start) => start,
+            None => return ret,
+        };
+        let mut quotient = index;
+
+        while (ret.len() as u64) < self.entries {
+            let slot = self.slots[index];
+
+            if is_cluster_start(slot) {
+                quotient = index;
+            } else if is_run_start(slot) {
+                loop {
+                    quotient = self.incr(quotient);
+                    if self.slots[quotient] & OCCUPIED != 0 {
+                        break;
+                    }
+                }
+            }
+
+            if !is_empty(slot) {
+                ret.push(((quotient as u64) << self.r) | self.remainder(slot));
+            }
+
+            index = self.incr(index);
+        }
+
+        ret.sort_unstable();
+        ret
+    }
+
+    /// A copy of this filter with twice as many slots, moving a bit from each
+    /// remainder to the quotient.  The false-positive rate doubles for a given
+    /// load.  Returns `None` if there are no remainder bits to spare.
+    pub fn resized(&self) -> Option<Self> {
+        if self.r <= 1 {
+            return None;
+        }
+
+        let mut ret = Self::from_params(QuotientFilterParams {
+            q: self.q + 1,
+            r: self.r - 1,
+        });
+
+        for f in self.fingerprints() {
+            ret.insert_fingerprint(f);
+        }
+
+        Some(ret)
+    }
+
+    /// Combine two filters with the same fingerprint width, sized to hold
+    /// both at no more than the maximum load.  Returns `None` if the
+    /// fingerprint widths differ, or the result would need more remainder
+    /// bits than there are.
+    pub fn merge(&self, other: &Self) -> Option<Self> {
+        let bits = self.q + self.r;
+        if bits != other.q + other.r {
+            return None;
+        }
+
+        let entries = self.entries + other.entries;
+        let mut q = self.q.max(other.q);
+        while (QuotientFilterParams { q, r: bits - q }).capacity() < entries {
+            q += 1;
+        }
+
+        if q >= bits {
+            return None;
+        }
+
+        let mut ret = Self::from_params(QuotientFilterParams { q, r: bits - q });
+        for f in self.fingerprints().into_iter().chain(other.fingerprints()) {
+            ret.insert_fingerprint(f);
+        }
+
+        Some(ret)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    pub fn false_positive_rate(&self) -> f64 {
+        self.params().false_positive_rate(self.entries)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(self.entries, self.slots.len() as u64, self.r);
+        header.flags = format::FLAG_QUOTIENT;
+        header
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+
+        if !header.is_quotient() || header.is_sparse() || header.is_compressed() {
+            return Err(invalid_data("not a quotient filter"));
+        }
+
+        if !header.m.is_power_of_two() || header.m < 2 {
+            return Err(invalid_data("invalid slot count"));
+        }
+
+        let q = header.m.trailing_zeros();
+        if header.k == 0 || header.k > 61 || q + header.k > 64 {
+            return Err(invalid_data("invalid remainder size"));
+        }
+
+        let mut body = vec![0; header.m as usize * 8];
+        reader.read_exact(&mut body[..])?;
+        header.verify_body(&body)?;
+
+        Ok(Self {
+            q,
+            r: header.k,
+            entries: header.n,
+            slots: body
+                .chunks(8)
+                .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(File::open(path.as_ref())?))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let body: Vec<u8> = self
+            .slots
+            .iter()
+            .flat_map(|slot| slot.to_be_bytes().to_vec())
+            .collect();
+
+        let mut header = self.file_header();
+        header.set_checksums(&body);
+        header.write(&mut writer)?;
+        writer.write_all(&body)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut writer = io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotient_insert_resize_merge() {
+        let mut a = QuotientFilter::with_capacity_p(1000, 0.01);
+        let mut b = QuotientFilter::with_capacity_p(1000, 0.01);
+
+        // fingerprint collisions are reported as duplicates
+        let added = (0..1000).filter(|i| a.insert(*i)).count() as u64;
+        for i in 1000..2000 {
+            b.insert(i);
+        }
+        assert!(!a.insert(0));
+        assert!(added > 990);
+        assert_eq!(added, a.len());
+        assert!((0..1000).all(|i| a.contains(i)));
+        assert!((2000..12_000).filter(|i| a.contains(*i)).count() < 100);
+
+        let mut fps = a.fingerprints();
+        fps.dedup();
+        assert_eq!(added, fps.len() as u64);
+
+        let resized = a.resized().unwrap();
+        assert_eq!(a.q + 1, resized.q);
+        assert_eq!(added, resized.len());
+        assert!((0..1000).all(|i| resized.contains(i)));
+
+        let merged = a.merge(&b).unwrap();
+        assert!(merged.len() > 1980 && merged.len() <= a.len() + b.len());
+        assert!(merged.q > a.q);
+        assert!((0..2000).all(|i| merged.contains(i)));
+        assert!(merged.merge(&resized).is_some());
+    }
+
+    #[test]
+    fn quotient_save_load() {
+        let path = "test_quotient.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut qf = QuotientFilter::with_capacity_p(1000, 0.01);
+        for i in 0..1000 {
+            qf.insert(i);
+        }
+
+        qf.save(path).unwrap();
+        assert_eq!(qf, QuotientFilter::load(path).unwrap());
+        assert!(crate::BloomFilter::load(path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+/// Refreshing a loaded filter from its file, for long-running readers of a
+/// filter another process keeps saving.
+///
+/// A file holding the same filter with page checksums lets `reload` compare
+/// them against its own pages and read back only those which differ, so
+/// picking up a handful of inserts costs a handful of page reads.  Anything
+/// else, such as a file rewritten with different parameters or without
+/// page checksums, is loaded again in full.
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+use crate::format::{self, FileHeader};
+use crate::paged::invalid_data;
+use crate::BloomFilter;
+
+impl BloomFilter {
+    /// Bring the filter up to date with the file at `path`, returning the
+    /// number of pages read.  Fails if the filter has unsaved changes,
+    /// which would be lost, or if the file changes part way through, in
+    /// which case the filter is left as it was and the reload can be tried
+    /// again.
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        if self.is_dirty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "filter has unsaved changes",
+            ));
+        }
+
+        let mut file = File::open(path.as_ref())?;
+        let header = FileHeader::read(&mut file)?;
+        // files written before generations were recorded are all zero
+        if header.generation() != 0 && header.generation() == self.generation {
+            return Ok(0);
+        }
+
+        let sums = match &header.page_checksums {
+            Some(sums)
+                if header.without_checksums() == self.file_header().without_checksums()
+                    && file.metadata()?.len() >= header.file_len() =>
+            {
+                sums
+            }
+            _ => {
+                let preallocate = self.preallocate;
+                *self = Self::load_with_key(path, self.params.hash_key)?;
+                self.preallocate = preallocate;
+                return Ok(self.pages as usize);
+            }
+        };
+
+        let page_size = self.params.page_size as usize;
+        let changed: Vec<usize> = self
+            .filter
+            .as_bytes()
+            .chunks(page_size)
+            .enumerate()
+            .filter(|(index, page)| format::crc32c(page) != sums[*index])
+            .map(|(index, _)| index)
+            .collect();
+
+        // read everything first, so a torn read changes nothing
+        let mut pages = Vec::with_capacity(changed.len());
+        for index in changed.iter().copied() {
+            let mut page = vec![0; page_size];
+            file.seek(io::SeekFrom::Start(
+                (format::HEADER_SIZE + index * page_size) as u64,
+            ))?;
+            file.read_exact(&mut page)?;
+            if format::crc32c(&page) != sums[index] {
+                return Err(invalid_data("page changed while reloading"));
+            }
+            pages.push((index, page));
+        }
+
+        self.filter.with_bytes_mut(|buf| {
+            for (index, page) in &pages {
+                buf[index * page_size..(index + 1) * page_size].copy_from_slice(page);
+            }
+        });
+        for index in changed.iter().copied() {
+            self.changed[index] = header.generation();
+        }
+        self.generation = header.generation();
+        self.count = self.header_count(&header);
+
+        Ok(changed.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomfilter_reload() {
+        let path = "test_reload.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut writer = BloomFilter::with_capacity_p(1_000_000, 0.01);
+        for i in 0..1000u32 {
+            writer.insert(i);
+        }
+        writer.save(path).unwrap();
+
+        let mut reader = BloomFilter::load(path).unwrap();
+        assert_eq!(0, reader.reload(path).unwrap());
+
+        // only the page written to is read back
+        writer.insert(5000u32);
+        writer.save(path).unwrap();
+        assert_eq!(1, reader.reload(path).unwrap());
+        assert!(reader.contains(5000u32));
+        assert_eq!(writer.generation(), reader.generation());
+        assert_eq!(writer.count, reader.count);
+
+        // a file with different parameters is loaded in full
+        let mut other = BloomFilter::with_capacity_p(1000, 0.01);
+        other.insert(7u32);
+        std::fs::remove_file(path).unwrap();
+        other.save(path).unwrap();
+        assert_eq!(other.pages as usize, reader.reload(path).unwrap());
+        assert!(reader.contains(7u32));
+        assert_eq!(other.params.m, reader.params.m);
+
+        reader.insert(8u32);
+        assert!(reader.reload(path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+/// Sliding-window bloom filter, made of a fixed number of generations.
+///
+/// Inserts go to the newest generation, queries check them all, and
+/// `rotate()` starts a new generation while dropping the oldest.  Rotating
+/// once an hour with 24 generations answers "seen in the last 24 hours",
+/// give or take an hour.
+///
+/// Filters are saved to a directory, with each generation in its own file in
+/// the usual single-filter format, so saves only rewrite dirty pages and
+/// rotation only creates one file and deletes another.  A small manifest
+/// records which generations are current:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "ROTBLv00"
+/// 8       4     number of generations kept (big-endian u32)
+/// 12      4     zero padding
+/// 16      8     sequence number of the oldest generation (big-endian u64)
+/// 24      8     sequence number of the newest generation (big-endian u64)
+/// ```
+///
+/// Generation files are named after their sequence number, so rotating never
+/// reuses a name.
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::paged::{invalid_data, write_atomic};
+use crate::{BloomFilter, BloomFilterParams, BloomHash, Durability};
+
+const ROTATING_MAGIC: &[u8; 8] = b"ROTBLv00";
+const ROTATING_HEADER_SIZE: usize = 32;
+const MANIFEST_NAME: &str = "manifest";
+
+#[derive(Debug)]
+pub struct RotatingBloomFilter {
+    generations: usize,
+    /// Sequence number of the oldest generation in `filters`.
+    first_seq: u64,
+    filters: VecDeque<BloomFilter>,
+    /// Generations rotated out since the last save, whose files are pending
+    /// deletion.
+    retired: Vec<u64>,
+    /// Highest sequence number written by the last save, if any.
+    saved_seq: Option<u64>,
+}
+
+fn generation_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("gen-{:016x}.bf", seq))
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl RotatingBloomFilter {
+    /// Keep `generations` filters, each sized by `params`.
+    pub fn new(params: BloomFilterParams, generations: usize) -> Self {
+        assert!(generations > 0);
+
+        let mut filters = VecDeque::with_capacity(generations);
+        filters.push_back(BloomFilter::from_params(params));
+
+        Self {
+            generations,
+            first_seq: 0,
+            filters,
+            retired: vec![],
+            saved_seq: None,
+        }
+    }
+
+    /// Keep `generations` filters, each holding `capacity` items at a
+    /// false-positive rate of `p`.  Checking every generation makes the
+    /// overall rate up to `generations` times higher.
+    pub fn with_capacity_p(capacity: u64, p: f64, generations: usize) -> Self {
+        Self::new(BloomFilterParams::with_capacity_p(capacity, p), generations)
+    }
+
+    fn newest_seq(&self) -> u64 {
+        self.first_seq + self.filters.len() as u64 - 1
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&self.filters[0].rehash(item.into()))
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.filters.iter().any(|filter| filter.contains_hash(hash))
+    }
+
+    /// Insert an item into the newest generation, returning `true` if it was
+    /// not present in any generation.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        let hash = self.filters[0].rehash(item.into());
+        self.insert_hash(&hash)
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let seen = self.contains_hash(hash);
+
+        self.filters.back_mut().unwrap().insert_hash(hash);
+        !seen
+    }
+
+    /// Start a new generation, dropping the oldest if there are already
+    /// `generations` of them.
+    pub fn rotate(&mut self) {
+        let fresh = self.filters.back().unwrap().empty_like();
+        self.filters.push_back(fresh);
+
+        if self.filters.len() > self.generations {
+            self.filters.pop_front();
+            self.retired.push(self.first_seq);
+            self.first_seq += 1;
+        }
+    }
+
+    /// Number of generations currently held.
+    pub fn num_generations(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Maximum number of generations held.
+    pub fn generations(&self) -> usize {
+        self.generations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.iter().all(BloomFilter::is_empty)
+    }
+
+    /// Load the generations saved in the directory `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let dir = path.as_ref();
+
+        let mut header = [0; ROTATING_HEADER_SIZE];
+        fs::File::open(dir.join(MANIFEST_NAME))?.read_exact(&mut header[..])?;
+
+        if &header[0..8] != ROTATING_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let generations = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+        let first_seq = u64::from_be_bytes(header[16..24].try_into().unwrap());
+        let last_seq = u64::from_be_bytes(header[24..32].try_into().unwrap());
+
+        if generations == 0 || last_seq < first_seq || last_seq - first_seq >= generations as u64 {
+            return Err(invalid_data("invalid generations"));
+        }
+
+        let filters = (first_seq..=last_seq)
+            .map(|seq| BloomFilter::load(generation_path(dir, seq)))
+            .collect::<io::Result<VecDeque<_>>>()?;
+
+        let m = filters[0].params.m;
+        if filters.iter().any(|filter| filter.params.m != m) {
+            return Err(invalid_data("mismatched generations"));
+        }
+
+        Ok(Self {
+            generations,
+            first_seq,
+            filters,
+            retired: vec![],
+            saved_seq: Some(last_seq),
+        })
+    }
+
+    /// Save to the directory `path`, creating it if need be.
+    ///
+    /// Generations already saved there only have their dirty pages written,
+    /// and files for generations rotated out are removed once the manifest no
+    /// longer refers to them.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let dir = path.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let first_seq = self.first_seq;
+        let newest_seq = self.newest_seq();
+
+        for (seq, filter) in (first_seq..).zip(self.filters.iter_mut()) {
+            let file = generation_path(dir, seq);
+
+            // a file left by an interrupted save of a generation we've never
+            // written ourselves would otherwise only get our dirty pages
+            if !matches!(self.saved_seq, Some(saved) if saved >= seq) {
+                remove_if_exists(&file)?;
+            }
+
+            filter.save(&file)?;
+        }
+
+        let mut header = [0; ROTATING_HEADER_SIZE];
+        header[0..8].copy_from_slice(ROTATING_MAGIC);
+        header[8..12].copy_from_slice(&(self.generations as u32).to_be_bytes());
+        header[16..24].copy_from_slice(&first_seq.to_be_bytes());
+        header[24..32].copy_from_slice(&newest_seq.to_be_bytes());
+
+        // replace the manifest in one step, so it never refers to a mix of
+        // old and new generations
+        write_atomic(&dir.join(MANIFEST_NAME), Durability::Full, |file| {
+            file.write_all(&header[..])
+        })?;
+
+        self.saved_seq = Some(newest_seq);
+
+        for seq in self.retired.drain(..) {
+            remove_if_exists(&generation_path(dir, seq))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotating_window_save_load() {
+        let dir = Path::new("test_rotating.d");
+        let _ = fs::remove_dir_all(dir);
+
+        let mut rbf = RotatingBloomFilter::with_capacity_p(1000, 0.01, 3);
+        for generation in 0..3 {
+            if generation > 0 {
+                rbf.rotate();
+            }
+            for i in 0..100 {
+                assert!(rbf.insert(generation * 1000 + i));
+            }
+        }
+
+        rbf.save(dir).unwrap();
+        assert!(generation_path(dir, 0).exists());
+
+        rbf.rotate();
+        assert_eq!(3, rbf.num_generations());
+        assert!((0..100).all(|i| !rbf.contains(i)));
+        assert!(rbf.insert(3000));
+        rbf.save(dir).unwrap();
+        assert!(!generation_path(dir, 0).exists());
+
+        let mut rbf = RotatingBloomFilter::load(dir).unwrap();
+        assert_eq!(3, rbf.num_generations());
+        assert!((0..100).all(|i| !rbf.contains(i)));
+        assert!((1000..1100).all(|i| rbf.contains(i)));
+        assert!((2000..2100).all(|i| rbf.contains(i)));
+        assert!(rbf.contains(3000));
+
+        // new generations match the loaded ones
+        rbf.rotate();
+        rbf.save(dir).unwrap();
+        assert!(RotatingBloomFilter::load(dir).is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
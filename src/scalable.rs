@@ -0,0 +1,378 @@
+/// Scalable bloom filter, after Almeida et al.
+///
+/// A chain of `BloomFilter`s, each larger than the last with a tighter false-
+/// positive rate, so the compound rate converges on the requested `p` however
+/// many items are added.  Inserts go to the newest filter; once that is full a
+/// new one is appended.
+///
+/// All filters are saved to a single file: a short header recording the
+/// target `p`, growth factor, tightening ratio and number of filters, followed
+/// by each filter in the usual single-filter format.
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::{BloomFilter, BloomFilterParamsBuilder, BloomHash, ParamsError};
+
+const DEFAULT_GROWTH: u32 = 2;
+const DEFAULT_TIGHTENING: f64 = 0.9;
+
+/// The tightest false-positive rate a generation is given.  Tightening
+/// compounds, so without a floor the rate eventually underflows to zero.
+pub const SCALABLE_MIN_P: f64 = 1e-15;
+
+const SCALABLE_MAGIC: &[u8; 8] = b"SBLOOM00";
+const SCALABLE_HEADER_SIZE: usize = 32;
+
+#[derive(Debug)]
+pub struct ScalableBloomFilter {
+    p: f64,
+    growth: u32,
+    tightening: f64,
+    filters: Vec<BloomFilter>,
+}
+
+/// A snapshot of one generation within a `ScalableBloomFilter`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterStat {
+    pub index: usize,
+    pub capacity: u64,
+    pub count_estimate: u64,
+    /// `count_estimate` as a fraction of `capacity`.
+    pub saturation: f64,
+}
+
+impl ScalableBloomFilter {
+    pub fn with_capacity_p(initial_capacity: u64, p: f64) -> Self {
+        Self::new(initial_capacity, p, DEFAULT_GROWTH, DEFAULT_TIGHTENING)
+    }
+
+    /// Each new filter has `growth` times the capacity of the last, and
+    /// `tightening` times its false-positive rate.
+    pub fn new(initial_capacity: u64, p: f64, growth: u32, tightening: f64) -> Self {
+        assert!(growth >= 1);
+        assert!(tightening > 0.0 && tightening < 1.0);
+
+        let mut ret = Self {
+            p,
+            growth,
+            tightening,
+            filters: vec![],
+        };
+
+        ret.filters.push(BloomFilter::with_capacity_p(
+            initial_capacity,
+            ret.generation_p(0),
+        ));
+        ret
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; SCALABLE_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != SCALABLE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad magic"));
+        }
+
+        let p = f64::from_be_bytes(header[8..16].try_into().unwrap());
+        let tightening = f64::from_be_bytes(header[16..24].try_into().unwrap());
+        let growth = u32::from_be_bytes(header[24..28].try_into().unwrap());
+        let num_filters = u32::from_be_bytes(header[28..32].try_into().unwrap());
+
+        if num_filters == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no filters"));
+        }
+        // as new asserts, so a loaded filter grows as a built one would
+        if growth < 1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid growth"));
+        }
+        if !(tightening > 0.0 && tightening < 1.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid tightening",
+            ));
+        }
+        if !(p > 0.0 && p < 1.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid false-positive rate",
+            ));
+        }
+
+        let filters = (0..num_filters)
+            .map(|_| BloomFilter::from_reader(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            p,
+            growth,
+            tightening,
+            filters,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(File::open(path.as_ref())?))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; SCALABLE_HEADER_SIZE];
+        header[0..8].copy_from_slice(SCALABLE_MAGIC);
+        header[8..16].copy_from_slice(&self.p.to_be_bytes());
+        header[16..24].copy_from_slice(&self.tightening.to_be_bytes());
+        header[24..28].copy_from_slice(&self.growth.to_be_bytes());
+        header[28..32].copy_from_slice(&(self.filters.len() as u32).to_be_bytes());
+        writer.write_all(&header[..])?;
+
+        for filter in &self.filters {
+            filter.write_to(&mut writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write every filter to `path`, replacing any existing file.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut writer = io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        for filter in self.filters.iter_mut() {
+            filter.clear_dirty();
+        }
+
+        Ok(())
+    }
+
+    // p0 = p * (1 - r), giving a compound rate of at most p over the series
+    // until SCALABLE_MIN_P is reached
+    fn generation_p(&self, index: usize) -> f64 {
+        let index = index.min(i32::MAX as usize) as i32;
+        (self.p * (1.0 - self.tightening) * self.tightening.powi(index)).max(SCALABLE_MIN_P)
+    }
+
+    fn grow(&mut self) -> Result<(), ParamsError> {
+        let last = self.filters.last().expect("at least one filter");
+        let capacity = last.params.n.saturating_mul(u64::from(self.growth));
+        let p = self.generation_p(self.filters.len());
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(capacity)
+            .false_positives(p)
+            .to_params()?;
+        self.filters.push(BloomFilter::from_params(params));
+        Ok(())
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.filters.iter().any(|f| f.contains_hash(hash))
+    }
+
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    ///
+    /// If a new filter is needed but can't be sized, the item goes into the
+    /// newest one regardless, at the cost of its false-positive rate.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        match self.try_insert_hash(hash) {
+            Ok(inserted) => inserted,
+            Err(_) => self
+                .filters
+                .last_mut()
+                .expect("at least one filter")
+                .insert_hash(hash),
+        }
+    }
+
+    /// As `insert`, but fail rather than overfill the newest filter when the
+    /// next one would be too large.
+    pub fn try_insert<T: Into<BloomHash>>(&mut self, item: T) -> Result<bool, ParamsError> {
+        self.try_insert_hash(&item.into())
+    }
+
+    /// As `insert_hash`, but fail rather than overfill the newest filter.
+    pub fn try_insert_hash(&mut self, hash: &BloomHash) -> Result<bool, ParamsError> {
+        if self.contains_hash(hash) {
+            return Ok(false);
+        }
+
+        if self.filters.last().expect("at least one filter").is_full() {
+            self.grow()?;
+        }
+
+        Ok(self
+            .filters
+            .last_mut()
+            .expect("at least one filter")
+            .insert_hash(hash))
+    }
+
+    pub fn num_filters(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn count_estimate(&self) -> u64 {
+        self.filters.iter().map(|f| f.count_estimate()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.iter().all(|f| f.is_empty())
+    }
+
+    pub fn filter_stats(&self) -> Vec<FilterStat> {
+        self.filters
+            .iter()
+            .enumerate()
+            .map(|(index, f)| {
+                let count_estimate = f.count_estimate();
+                FilterStat {
+                    index,
+                    capacity: f.params.n,
+                    count_estimate,
+                    saturation: count_estimate as f64 / f.params.n as f64,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalable_stats() {
+        let mut sbf = ScalableBloomFilter::with_capacity_p(1000, 0.01);
+        let initial = sbf.filter_stats()[0].capacity;
+
+        for i in 0..(initial + 1000) {
+            sbf.insert(i);
+        }
+
+        let stats = sbf.filter_stats();
+        assert_eq!(2, sbf.num_filters());
+        assert_eq!(2, stats.len());
+        assert_eq!(0, stats[0].index);
+        assert_eq!(1, stats[1].index);
+        assert!(stats[1].capacity > stats[0].capacity);
+        assert!(stats[0].saturation > 0.9);
+        assert!(stats[1].saturation < stats[0].saturation);
+
+        for i in 0..(initial + 1000) {
+            assert!(sbf.contains(i));
+        }
+    }
+
+    #[test]
+    fn scalable_hashes() {
+        let mut sbf = ScalableBloomFilter::with_capacity_p(1000, 0.01);
+        let initial = sbf.filter_stats()[0].capacity;
+        let hashes: Vec<BloomHash> = (0..(initial + 1000)).map(BloomHash::from).collect();
+
+        for hash in &hashes {
+            sbf.insert_hash(hash);
+        }
+
+        assert_eq!(2, sbf.num_filters());
+        assert!(!sbf.insert_hash(&hashes[0]));
+        assert!(hashes.iter().all(|hash| sbf.contains_hash(hash)));
+        assert!((0..(initial + 1000)).all(|i| sbf.contains(i)));
+    }
+
+    #[test]
+    fn scalable_tightening_floor() {
+        let mut sbf = ScalableBloomFilter::new(10, 0.01, 1, 0.1);
+
+        // 0.1^400 underflows, so without the floor these would reach zero
+        assert_eq!(SCALABLE_MIN_P, sbf.generation_p(400));
+        assert_eq!(SCALABLE_MIN_P, sbf.generation_p(usize::MAX));
+
+        // grow past the generation where the rate would underflow
+        while sbf.num_filters() <= 400 {
+            sbf.grow().unwrap();
+        }
+        assert!(sbf.filters.iter().all(|f| f.params.p > 0.0));
+
+        for i in 0..1000 {
+            sbf.try_insert(i).unwrap();
+        }
+        assert!((0..1000).all(|i| sbf.contains(i)));
+    }
+
+    #[test]
+    fn scalable_save_load() {
+        let path = "test_scalable.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut sbf = ScalableBloomFilter::new(1000, 0.01, 4, 0.8);
+        let initial = sbf.filter_stats()[0].capacity;
+
+        for i in 0..(initial + 1000) {
+            sbf.insert(i);
+        }
+
+        sbf.save(path).unwrap();
+
+        let loaded = ScalableBloomFilter::load(path).unwrap();
+        assert_eq!(2, loaded.num_filters());
+        assert_eq!(4, loaded.growth);
+        assert_eq!(0.8, loaded.tightening);
+        assert_eq!(sbf.filter_stats(), loaded.filter_stats());
+
+        for i in 0..(initial + 1000) {
+            assert!(loaded.contains(i));
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn scalable_reject_invalid() {
+        let sbf = ScalableBloomFilter::new(1000, 0.01, 2, 0.5);
+        let mut buf = vec![];
+        sbf.write_to(&mut buf).unwrap();
+        assert!(ScalableBloomFilter::from_reader(&buf[..]).is_ok());
+
+        let corrupt = |range: std::ops::Range<usize>, bytes: &[u8]| {
+            let mut buf = buf.clone();
+            buf[range].copy_from_slice(bytes);
+            ScalableBloomFilter::from_reader(&buf[..])
+                .unwrap_err()
+                .kind()
+        };
+
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            corrupt(24..28, &0u32.to_be_bytes())
+        );
+        for tightening in [0.0, 1.0, -0.5, f64::NAN] {
+            assert_eq!(
+                io::ErrorKind::InvalidData,
+                corrupt(16..24, &f64::to_be_bytes(tightening))
+            );
+        }
+        for p in [0.0, 1.0, 1.5, f64::NAN] {
+            assert_eq!(
+                io::ErrorKind::InvalidData,
+                corrupt(8..16, &f64::to_be_bytes(p))
+            );
+        }
+    }
+}
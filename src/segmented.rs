@@ -0,0 +1,155 @@
+/// Filters saved as a directory of segment files rather than one large file.
+///
+/// The directory holds a `header` file, with checksums covering the whole
+/// body, and the body split into `segment.NNNNN` files of
+/// `format::SEGMENT_PAGES` pages each.  A save rewrites only segments holding
+/// dirty pages, each replaced atomically, and then the header, which makes
+/// incremental backups of a large filter cheap and keeps every file well
+/// under filesystem size limits.
+///
+/// A crash between replacing segments and the header leaves a directory
+/// which fails its checksums on load, rather than a silently mixed filter.
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::paged::{self, invalid_data};
+use crate::{check_hash_key, header_params, BloomFilter};
+
+const HEADER_NAME: &str = "header";
+
+fn segment_path(dir: &Path, segment: usize) -> PathBuf {
+    dir.join(format!("segment.{:05}", segment))
+}
+
+impl BloomFilter {
+    /// Save the filter as a directory of segment files at `dir`, creating it
+    /// if necessary.  Only segments with dirty pages are rewritten, unless
+    /// the directory holds a different filter.
+    pub fn save_segmented<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        self.generation += 1;
+        let mut header = self.file_header();
+        header.flags |= format::FLAG_SEGMENTED;
+
+        let bytes = self.filter.as_bytes();
+        let segment_len = self.params.page_size as usize * format::SEGMENT_PAGES;
+        let existing = File::open(dir.join(HEADER_NAME))
+            .and_then(FileHeader::read)
+            .ok()
+            .filter(|existing| existing.without_checksums() == header.without_checksums());
+
+        let full = existing.is_none();
+        let dirty = paged::dirty_pages(&self.dirty);
+
+        match existing {
+            Some(existing) if existing.is_checksummed() => {
+                header = existing;
+                header.update_checksums(bytes, dirty.iter().copied());
+                self.stamp(&mut header);
+            }
+            _ => header.set_checksums(bytes),
+        }
+
+        for (segment, body) in bytes.chunks(segment_len).enumerate() {
+            let path = segment_path(dir, segment);
+            let pages = segment * format::SEGMENT_PAGES..(segment + 1) * format::SEGMENT_PAGES;
+            let stale = full
+                || dirty.iter().any(|page| pages.contains(page))
+                || fs::metadata(&path).map_or(true, |meta| meta.len() != body.len() as u64);
+
+            if stale {
+                paged::write_atomic(&path, self.durability, |file| {
+                    paged::write_sparse(file, body)
+                })?;
+            }
+        }
+
+        paged::write_atomic(&dir.join(HEADER_NAME), self.durability, |file| {
+            header.write(file)
+        })?;
+        self.clear_dirty();
+
+        Ok(())
+    }
+
+    /// Load a filter saved with `save_segmented`.
+    pub fn load_segmented<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let header = FileHeader::read(File::open(dir.join(HEADER_NAME))?)?;
+        if !header.is_segmented() {
+            return Err(invalid_data("not a segmented filter"));
+        }
+        let params = header_params(&header)?;
+        check_hash_key(&params, (0, 0))?;
+
+        let segment_len = params.page_size as usize * format::SEGMENT_PAGES;
+        let mut filter = BitVec::from_elem((params.m / 8 * 8) as usize, false);
+        filter.with_bytes_mut(|buf| {
+            for (segment, body) in buf.chunks_mut(segment_len).enumerate() {
+                let mut file = File::open(segment_path(dir, segment))?;
+                file.read_exact(body)?;
+            }
+            Ok::<_, io::Error>(())
+        })?;
+        header.verify_body(filter.as_bytes())?;
+
+        Ok(Self::from_loaded(params, filter, &header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn bloomfilter_segmented() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = std::path::Path::new("test_segmented.bf");
+        let _ = std::fs::remove_dir_all(dir);
+
+        // two segments, the last one short
+        let params = BloomFilterParamsBuilder::default()
+            .bits(u64::from(format::PAGE_SIZE) * 8 * (format::SEGMENT_PAGES as u64 + 10))
+            .capacity(1_000_000)
+            .to_params()
+            .unwrap();
+        let mut bf = BloomFilter::from_params(params);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        bf.save_segmented(dir).unwrap();
+        assert!(dir.join("segment.00001").exists());
+        assert!(BloomFilter::load(dir.join("header")).is_err());
+
+        let loaded = BloomFilter::load_segmented(dir).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+        assert_eq!(bf.digest(), loaded.digest());
+
+        // only the segment holding the dirty page is replaced
+        let before: Vec<_> = (0..2)
+            .map(|s| dir.join(format!("segment.{:05}", s)))
+            .map(|path| std::fs::metadata(path).unwrap().ino())
+            .collect();
+        bf.insert(5000u32);
+        bf.save_segmented(dir).unwrap();
+        let after: Vec<_> = (0..2)
+            .map(|s| dir.join(format!("segment.{:05}", s)))
+            .map(|path| std::fs::metadata(path).unwrap().ino())
+            .collect();
+        assert_eq!(1, before.iter().zip(&after).filter(|(a, b)| a != b).count());
+
+        let loaded = BloomFilter::load_segmented(dir).unwrap();
+        assert!(loaded.contains(5000u32));
+        assert_eq!(bf.digest(), loaded.digest());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
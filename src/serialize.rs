@@ -0,0 +1,121 @@
+/// Serde support, so filters can be kept in larger application state and
+/// serialized with it in whatever format that uses.
+///
+/// A `BloomFilter` serializes as its params, the generation of its latest
+/// save, its count and its bit array, and deserializes as a clean filter
+/// with its settings at their defaults.  The file format's checksums and
+/// flags are left to the enclosing format, so for filters kept on their own
+/// `write_to` and `from_reader` remain the better choice.
+use bitvec_rs::BitVec;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::format;
+use crate::{BloomFilter, BloomFilterParams, BloomFilterParamsBuilder};
+
+#[derive(Serialize)]
+struct FilterRef<'a> {
+    params: &'a BloomFilterParams,
+    generation: u64,
+    count: u64,
+    bits: &'a [u8],
+}
+
+#[derive(Deserialize)]
+struct FilterOwned {
+    params: BloomFilterParams,
+    generation: u64,
+    count: u64,
+    bits: Vec<u8>,
+}
+
+impl Serialize for BloomFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FilterRef {
+            params: &self.params,
+            generation: self.generation,
+            count: self.count,
+            bits: self.filter.as_bytes(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BloomFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let FilterOwned {
+            params,
+            generation,
+            count,
+            bits,
+        } = FilterOwned::deserialize(deserializer)?;
+
+        if !format::is_valid_page_size(params.page_size)
+            || params.m == 0
+            || params.m % (u64::from(params.page_size) * 8) != 0
+        {
+            return Err(D::Error::custom("invalid filter geometry"));
+        }
+        if bits.len() != (params.m / 8) as usize {
+            return Err(D::Error::custom("bit array doesn't match params"));
+        }
+
+        // recalculate p rather than trust it
+        let checked = BloomFilterParamsBuilder::default()
+            .capacity(params.n)
+            .bits(params.m)
+            .hashes(params.k)
+            .layout(params.layout)
+            .page_size(params.page_size)
+            .hash_key(params.hash_key.0, params.hash_key.1)
+            .hash_algorithm(params.hash_algorithm)
+            .to_params()
+            .map_err(|e| D::Error::custom(format!("invalid filter params: {}", e)))?;
+
+        let mut filter = BitVec::from_elem(params.m as usize, false);
+        filter.with_bytes_mut(|buf| buf.copy_from_slice(&bits));
+
+        let mut ret = BloomFilter::from_bits(
+            BloomFilterParams {
+                requested_p: params.requested_p,
+                ..checked
+            },
+            filter,
+            generation,
+        );
+        ret.count = count;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_serde() {
+        let mut bf = BloomFilter::with_capacity_p(10_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+
+        let json = serde_json::to_string(&bf).unwrap();
+        let loaded: BloomFilter = serde_json::from_str(&json).unwrap();
+        assert_eq!(bf.params, loaded.params);
+        assert_eq!(bf.count, loaded.count);
+        assert_eq!(bf.digest(), loaded.digest());
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+        assert!(!loaded.is_dirty());
+
+        let params: BloomFilterParams =
+            serde_json::from_str(&serde_json::to_string(&bf.params).unwrap()).unwrap();
+        assert_eq!(bf.params, params);
+        let hash = BloomHash::from("meep");
+        let copy: BloomHash = serde_json::from_str(&serde_json::to_string(&hash).unwrap()).unwrap();
+        assert_eq!(hash, copy);
+
+        // a bit array which doesn't match the params is refused
+        let long = json.replace("\"bits\":[", "\"bits\":[0,");
+        assert!(serde_json::from_str::<BloomFilter>(&long).is_err());
+    }
+}
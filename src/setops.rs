@@ -0,0 +1,199 @@
+/// Set operations between filters built separately with the same geometry,
+/// such as one per shard or machine.
+///
+/// Filters can only be combined bit for bit when they agree on the number of
+/// bits, the number of probes, how those probes are laid out over pages, and
+/// the key and algorithm items are hashed with; `is_compatible` checks them
+/// all.
+use crate::BloomFilter;
+
+impl BloomFilter {
+    /// Whether `other` has the same geometry and hash key, and so can be
+    /// combined with this filter.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.params.m == other.params.m
+            && self.params.k == other.params.k
+            && self.params.layout == other.params.layout
+            && self.params.page_size == other.params.page_size
+            && self.params.hash_key == other.params.hash_key
+            && self.params.hash_algorithm == other.params.hash_algorithm
+    }
+
+    /// Bitwise-or `other` into this filter, which then holds every item of
+    /// both.  Pages gaining bits are marked dirty for the next `save`.
+    ///
+    /// Panics if the filters are not compatible.
+    pub fn union(&mut self, other: &Self) {
+        assert!(self.is_compatible(other), "incompatible filter geometry");
+
+        let page_size = self.params.page_size as usize;
+        let mut changed = vec![];
+        self.filter.with_bytes_mut(|bytes| {
+            for (index, (dst, src)) in bytes
+                .chunks_mut(page_size)
+                .zip(other.filter.as_bytes().chunks(page_size))
+                .enumerate()
+            {
+                if dst.iter().zip(src).any(|(a, b)| b & !a != 0) {
+                    for (a, b) in dst.iter_mut().zip(src) {
+                        *a |= *b;
+                    }
+                    changed.push(index);
+                }
+            }
+        });
+
+        for index in changed {
+            self.mark_dirty(index);
+        }
+        self.count = self.count_estimate();
+    }
+
+    /// A new filter holding the bitwise-and of this and `other`.
+    ///
+    /// Every item in both filters is in the result, but so are items whose
+    /// bits were set in one by some items and in the other by others, so it
+    /// answers positive more often than a filter built from the true
+    /// intersection would, increasingly so as the inputs fill.  Its count
+    /// estimate is inflated to match.
+    ///
+    /// Panics if the filters are not compatible.
+    pub fn intersect(&self, other: &Self) -> Self {
+        assert!(self.is_compatible(other), "incompatible filter geometry");
+
+        let mut ret = self.empty_like();
+        let src = self.filter.as_bytes();
+        ret.filter.with_bytes_mut(|bytes| {
+            for ((dst, a), b) in bytes.iter_mut().zip(src).zip(other.filter.as_bytes()) {
+                *dst = a & b;
+            }
+        });
+
+        ret.count = ret.count_estimate();
+        ret.mark_all_dirty();
+        ret
+    }
+
+    /// Estimated number of items in both filters, by inclusion-exclusion
+    /// over the estimated sizes of each and of their union, following
+    /// Swamidass and Baldi.  Unlike the count of `intersect`, this isn't
+    /// inflated by bits coincidentally set in both.
+    ///
+    /// Panics if the filters are not compatible.
+    pub fn estimate_intersection_count(&self, other: &Self) -> u32 {
+        let (a, b, union) = self.estimate_sizes(other);
+        (a + b - union).max(0.0).round() as u32
+    }
+
+    /// Estimated Jaccard similarity of the two filters' items: the size of
+    /// their intersection over that of their union.  Two empty filters are
+    /// taken to be identical.
+    ///
+    /// Panics if the filters are not compatible.
+    pub fn estimate_jaccard(&self, other: &Self) -> f64 {
+        let (a, b, union) = self.estimate_sizes(other);
+        if union <= 0.0 {
+            return 1.0;
+        }
+        ((a + b - union) / union).clamp(0.0, 1.0)
+    }
+
+    /// Estimated item counts of each filter and of their union, from the
+    /// number of bits set in each and in their bitwise-or.
+    fn estimate_sizes(&self, other: &Self) -> (f64, f64, f64) {
+        assert!(self.is_compatible(other), "incompatible filter geometry");
+
+        let (mut a, mut b, mut union) = (0u64, 0u64, 0u64);
+        for (x, y) in self.filter.as_bytes().iter().zip(other.filter.as_bytes()) {
+            a += u64::from(x.count_ones());
+            b += u64::from(y.count_ones());
+            union += u64::from((x | y).count_ones());
+        }
+
+        let m = self.params.m as f64;
+        let k = f64::from(self.params.k);
+        let size = |ones: u64| -(m / k) * (1.0 - ones as f64 / m).ln();
+        (size(a), size(b), size(union))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_union() {
+        let mut a = BloomFilter::with_capacity_p(10_000, 0.01);
+        let mut b = a.empty_like();
+        for i in 0..1000u32 {
+            a.insert(i);
+            b.insert(i + 1000);
+        }
+        a.clear_dirty();
+
+        a.union(&b);
+        assert!((0..2000u32).all(|i| a.contains(i)));
+        assert!(a.is_dirty());
+        assert!((a.count_estimate() as i64 - 2000).abs() < 100);
+
+        // nothing new, nothing dirty
+        a.clear_dirty();
+        a.union(&b);
+        assert!(!a.is_dirty());
+
+        let keyed = BloomFilter::from_params(
+            BloomFilterParamsBuilder::default()
+                .capacity(10_000)
+                .false_positives(0.01)
+                .hash_key(1, 2)
+                .to_params()
+                .unwrap(),
+        );
+        assert!(!a.is_compatible(&keyed));
+        assert!(!a.is_compatible(&BloomFilter::with_capacity_p(20_000, 0.01)));
+    }
+
+    #[test]
+    fn bloomfilter_intersect() {
+        let mut a = BloomFilter::with_capacity_p(10_000, 0.01);
+        let mut b = a.empty_like();
+        for i in 0..1000u32 {
+            a.insert(i);
+            b.insert(i + 500);
+        }
+
+        let both = a.intersect(&b);
+        assert!((500..1000u32).all(|i| both.contains(i)));
+        let stray = (0..500u32)
+            .chain(1000..1500)
+            .filter(|i| both.contains(*i))
+            .count();
+        assert!(stray < 50);
+        assert!(both.count_estimate() >= 450);
+
+        // the inputs are untouched
+        assert!(a.contains(0u32) && !a.contains(1400u32));
+    }
+
+    #[test]
+    fn bloomfilter_estimate_jaccard() {
+        let mut a = BloomFilter::with_capacity_p(100_000, 0.01);
+        let mut b = a.empty_like();
+        for i in 0..10_000u32 {
+            a.insert(i);
+            b.insert(i + 5000);
+        }
+
+        // 5000 shared of 15000 in all
+        let shared = a.estimate_intersection_count(&b);
+        assert!((shared as i64 - 5000).abs() < 250, "{}", shared);
+        let jaccard = a.estimate_jaccard(&b);
+        assert!((jaccard - 1.0 / 3.0).abs() < 0.02, "{}", jaccard);
+
+        assert!((a.estimate_jaccard(&a) - 1.0).abs() < 1e-9);
+        let empty = a.empty_like();
+        assert_eq!(0, a.estimate_intersection_count(&empty));
+        assert_eq!(0.0, a.estimate_jaccard(&empty));
+        assert_eq!(1.0, empty.estimate_jaccard(&empty));
+    }
+}
@@ -0,0 +1,143 @@
+/// Shifting bloom filter, after Yang et al., answering whether an item was
+/// inserted at least c times.
+///
+/// The c-th insert of an item sets its usual k probes shifted c - 1 bits
+/// along, so an item inserted c times has its probes set at every shift
+/// below c.  The multiplicity is the number of consecutive shifts, from
+/// zero, at which all of its probes are set.  Counts are exact when no other
+/// item's probes intrude, and otherwise may only be overestimated.
+///
+/// Probing is the same as `BloomFilter`'s, shifts wrapping within the page or
+/// segment each probe falls in, and the filter is saved in the same format:
+/// loaded as a plain `BloomFilter` it answers `contains` correctly, if with a
+/// higher false-positive rate.  The maximum count is not saved.
+///
+/// Each insert consumes capacity like inserting a new item into a plain
+/// filter, so size the filter for the total number of inserts.
+use std::io;
+use std::path::Path;
+
+use crate::{BloomFilter, BloomFilterParams, BloomHash};
+
+#[derive(Debug)]
+pub struct ShiftingBloomFilter {
+    filter: BloomFilter,
+    max_count: u32,
+}
+
+impl ShiftingBloomFilter {
+    /// A filter counting each item up to `max_count` inserts.
+    pub fn new(params: BloomFilterParams, max_count: u32) -> Self {
+        Self::from_filter(BloomFilter::from_params(params), max_count)
+    }
+
+    pub fn with_capacity_p(capacity: u64, p: f64, max_count: u32) -> Self {
+        Self::new(BloomFilterParams::with_capacity_p(capacity, p), max_count)
+    }
+
+    fn from_filter(filter: BloomFilter, max_count: u32) -> Self {
+        assert!(max_count > 0);
+
+        Self { filter, max_count }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, max_count: u32) -> io::Result<Self> {
+        Ok(Self::from_filter(BloomFilter::load(path)?, max_count))
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.filter.save(path)
+    }
+
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    /// Insert an item, returning its estimated count afterwards, which
+    /// saturates at `max_count()`.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> u32 {
+        self.insert_hash(&self.filter.rehash(item.into()))
+    }
+
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> u32 {
+        let count = self.count_hash(hash);
+        if count == self.max_count {
+            return count;
+        }
+
+        self.filter.insert_shifted(*hash, u64::from(count));
+        count + 1
+    }
+
+    /// Estimated number of inserts of an item, at most `max_count()`.
+    pub fn count<T: Into<BloomHash>>(&self, item: T) -> u32 {
+        self.count_hash(&self.filter.rehash(item.into()))
+    }
+
+    pub fn count_hash(&self, hash: &BloomHash) -> u32 {
+        (0..self.max_count)
+            .take_while(|shift| self.filter.probe_shifted(*hash, u64::from(*shift)))
+            .count() as u32
+    }
+
+    /// Whether an item may have been inserted at least `count` times.
+    pub fn contains_at_least<T: Into<BloomHash>>(&self, item: T, count: u32) -> bool {
+        match count {
+            0 => true,
+            c if c > self.max_count => false,
+            c => self
+                .filter
+                .probe_shifted(self.filter.rehash(item.into()), u64::from(c - 1)),
+        }
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_at_least(item, 1)
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.filter.probe_shifted(*hash, 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifting_counts_save_load() {
+        let path = "test_shifting.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut sbf = ShiftingBloomFilter::with_capacity_p(20_000, 0.001, 8);
+        for i in 0..1000u32 {
+            for _ in 0..i % 5 + 1 {
+                sbf.insert(i);
+            }
+        }
+        for _ in 0..20 {
+            sbf.insert("many");
+        }
+
+        assert_eq!(8, sbf.count("many"));
+        assert_eq!(0, sbf.count("none"));
+        assert!(sbf.contains_hash(&BloomHash::from("many")));
+        assert!(!sbf.contains_hash(&BloomHash::from("none")));
+        assert!((0..1000u32).all(|i| sbf.contains_at_least(i, i % 5 + 1)));
+        assert!((0..1000u32).filter(|i| sbf.count(*i) != i % 5 + 1).count() < 10);
+
+        sbf.save(path).unwrap();
+        let loaded = ShiftingBloomFilter::load(path, 8).unwrap();
+        assert!((0..1000u32).all(|i| loaded.count(i) > i % 5));
+
+        let plain = BloomFilter::load(path).unwrap();
+        assert!((0..1000u32).all(|i| plain.contains(i)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+/// Immutable point-in-time views of a filter, so a background thread can
+/// write out a consistent image while inserts carry on.
+///
+/// A `Snapshot` holds its own copy of each page, shared with the next
+/// snapshot taken while it's still alive wherever the page hasn't changed
+/// in between, so regular snapshots of a filter mostly updated in a few
+/// places copy little.  Snapshots are cheap to clone and can be sent between
+/// threads.
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::format::{self, FileHeader};
+use crate::hasher::CustomHasher;
+use crate::{lsb_first, probe_bits, BloomFilter, BloomFilterParams, BloomHash};
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    params: BloomFilterParams,
+    hasher: Option<CustomHasher>,
+    pages: Arc<Vec<Arc<[u8]>>>,
+    header: FileHeader,
+    lsb_first: bool,
+}
+
+impl BloomFilter {
+    /// A view of the filter as it is now, unaffected by later changes.
+    pub fn snapshot(&mut self) -> Snapshot {
+        let previous = self.snapshot.upgrade();
+        let page_size = self.params.page_size as usize;
+
+        let pages: Vec<Arc<[u8]>> = self
+            .filter
+            .as_bytes()
+            .chunks(page_size)
+            .enumerate()
+            .map(|(index, page)| match previous.as_ref().map(|p| &p[index]) {
+                Some(shared) if shared[..] == *page => Arc::clone(shared),
+                _ => Arc::from(page),
+            })
+            .collect();
+
+        let pages = Arc::new(pages);
+        self.snapshot = Arc::downgrade(&pages);
+
+        Snapshot {
+            params: self.params.clone(),
+            hasher: self.hasher.clone(),
+            pages,
+            header: self.file_header(),
+            lsb_first: lsb_first(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn params(&self) -> &BloomFilterParams {
+        &self.params
+    }
+
+    /// The generation of the filter's latest save when the snapshot was
+    /// taken.
+    pub fn generation(&self) -> u64 {
+        self.header.generation()
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        let hash = item.into();
+        self.contains_hash(&match &self.hasher {
+            Some(hasher) => hasher.rehash(hash),
+            None => self.params.rehash(hash),
+        })
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        let page_bits = u64::from(self.params.page_size) * 8;
+        let pages = self.params.m / (u64::from(self.params.page_size) * 8);
+
+        probe_bits(&self.params, pages, *hash, 0).all(|bit| {
+            let page = &self.pages[(bit / page_bits) as usize];
+            let shift = (bit % 8) as u8;
+            let mask = if self.lsb_first {
+                1 << shift
+            } else {
+                0x80 >> shift
+            };
+            page[(bit % page_bits / 8) as usize] & mask != 0
+        })
+    }
+
+    /// Write the snapshot, as `BloomFilter::write_to` would have written the
+    /// filter when it was taken.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = self.header.clone();
+        header.set_page_checksums(self.pages.iter().map(|page| format::crc32c(page)).collect());
+        header.write(&mut writer)?;
+
+        for page in self.pages.iter() {
+            writer.write_all(page)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the snapshot shares page `index` with `other`, rather than
+    /// each holding a copy.
+    #[cfg(test)]
+    fn shares_page(&self, other: &Snapshot, index: usize) -> bool {
+        Arc::ptr_eq(&self.pages[index], &other.pages[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomfilter_snapshot() {
+        let mut bf = BloomFilter::with_capacity_p(1_000_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+
+        let before = bf.snapshot();
+        let mut expected = vec![];
+        bf.write_to(&mut expected).unwrap();
+
+        // later inserts don't show through
+        let hash = BloomHash::from(5000u32);
+        bf.insert_hash(&hash);
+        assert!(!before.contains_hash(&hash));
+        assert!((0..1000u32).all(|i| before.contains(i)));
+
+        let writer = {
+            let before = before.clone();
+            std::thread::spawn(move || {
+                let mut buf = vec![];
+                before.write_to(&mut buf).unwrap();
+                buf
+            })
+        };
+        assert_eq!(expected, writer.join().unwrap());
+
+        // unchanged pages are shared with the previous snapshot
+        let after = bf.snapshot();
+        assert!(after.contains_hash(&hash));
+        let changed = (0..before.pages.len())
+            .filter(|index| !after.shares_page(&before, *index))
+            .count();
+        assert_eq!(1, changed);
+
+        let loaded = BloomFilter::from_reader(&expected[..]).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+    }
+}
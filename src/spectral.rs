@@ -0,0 +1,243 @@
+/// Spectral bloom filter, after Cohen and Matias, for estimating how many
+/// times each item has been inserted.
+///
+/// Each bit of the plain filter becomes a 16-bit counter.  The estimate for an
+/// item is the minimum of its k counters, and inserts use minimal increase:
+/// only the counters at that minimum are incremented, which keeps the
+/// overestimates caused by collisions much smaller than incrementing them
+/// all.  The price is that items cannot be removed.
+///
+/// Counters share the paged layout of the other filters, and files share
+/// their header with `FLAG_SPECTRAL` set and a body of two big-endian bytes per
+/// counter.
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::paged::{invalid_data, save_paged};
+use crate::{BloomFilterParams, BloomFilterParamsBuilder, BloomHash, BLOOM_PAGE_SIZE};
+
+const COUNTER_BYTES: u64 = 2;
+const SPECTRAL_PAGE_SLOTS: u64 = BLOOM_PAGE_SIZE as u64 / COUNTER_BYTES;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectralBloomFilter {
+    params: BloomFilterParams,
+    count: u64,
+    pages: u64,
+    dirty: BitVec,
+    counters: Vec<u8>,
+}
+
+impl SpectralBloomFilter {
+    pub fn from_params(params: BloomFilterParams) -> Self {
+        let requested_p = params.requested_p.unwrap_or(params.p);
+        let params = BloomFilterParamsBuilder::default()
+            .bits(params.m + (SPECTRAL_PAGE_SLOTS - (params.m % SPECTRAL_PAGE_SLOTS)))
+            .false_positives(params.p)
+            .to_params()
+            .map(|params| BloomFilterParams {
+                requested_p: Some(requested_p),
+                ..params
+            })
+            .unwrap();
+
+        let pages = params.m / SPECTRAL_PAGE_SLOTS;
+
+        Self {
+            dirty: BitVec::from_elem(pages as usize, false),
+            counters: vec![0; (params.m * COUNTER_BYTES) as usize],
+            count: 0,
+            pages,
+            params,
+        }
+    }
+
+    pub fn with_capacity_p(capacity: u64, p: f64) -> Self {
+        Self::from_params(BloomFilterParams::with_capacity_p(capacity, p))
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let header = FileHeader::read(&mut reader)?;
+
+        if !header.is_spectral() || header.is_sparse() || header.is_compressed() {
+            return Err(invalid_data("not a spectral filter"));
+        }
+
+        let params = BloomFilterParamsBuilder::default()
+            .capacity(header.n)
+            .bits(header.m)
+            .hashes(header.k)
+            .to_params()
+            .map_err(|e| invalid_data(&format!("invalid parameters: {}", e)))?;
+
+        if params.m % SPECTRAL_PAGE_SLOTS != 0 {
+            return Err(invalid_data("invalid counter count"));
+        }
+
+        let mut counters = vec![0; header.body_len() as usize];
+        reader.read_exact(&mut counters[..])?;
+        header.verify_body(&counters)?;
+
+        let pages = params.m / SPECTRAL_PAGE_SLOTS;
+
+        let mut ret = Self {
+            dirty: BitVec::from_elem(pages as usize, false),
+            counters,
+            count: 0,
+            pages,
+            params,
+        };
+
+        // with minimal increase each insert bumps at least one counter, so
+        // this is a lower bound rather than the true count
+        ret.count = (0..ret.params.m as usize)
+            .map(|slot| u64::from(ret.get(slot)))
+            .sum::<u64>()
+            / u64::from(ret.params.k.max(1));
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(self.params.n, self.params.m, self.params.k);
+        header.flags = format::FLAG_SPECTRAL;
+        header
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        save_paged(
+            path.as_ref(),
+            &self.file_header(),
+            &self.counters[..],
+            &mut self.dirty,
+        )
+    }
+
+    fn page(&self, hash: BloomHash) -> u64 {
+        if self.pages > 0 {
+            hash.nth(self.params.k + 1) % self.pages
+        } else {
+            0
+        }
+    }
+
+    fn slots(&self, hash: BloomHash) -> impl Iterator<Item = usize> {
+        let offset = self.page(hash) * SPECTRAL_PAGE_SLOTS;
+
+        (0..self.params.k).map(move |k| (offset + (hash.nth(k) % SPECTRAL_PAGE_SLOTS)) as usize)
+    }
+
+    fn get(&self, slot: usize) -> u16 {
+        let i = slot * COUNTER_BYTES as usize;
+        u16::from_be_bytes([self.counters[i], self.counters[i + 1]])
+    }
+
+    fn set(&mut self, slot: usize, value: u16) {
+        let i = slot * COUNTER_BYTES as usize;
+        self.counters[i..i + COUNTER_BYTES as usize].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Estimated number of times an item has been inserted.  This is never
+    /// an underestimate, unless counters have saturated at `u16::MAX`.
+    pub fn estimate_count<T: Into<BloomHash>>(&self, item: T) -> u16 {
+        self.slots(item.into())
+            .map(|slot| self.get(slot))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.estimate_count(item) > 0
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        self.estimate_count(*hash) > 0
+    }
+
+    /// Insert an item, returning its new estimated count.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> u16 {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> u16 {
+        let hash = *hash;
+        let min = self.estimate_count(hash);
+
+        if min == u16::MAX {
+            return min;
+        }
+
+        let slots: Vec<usize> = self.slots(hash).collect();
+        for slot in slots {
+            if self.get(slot) == min {
+                self.set(slot, min + 1);
+            }
+        }
+
+        self.count += 1;
+        self.dirty.set(self.page(hash) as usize, true);
+        min + 1
+    }
+
+    /// Total number of inserts.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl From<BloomFilterParams> for SpectralBloomFilter {
+    fn from(p: BloomFilterParams) -> Self {
+        Self::from_params(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spectral_estimate_save_load() {
+        let path = "test_spectral.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut sbf = SpectralBloomFilter::with_capacity_p(1000, 0.01);
+        for i in 0..1000u32 {
+            for _ in 0..=(i % 5) {
+                sbf.insert(i);
+            }
+        }
+
+        assert!((0..1000u32).all(|i| sbf.estimate_count(i) > (i % 5) as u16));
+        let exact = (0..1000u32)
+            .filter(|i| sbf.estimate_count(*i) == (i % 5 + 1) as u16)
+            .count();
+        assert!(exact > 980);
+        assert_eq!(0, sbf.estimate_count(5000));
+
+        sbf.save(path).unwrap();
+        assert_eq!(1, sbf.insert(5000));
+        assert_eq!(2, sbf.insert(5000));
+        sbf.save(path).unwrap();
+
+        let loaded = SpectralBloomFilter::load(path).unwrap();
+        assert_eq!(sbf.counters, loaded.counters);
+        assert_eq!(2, loaded.estimate_count(5000));
+        assert!(crate::BloomFilter::load(path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
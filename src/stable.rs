@@ -0,0 +1,199 @@
+/// Stable bloom filter, after Deng and Rafiei.
+///
+/// Each cell holds a small counter.  Inserting an item first decrements a few
+/// other cells, then sets the item's k cells to the maximum value, so old
+/// items gradually fade out and the proportion of zero cells settles at a
+/// stable point however long the stream.  The price is false negatives for
+/// items not seen recently.
+use crate::BloomHash;
+
+/// How cells are chosen for decrementing on each insert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecrementPolicy {
+    /// Decrement this many cells chosen independently at random.
+    Random(u32),
+    /// Decrement this many consecutive cells from a random starting point,
+    /// which is cheaper and almost as good.
+    Consecutive(u32),
+}
+
+impl DecrementPolicy {
+    fn cells(self) -> u32 {
+        match self {
+            DecrementPolicy::Random(p) | DecrementPolicy::Consecutive(p) => p,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StableBloomFilter {
+    k: u32,
+    max: u8,
+    policy: DecrementPolicy,
+    rng: u64,
+    cells: Vec<u8>,
+}
+
+impl StableBloomFilter {
+    pub fn new(cells: u32, k: u32, max: u8, policy: DecrementPolicy) -> Self {
+        assert!(cells > 0);
+        assert!(k > 0);
+        assert!(max > 0);
+
+        Self {
+            k,
+            max,
+            policy,
+            rng: 0x853c_49e6_748f_ea9b,
+            cells: vec![0; cells as usize],
+        }
+    }
+
+    /// A filter decrementing random cells at the rate which gives a stable
+    /// false-positive rate of `fp`.
+    pub fn with_fp_rate(cells: u32, k: u32, max: u8, fp: f64) -> Self {
+        let p = Self::optimal_decrements(cells, k, max, fp);
+        Self::new(cells, k, max, DecrementPolicy::Random(p))
+    }
+
+    /// The number of cells to decrement per insert for a stable false-positive
+    /// rate of `fp`, from equation 19 of the paper.
+    pub fn optimal_decrements(cells: u32, k: u32, max: u8, fp: f64) -> u32 {
+        let m = f64::from(cells);
+        let k = f64::from(k);
+        let max = f64::from(max);
+
+        let denom = ((1.0 - fp.powf(1.0 / k)).powf(-1.0 / max) - 1.0) * (1.0 / k - 1.0 / m);
+        ((1.0 / denom).round() as u32).max(1)
+    }
+
+    /// Reseed the generator choosing cells to decrement.
+    pub fn seed(&mut self, seed: u64) {
+        // xorshift state must not be zero
+        self.rng = seed | 1;
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn cell(&self, hash: BloomHash, i: u32) -> usize {
+        (hash.nth(i) % self.cells.len() as u64) as usize
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    /// Check for a pre-computed hash, which may be reused across filters.
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        (0..self.k).all(|i| self.cells[self.cell(*hash, i)] > 0)
+    }
+
+    /// Insert an item, returning `true` if it was not already present.
+    pub fn insert<T: Into<BloomHash>>(&mut self, item: T) -> bool {
+        self.insert_hash(&item.into())
+    }
+
+    /// Insert a pre-computed hash, which may be reused across filters.
+    pub fn insert_hash(&mut self, hash: &BloomHash) -> bool {
+        let hash = *hash;
+        let seen = self.contains_hash(&hash);
+
+        self.decrement();
+
+        for i in 0..self.k {
+            let cell = self.cell(hash, i);
+            self.cells[cell] = self.max;
+        }
+
+        !seen
+    }
+
+    fn decrement(&mut self) {
+        let len = self.cells.len();
+
+        match self.policy {
+            DecrementPolicy::Random(p) => {
+                for _ in 0..p {
+                    let cell = (self.next_random() % len as u64) as usize;
+                    self.cells[cell] = self.cells[cell].saturating_sub(1);
+                }
+            }
+            DecrementPolicy::Consecutive(p) => {
+                let start = (self.next_random() % len as u64) as usize;
+                for i in 0..p as usize {
+                    let cell = (start + i) % len;
+                    self.cells[cell] = self.cells[cell].saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    pub fn policy(&self) -> DecrementPolicy {
+        self.policy
+    }
+
+    pub fn decrements(&self) -> u32 {
+        self.policy.cells()
+    }
+
+    /// Fraction of cells which are non-zero.
+    pub fn fill_ratio(&self) -> f64 {
+        self.cells.iter().filter(|c| **c > 0).count() as f64 / self.cells.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_fill_stabilises() {
+        for policy in &[
+            DecrementPolicy::Random(10),
+            DecrementPolicy::Consecutive(10),
+        ] {
+            let mut sbf = StableBloomFilter::new(10_000, 3, 3, *policy);
+
+            for i in 0..20_000 {
+                sbf.insert(i);
+            }
+            let fill = sbf.fill_ratio();
+
+            for i in 20_000..60_000 {
+                sbf.insert(i);
+            }
+
+            assert!(fill < 0.9);
+            assert!((sbf.fill_ratio() - fill).abs() < 0.05);
+
+            // recent items survive, ancient ones mostly don't
+            assert!((59_900..60_000).all(|i| sbf.contains(i)));
+            assert!((0..1000).filter(|i| sbf.contains(*i)).count() < 900);
+
+            let hash = BloomHash::from("hashed");
+            sbf.insert_hash(&hash);
+            assert!(sbf.contains_hash(&hash));
+            assert!(sbf.contains("hashed"));
+        }
+    }
+
+    #[test]
+    fn stable_optimal_decrements() {
+        let p = StableBloomFilter::optimal_decrements(100_000, 3, 3, 0.01);
+        assert!(p > 0);
+
+        let mut sbf = StableBloomFilter::with_fp_rate(100_000, 3, 3, 0.01);
+        for i in 0..500_000 {
+            sbf.insert(i);
+        }
+
+        let fp = (1_000_000..1_010_000).filter(|i| sbf.contains(*i)).count();
+        assert!(fp < 200);
+    }
+}
@@ -0,0 +1,101 @@
+/// Reading a saved filter's vital statistics from its header alone, for
+/// tooling which shouldn't have to load a multi-gigabyte body to show them.
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::format::{FileHeader, Provenance};
+use crate::{estimate_count, header_params, BloomFilter};
+
+/// What `BloomFilter::stat` found in a file's header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterInfo {
+    /// Format version of the file.
+    pub version: u8,
+    /// Capacity.
+    pub n: u64,
+    /// Size in bits.
+    pub m: u64,
+    /// Probes per item.
+    pub k: u32,
+    /// False-positive probability at capacity.
+    pub p: f64,
+    /// Number of pages in the body.
+    pub pages: u64,
+    pub page_size: u32,
+    /// Generation of the save which wrote the file.
+    pub generation: u64,
+    /// Number of items, exactly if the file records it, or else estimated if
+    /// it records how many bits are set, as files written since it began
+    /// doing so do.
+    pub count_estimate: Option<u64>,
+    /// Where and when the file was saved, if its saver recorded it.
+    pub provenance: Option<Provenance>,
+}
+
+impl BloomFilter {
+    /// Describe the filter saved at `path`, reading only its header.
+    pub fn stat<P: AsRef<Path>>(path: P) -> io::Result<FilterInfo> {
+        let header = FileHeader::read(File::open(path.as_ref())?)?;
+        let params = header_params(&header)?;
+
+        Ok(FilterInfo {
+            version: header.version,
+            n: params.n,
+            m: params.m,
+            k: params.k,
+            p: params.p,
+            pages: header.pages(),
+            page_size: header.page_size(),
+            generation: header.generation(),
+            count_estimate: header
+                .count()
+                .or_else(|| header.ones().map(|ones| estimate_count(&params, ones))),
+            provenance: header.provenance(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn bloomfilter_stat() {
+        let path = "test_stat.bf";
+        let _ = std::fs::remove_file(path);
+
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        for i in 0..10_000u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+
+        let info = BloomFilter::stat(path).unwrap();
+        assert_eq!(format::MAX_VERSION, info.version);
+        assert_eq!(bf.params.m, info.m);
+        assert_eq!(bf.params.k, info.k);
+        assert_eq!(bf.pages, info.pages);
+        assert_eq!(1, info.generation);
+        assert_eq!(Some(bf.count), info.count_estimate);
+
+        // an in place save keeps the count up to date
+        for i in 10_000..20_000u32 {
+            bf.insert(i);
+        }
+        bf.save(path).unwrap();
+        let info = BloomFilter::stat(path).unwrap();
+        assert_eq!(2, info.generation);
+        assert_eq!(Some(bf.count), info.count_estimate);
+        assert_eq!(None, info.provenance);
+
+        bf.set_provenance_label(Some("nightly build".to_string()));
+        bf.save(path).unwrap();
+        let provenance = BloomFilter::stat(path).unwrap().provenance.unwrap();
+        assert_eq!("nightly build", provenance.label);
+        assert_eq!(env!("CARGO_PKG_VERSION"), provenance.crate_version);
+        assert!(provenance.saved_at > 1_600_000_000);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+/// Saving and loading through a `FilterStorage`, for keeping filters
+/// somewhere other than local files, such as an object store.
+///
+/// Storage deals only in the header and whole pages, addressed by index, so
+/// an implementation can keep each as a separate object.  As with `save`,
+/// saving to storage which already holds the same filter writes only the
+/// pages dirtied since, then the header.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use bitvec_rs::BitVec;
+
+use crate::format::{self, FileHeader};
+use crate::{check_hash_key, header_params, paged, BloomFilter, Durability};
+
+/// Somewhere a filter's header and pages can be kept.
+pub trait FilterStorage {
+    /// Fill `buf` with the header, failing if there isn't one.
+    fn read_header(&mut self, buf: &mut [u8]) -> io::Result<()>;
+
+    fn write_header(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Fill `buf` with page `index`, whose size is the length of `buf`.
+    fn read_page(&mut self, index: usize, buf: &mut [u8]) -> io::Result<()>;
+
+    fn write_page(&mut self, index: usize, buf: &[u8]) -> io::Result<()>;
+
+    /// Make everything written so far durable.
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// Storage in a single file, laid out as `save` writes it.
+#[derive(Debug)]
+pub struct FileStorage {
+    file: File,
+}
+
+impl FileStorage {
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    /// Open an existing file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+        Ok(Self::new(file))
+    }
+
+    /// Open a file, creating it if necessary.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+        Ok(Self::new(file))
+    }
+
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)
+    }
+
+    fn write_all_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.write_all(buf)
+    }
+}
+
+impl FilterStorage for FileStorage {
+    fn read_header(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.read_exact_at(0, buf)
+    }
+
+    fn write_header(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write_all_at(0, buf)
+    }
+
+    fn read_page(&mut self, index: usize, buf: &mut [u8]) -> io::Result<()> {
+        self.read_exact_at((format::HEADER_SIZE + index * buf.len()) as u64, buf)
+    }
+
+    fn write_page(&mut self, index: usize, buf: &[u8]) -> io::Result<()> {
+        self.write_all_at((format::HEADER_SIZE + index * buf.len()) as u64, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+impl BloomFilter {
+    /// Save the filter to `storage`.
+    pub fn save_to_storage<S: FilterStorage + ?Sized>(
+        &mut self,
+        storage: &mut S,
+    ) -> io::Result<()> {
+        self.generation += 1;
+        let mut header = self.file_header();
+
+        let mut buf = vec![0; format::HEADER_SIZE];
+        let existing = storage
+            .read_header(&mut buf)
+            .and_then(|()| FileHeader::from_bytes(&buf))
+            .ok()
+            .filter(|existing| existing.without_checksums() == header.without_checksums());
+
+        let bytes = self.filter.as_bytes();
+        match existing {
+            Some(existing) => {
+                header = existing;
+                self.stamp(&mut header);
+                let pages = paged::dirty_pages(&self.dirty);
+                let writes = paged::in_place(&mut header, bytes, &pages);
+                for (index, (_, page)) in pages.iter().zip(writes) {
+                    storage.write_page(*index, page)?;
+                }
+            }
+            None => {
+                let page_size = self.params.page_size as usize;
+                for (index, page) in bytes.chunks(page_size).enumerate() {
+                    storage.write_page(index, page)?;
+                }
+                header.set_checksums(bytes);
+            }
+        }
+
+        // the header goes last, so it never describes pages not yet written
+        buf.clear();
+        header.write(&mut buf)?;
+        storage.write_header(&buf)?;
+        if self.durability != Durability::Unsynced {
+            storage.sync()?;
+        }
+        self.clear_dirty();
+
+        Ok(())
+    }
+
+    /// Load a filter saved to `storage`.
+    pub fn load_from_storage<S: FilterStorage + ?Sized>(storage: &mut S) -> io::Result<Self> {
+        let mut buf = vec![0; format::HEADER_SIZE];
+        storage.read_header(&mut buf)?;
+        let header = FileHeader::from_bytes(&buf)?;
+        if header.is_sparse()
+            || header.is_compressed()
+            || header.is_segmented()
+            || header.is_encrypted()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "only dense filters can be loaded from storage",
+            ));
+        }
+
+        let params = header_params(&header)?;
+        check_hash_key(&params, (0, 0))?;
+
+        let mut filter = BitVec::from_elem((params.m / 8 * 8) as usize, false);
+        let page_size = header.page_size() as usize;
+        filter.with_bytes_mut(|buf| {
+            for (index, page) in buf.chunks_mut(page_size).enumerate() {
+                storage.read_page(index, page)?;
+            }
+            Ok::<_, io::Error>(())
+        })?;
+        header.verify_body(filter.as_bytes())?;
+
+        Ok(Self::from_loaded(params, filter, &header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Storage keeping each page as a separate object, as a bucket might.
+    #[derive(Default)]
+    struct Objects {
+        header: Option<Vec<u8>>,
+        pages: HashMap<usize, Vec<u8>>,
+        writes: usize,
+    }
+
+    impl FilterStorage for Objects {
+        fn read_header(&mut self, buf: &mut [u8]) -> io::Result<()> {
+            let header = self
+                .header
+                .as_ref()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            buf.copy_from_slice(header);
+            Ok(())
+        }
+
+        fn write_header(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.header = Some(buf.to_vec());
+            Ok(())
+        }
+
+        fn read_page(&mut self, index: usize, buf: &mut [u8]) -> io::Result<()> {
+            let page = self
+                .pages
+                .get(&index)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            buf.copy_from_slice(page);
+            Ok(())
+        }
+
+        fn write_page(&mut self, index: usize, buf: &[u8]) -> io::Result<()> {
+            self.pages.insert(index, buf.to_vec());
+            self.writes += 1;
+            Ok(())
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bloomfilter_storage() {
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+
+        let mut objects = Objects::default();
+        bf.save_to_storage(&mut objects).unwrap();
+        assert_eq!(bf.pages as usize, objects.writes);
+
+        // only the dirty page is written again
+        bf.insert(5000u32);
+        bf.save_to_storage(&mut objects).unwrap();
+        assert_eq!(bf.pages as usize + 1, objects.writes);
+
+        let loaded = BloomFilter::load_from_storage(&mut objects).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+        assert!(loaded.contains(5000u32));
+        assert_eq!(2, loaded.generation());
+
+        // file storage is laid out as save writes it
+        let path = "test_storage.bf";
+        let _ = std::fs::remove_file(path);
+        bf.save_to_storage(&mut FileStorage::create(path).unwrap())
+            .unwrap();
+        BloomFilter::verify(path).unwrap();
+        let loaded = BloomFilter::load(path).unwrap();
+        assert!(loaded.contains(5000u32));
+
+        let mut storage = FileStorage::open(path).unwrap();
+        assert!(BloomFilter::load_from_storage(&mut storage)
+            .unwrap()
+            .contains(5000u32));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
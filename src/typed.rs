@@ -0,0 +1,128 @@
+/// A `BloomFilter` for items of a single type.
+///
+/// `BloomFilter` takes anything `Into<BloomHash>`, so a filter of URLs will
+/// happily answer for a user ID, almost always "no", and nothing catches the
+/// mistake.  `TypedBloomFilter<Url>` only takes `&Url`, which the compiler
+/// checks.  Items are hashed with the filter's `hash`, as `insert` does, so
+/// a typed filter and a plain one given the same items agree, and files
+/// saved by either load into the other.
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use crate::{BloomFilter, BloomFilterParams};
+
+pub struct TypedBloomFilter<T: ?Sized> {
+    filter: BloomFilter,
+    // fn(&T) rather than T, so the wrapper is Send and Sync whatever T is
+    item: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> fmt::Debug for TypedBloomFilter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedBloomFilter")
+            .field("filter", &self.filter)
+            .finish()
+    }
+}
+
+impl<T: Hash + ?Sized> TypedBloomFilter<T> {
+    /// Wrap an existing filter, taking on trust that it holds `T`s.
+    pub fn new(filter: BloomFilter) -> Self {
+        Self {
+            filter,
+            item: PhantomData,
+        }
+    }
+
+    pub fn from_params(params: BloomFilterParams) -> Self {
+        Self::new(BloomFilter::from_params(params))
+    }
+
+    pub fn with_capacity_p(capacity: u64, p: f64) -> Self {
+        Self::new(BloomFilter::with_capacity_p(capacity, p))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        BloomFilter::load(path).map(Self::new)
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.filter.save(path)
+    }
+
+    /// Insert an item, returning `true` if it was not already present.
+    pub fn insert(&mut self, item: &T) -> bool {
+        let hash = self.filter.hash(item);
+        self.filter.insert_hash(&hash)
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.filter.contains_hash(&self.filter.hash(item))
+    }
+
+    /// The underlying filter, for its statistics and settings.
+    pub fn filter(&self) -> &BloomFilter {
+        &self.filter
+    }
+
+    /// Unwrap the filter, giving up the check on what's inserted into it.
+    pub fn into_inner(self) -> BloomFilter {
+        self.filter
+    }
+}
+
+impl<'a, T: Hash + ?Sized + 'a> Extend<&'a T> for TypedBloomFilter<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        for item in iter {
+            self.insert(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Hash)]
+    struct Url(String);
+
+    #[test]
+    fn typed_bloomfilter() {
+        let path = "test_typed.bf";
+        let _ = std::fs::remove_file(path);
+
+        let urls: Vec<Url> = (0..1000)
+            .map(|i| Url(format!("https://example.com/{}", i)))
+            .collect();
+
+        let mut bf = TypedBloomFilter::with_capacity_p(1000, 0.01);
+        bf.extend(&urls);
+        assert!(urls.iter().all(|url| bf.contains(url)));
+        assert!(!bf.insert(&urls[0]));
+        assert!(bf.insert(&Url("https://example.org/".to_string())));
+
+        // unsized items work too
+        let mut names: TypedBloomFilter<str> = TypedBloomFilter::with_capacity_p(100, 0.01);
+        names.insert("meep");
+        assert!(names.contains("meep"));
+
+        // the same items extended into a plain filter set the same bits
+        let mut plain = BloomFilter::with_capacity_p(1000, 0.01);
+        plain.extend(&urls);
+        plain.insert_hash(&plain.hash(Url("https://example.org/".to_string())));
+        assert_eq!(1.0, bf.filter().estimate_jaccard(&plain));
+
+        bf.save(path).unwrap();
+        let loaded: TypedBloomFilter<Url> = TypedBloomFilter::load(path).unwrap();
+        assert!(urls.iter().all(|url| loaded.contains(url)));
+        assert_eq!(
+            bf.filter().count_estimate(),
+            loaded.into_inner().count_estimate()
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+/// Read-only filters over a saved filter held in memory, queried in place
+/// without copying its body, for filters received over the network or
+/// embedded with `include_bytes!`.
+///
+/// A `BloomFilterView` borrows or owns its buffer through `AsRef<[u8]>`, so
+/// it works as well over a `&'static [u8]` as a `Vec<u8>` or `bytes::Bytes`.
+/// As with mapping, only dense files of plain filters can be viewed, as
+/// sparse ones have no fixed place for each page.
+use std::io;
+
+use crate::format::{self, FileHeader, LoadError};
+use crate::paged::invalid_data;
+use crate::{
+    check_plain_hashing, estimate_count, header_params, lsb_first, probe_bits, BloomFilter,
+    BloomFilterParams, BloomHash,
+};
+
+/// The byte and mask of a bit within a filter body.
+pub(crate) fn bit_position(bit: u64, lsb_first: bool) -> (usize, u8) {
+    let shift = (bit % 8) as u8;
+    let mask = if lsb_first { 1 << shift } else { 0x80 >> shift };
+    (format::HEADER_SIZE + (bit / 8) as usize, mask)
+}
+
+/// The params and count of a dense filter file held whole in memory.
+pub(crate) fn check_dense(buf: &[u8]) -> io::Result<(BloomFilterParams, u64)> {
+    if buf.len() < format::HEADER_SIZE {
+        return Err(LoadError::TruncatedFile.into());
+    }
+
+    let header = FileHeader::from_bytes(&buf[..format::HEADER_SIZE])?;
+    if header.is_sparse() {
+        return Err(invalid_data("sparse filters can't be used in place"));
+    }
+    if header.is_compressed() {
+        return Err(invalid_data("compressed filters can't be used in place"));
+    }
+    if header.is_segmented() {
+        return Err(invalid_data("segmented filters can't be used in place"));
+    }
+    if header.is_encrypted() {
+        return Err(invalid_data("encrypted filters can't be used in place"));
+    }
+
+    let params = header_params(&header)?;
+    check_plain_hashing(&params)?;
+    if (buf.len() as u64) < header.file_len() {
+        return Err(LoadError::TruncatedFile.into());
+    }
+
+    let body = &buf[format::HEADER_SIZE..header.file_len() as usize];
+    header.verify_body(body)?;
+
+    let count = match header.count() {
+        Some(count) => count,
+        None => estimate_count(
+            &params,
+            body.iter().map(|b| u64::from(b.count_ones())).sum(),
+        ),
+    };
+    Ok((params, count))
+}
+
+#[derive(Debug, Clone)]
+pub struct BloomFilterView<B> {
+    params: BloomFilterParams,
+    pages: u64,
+    count: u64,
+    lsb_first: bool,
+    buf: B,
+}
+
+impl BloomFilter {
+    /// Read a filter from a buffer holding it as `write_to` or `save` would,
+    /// straight into its own storage.  For queries alone, `view` avoids
+    /// even that copy.
+    pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
+        Self::from_reader(buf)
+    }
+
+    /// Query the filter saved in `buf` in place.
+    pub fn view<B: AsRef<[u8]>>(buf: B) -> io::Result<BloomFilterView<B>> {
+        BloomFilterView::new(buf)
+    }
+}
+
+impl<B: AsRef<[u8]>> BloomFilterView<B> {
+    pub fn new(buf: B) -> io::Result<Self> {
+        let (params, count) = check_dense(buf.as_ref())?;
+
+        Ok(Self {
+            pages: params.m / (u64::from(params.page_size) * 8),
+            params,
+            count,
+            lsb_first: lsb_first(),
+            buf,
+        })
+    }
+
+    pub fn params(&self) -> &BloomFilterParams {
+        &self.params
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        let buf = self.buf.as_ref();
+        probe_bits(&self.params, self.pages, *hash, 0).all(|bit| {
+            let (byte, mask) = bit_position(bit, self.lsb_first);
+            buf[byte] & mask != 0
+        })
+    }
+
+    pub fn count_estimate(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloomfilter_view() {
+        let mut bf = BloomFilter::with_capacity_p(100_000, 0.01);
+        for i in 0..1000u32 {
+            bf.insert(i);
+        }
+        let mut buf = vec![];
+        bf.write_to(&mut buf).unwrap();
+
+        let loaded = BloomFilter::from_bytes(&buf).unwrap();
+        assert!((0..1000u32).all(|i| loaded.contains(i)));
+
+        let view = BloomFilter::view(&buf[..]).unwrap();
+        assert!((0..1000u32).all(|i| view.contains(i)));
+        assert!((0..3000u32).all(|i| view.contains(i) == loaded.contains(i)));
+        assert_eq!(bf.count, view.count_estimate());
+
+        // owned buffers work too
+        let view = BloomFilter::view(buf.clone()).unwrap();
+        assert!(view.contains(999u32));
+
+        buf[format::HEADER_SIZE + 100] ^= 0xff;
+        assert!(BloomFilter::view(&buf[..]).is_err());
+        assert!(BloomFilter::view(&buf[..format::HEADER_SIZE]).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+/// Static xor filter, after Graf and Lemire.
+///
+/// Built once from a complete set of items, then queried immutably.  Each
+/// item maps to three slots of 8-bit fingerprints whose xor equals its own
+/// fingerprint, giving a false-positive rate of about 0.4% in roughly 9.84
+/// bits per item, some 20% less than a bloom filter of similar accuracy.
+///
+/// Saved files are a short header followed by the fingerprint array:
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic, "XORF8v00"
+/// 8       8     seed (big-endian u64)
+/// 16      4     block length (big-endian u32)
+/// 20      4     number of items (big-endian u32)
+/// 24      ...   3 * block length fingerprints
+/// ```
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::paged::invalid_data;
+use crate::BloomHash;
+
+const XOR_MAGIC: &[u8; 8] = b"XORF8v00";
+const XOR_HEADER_SIZE: usize = 24;
+const MAX_ATTEMPTS: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorFilter {
+    seed: u64,
+    block_length: u32,
+    len: u32,
+    fingerprints: Vec<u8>,
+}
+
+pub(crate) fn mix(key: u64, seed: u64) -> u64 {
+    // murmur3 finaliser
+    let mut h = key.wrapping_add(seed);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+fn reduce(hash: u32, n: u32) -> u32 {
+    ((u64::from(hash) * u64::from(n)) >> 32) as u32
+}
+
+fn fingerprint(hash: u64) -> u8 {
+    (hash ^ (hash >> 32)) as u8
+}
+
+impl XorFilter {
+    fn slots(&self, hash: u64) -> [usize; 3] {
+        slots(hash, self.block_length)
+    }
+
+    /// Build a filter from a complete set of items.  Duplicates are ignored.
+    ///
+    /// Returns `None` if no working seed could be found, which is vanishingly
+    /// unlikely.
+    pub fn build<I, T>(items: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<BloomHash>,
+    {
+        let mut keys: Vec<u64> = items.into_iter().map(|item| item.into().h1).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let len = keys.len();
+        let block_length = block_length(len);
+        let (seed, order) = peel(&keys, block_length)?;
+        let mut fingerprints = vec![0u8; block_length as usize * 3];
+
+        for (key, i) in order.iter().rev() {
+            let hash = mix(keys[*key], seed);
+            let [a, b, c] = slots(hash, block_length);
+            fingerprints[*i] = 0;
+            fingerprints[*i] =
+                fingerprint(hash) ^ fingerprints[a] ^ fingerprints[b] ^ fingerprints[c];
+        }
+
+        Some(Self {
+            seed,
+            block_length,
+            len: len as u32,
+            fingerprints,
+        })
+    }
+
+    pub fn contains<T: Into<BloomHash>>(&self, item: T) -> bool {
+        self.contains_hash(&item.into())
+    }
+
+    pub fn contains_hash(&self, hash: &BloomHash) -> bool {
+        let hash = mix(hash.h1, self.seed);
+        let [a, b, c] = self.slots(hash);
+
+        fingerprint(hash) == self.fingerprints[a] ^ self.fingerprints[b] ^ self.fingerprints[c]
+    }
+
+    /// Number of distinct items the filter was built from.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bits_per_item(&self) -> f64 {
+        (self.fingerprints.len() * 8) as f64 / f64::from(self.len.max(1))
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; XOR_HEADER_SIZE];
+        reader.read_exact(&mut header[..])?;
+
+        if &header[0..8] != XOR_MAGIC {
+            return Err(invalid_data("bad magic"));
+        }
+
+        let seed = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        let block_length = u32::from_be_bytes(header[16..20].try_into().unwrap());
+        let len = u32::from_be_bytes(header[20..24].try_into().unwrap());
+
+        if block_length == 0 {
+            return Err(invalid_data("invalid block length"));
+        }
+
+        let mut fingerprints = vec![0; block_length as usize * 3];
+        reader.read_exact(&mut fingerprints[..])?;
+
+        Ok(Self {
+            seed,
+            block_length,
+            len,
+            fingerprints,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = [0; XOR_HEADER_SIZE];
+        header[0..8].copy_from_slice(XOR_MAGIC);
+        header[8..16].copy_from_slice(&self.seed.to_be_bytes());
+        header[16..20].copy_from_slice(&self.block_length.to_be_bytes());
+        header[20..24].copy_from_slice(&self.len.to_be_bytes());
+
+        writer.write_all(&header[..])?;
+        writer.write_all(&self.fingerprints[..])
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        self.write_to(&mut file)?;
+        file.sync_all()
+    }
+}
+
+pub(crate) fn slots(hash: u64, block_length: u32) -> [usize; 3] {
+    let h0 = reduce(hash as u32, block_length);
+    let h1 = reduce(hash.rotate_left(21) as u32, block_length) + block_length;
+    let h2 = reduce(hash.rotate_left(42) as u32, block_length) + 2 * block_length;
+
+    [h0 as usize, h1 as usize, h2 as usize]
+}
+
+/// Block length giving 3-wise peeling a good chance of success for `len`
+/// keys.
+pub(crate) fn block_length(len: usize) -> u32 {
+    let capacity = 32 + (1.23 * len as f64).ceil() as usize;
+    (capacity / 3) as u32
+}
+
+/// Find a seed under which every key can be peeled from the 3-wise hypergraph
+/// over `3 * block_length` slots, returning it along with the peeling order as
+/// pairs of key index and the slot that key alone maps to.  Slots must be
+/// assigned in reverse of this order.
+///
+/// Keys must be distinct.
+pub(crate) fn peel(keys: &[u64], block_length: u32) -> Option<(u64, Vec<(usize, usize)>)> {
+    let len = keys.len();
+    let size = block_length as usize * 3;
+
+    let mut xormask = vec![0usize; size];
+    let mut count = vec![0u32; size];
+    let mut queue = Vec::with_capacity(size);
+    let mut stack = Vec::with_capacity(len);
+
+    for seed in 0..MAX_ATTEMPTS {
+        let seed = mix(seed, 0x9e37_79b9_7f4a_7c15);
+
+        xormask.iter_mut().for_each(|x| *x = 0);
+        count.iter_mut().for_each(|c| *c = 0);
+        queue.clear();
+        stack.clear();
+
+        for (index, key) in keys.iter().enumerate() {
+            for slot in &slots(mix(*key, seed), block_length) {
+                xormask[*slot] ^= index;
+                count[*slot] += 1;
+            }
+        }
+
+        queue.extend((0..size).filter(|i| count[*i] == 1));
+
+        // peel slots used by exactly one key, recording the order
+        while let Some(i) = queue.pop() {
+            if count[i] != 1 {
+                continue;
+            }
+
+            let index = xormask[i];
+            stack.push((index, i));
+
+            for slot in &slots(mix(keys[index], seed), block_length) {
+                xormask[*slot] ^= index;
+                count[*slot] -= 1;
+                if count[*slot] == 1 {
+                    queue.push(*slot);
+                }
+            }
+        }
+
+        if stack.len() == len {
+            return Some((seed, stack));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xor_build_query_save_load() {
+        let path = "test_xor.bf";
+        let _ = std::fs::remove_file(path);
+
+        let xf = XorFilter::build((0..100_000).chain(0..1000)).unwrap();
+        assert_eq!(100_000, xf.len());
+        assert!(xf.bits_per_item() < 10.0);
+        assert!((0..100_000).all(|i| xf.contains(i)));
+
+        let fp = (100_000..200_000).filter(|i| xf.contains(*i)).count();
+        assert!(fp < 600);
+
+        xf.save(path).unwrap();
+        let loaded = XorFilter::load(path).unwrap();
+        assert_eq!(xf, loaded);
+
+        let empty = XorFilter::build(Vec::<u32>::new()).unwrap();
+        assert!(empty.is_empty());
+        assert!(!empty.contains(1));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
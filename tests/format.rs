@@ -0,0 +1,25 @@
+use std::fs::File;
+
+use blooming_rust::format::{self, FileHeader};
+use blooming_rust::*;
+
+#[test]
+fn parse_saved_header() {
+    let path = "test_format.bf";
+    let _ = std::fs::remove_file(path);
+
+    let mut bf = BloomFilter::with_capacity_p(1024, 0.01);
+    bf.insert("meep");
+    bf.save(path).unwrap();
+
+    let header = FileHeader::read(File::open(path).unwrap()).unwrap();
+    // the generation is an extension, needing the later version
+    assert_eq!(format::MAX_VERSION, header.version);
+    assert_eq!(1, header.generation());
+    assert_eq!(0, header.m % (u64::from(format::PAGE_SIZE) * 8));
+    assert!(header.n >= 1024);
+    assert!(header.k > 0);
+    assert_eq!(header.file_len(), std::fs::metadata(path).unwrap().len());
+
+    std::fs::remove_file(path).unwrap();
+}